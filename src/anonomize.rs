@@ -1,7 +1,7 @@
 use std::{fs::rename, io::Result};
 
 use crate::{
-    config::{compile_errors_file, student_diff_file, student_result_file}, darwin_config, list_students::list_students, project_runner::Project, util::file_replace_line
+    config::{student_blobs_file, student_diff_file}, darwin_config::{read_config, write_config}, list_students::list_students, project_runner::Project, result_store::update_result_store
 };
 
 pub fn anonomize(project: &Project) {
@@ -10,32 +10,33 @@ pub fn anonomize(project: &Project) {
 }
 
 fn _anonomize(_project: &Project) {
-    let tests = darwin_config::list_tests();
-
     for (i, student) in list_students().iter().enumerate() {
-        if anonomize_student(student, i, &tests).is_err() {
+        if anonomize_student(student, i).is_err() {
             eprintln!("Failed to anonomize {}", student);
         }
     }
 }
 
-fn anonomize_student(student: &str, i: usize, tests: &Vec<String>) -> Result<()> {
-    for test in tests {
-        if student_result_file(student, test).is_file() {
-            rename(
-                student_result_file(student, test),
-                student_result_file(&i.to_string(), test),
-            )?;
-        }
-    }
+fn anonomize_student(student: &str, i: usize) -> Result<()> {
+    update_result_store(|store| store.rename_student(student, &i.to_string()))?;
     rename(
         student_diff_file(student),
         student_diff_file(&i.to_string()),
     )?;
+    if student_blobs_file(student).is_file() {
+        rename(
+            student_blobs_file(student),
+            student_blobs_file(&i.to_string()),
+        )?;
+    }
 
-    let mut new_name = i.to_string();
-    new_name.push('\n');
-    file_replace_line(&compile_errors_file(), student, &new_name)?;
+    let mut config = read_config()?;
+    if let Some(root) = config.project_roots.remove(student) {
+        config.project_roots.insert(i.to_string(), root);
+    }
+    // Identifies the student
+    config.student_ids.remove(student);
+    write_config(config)?;
 
     Ok(())
 }
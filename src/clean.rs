@@ -1,23 +1,24 @@
 use std::{
-    fs::{create_dir, remove_dir_all, OpenOptions},
+    fs::{create_dir, remove_dir_all},
     io,
 };
 
-use crate::{config::{compile_errors_file, projects_dir, results_dir}, darwin_config::{read_config, write_config}};
+use crate::{config::{projects_dir, student_project_file}, darwin_config::{read_config, write_config}, result_store::{clear_result_store, remove_student_results}};
 
 pub fn clean() -> io::Result<()> {
     remove_dir_all(projects_dir())?;
     create_dir(projects_dir())?;
-    remove_dir_all(results_dir())?;
-    create_dir(results_dir())?;
-    OpenOptions::new()
-        .create(true)
-        .read(true)
-        .write(true)
-        .truncate(true)
-        .open(compile_errors_file())?;
+    clear_result_store()?;
     let mut config = read_config()?;
     config.tests_run.clear();
     write_config(config)?;
     Ok(())
 }
+
+/// Removes a single student's results, compile errors and timeouts, so their tests run again
+pub fn clean_student(student: &str) -> io::Result<()> {
+    if student_project_file(student).is_dir() {
+        remove_dir_all(student_project_file(student))?;
+    }
+    remove_student_results(student)
+}
@@ -1,28 +1,58 @@
 use std::{
     collections::HashSet,
-    fs::{remove_dir_all, remove_file, OpenOptions},
+    fs::{remove_dir_all, remove_file, File, OpenOptions},
     io::{stdin, stdout, Write},
     path::Path,
 };
 
+use clap::ValueEnum;
+use serde::Serialize;
 use strum::IntoEnumIterator;
 
 use crate::{
-    anonomize, clean, config::darwin_root, create_darwin, create_report, darwin_config::{self, ProjectType}, download_results, list_students::{self}, plagiarism_checker, project_runner::Project, run_tests::{self}, types::TestResultError, util::{is_test, prompt_digit, prompt_yn}, view_student_results, view_student_submission
+    anonomize, clean, config::{darwin_root, dependency_cache_dir}, create_darwin, create_report, darwin_config::{self, read_config, write_config, ProjectType, SandboxLevel, SubmissionSource}, download_results, grade, list_students::{self}, moodle_export, output::{print_error, print_json, print_table, OutputFormat}, plagiarism_checker::{self, PlagiarismAlgorithm, PlagiarismCheckOptions}, progress::{print_summary, ProgressReporter}, project_runner::{sandbox, Project}, run_tests::{self}, serve, types::{ClassNameSummary, ResultSummary, StatusMsg, TestResultError, TestState}, util::{is_test, prompt_digit, prompt_yn}, view_student_results::{self, VerboseResult}, view_student_submission
 };
 
-pub fn list_project_types() {
-    for t in ProjectType::iter() {
-        if !matches!(t, ProjectType::None) {
-            println!("{:?}", t);
+pub fn list_project_types(format: OutputFormat) {
+    let project_types: Vec<ProjectType> = ProjectType::iter().filter(|t| !matches!(t, ProjectType::None)).collect();
+    match format {
+        OutputFormat::Text => {
+            for t in project_types {
+                println!("{:?}", t);
+            }
+        }
+        // The names create-project takes
+        _ => {
+            let names: Vec<String> = project_types
+                .iter()
+                .filter_map(|t| t.to_possible_value().map(|value| value.get_name().to_string()))
+                .collect();
+            print_list("Project Type", &names, format);
         }
     }
 }
 
+/// One per line, or a one column table, or a json array of strings
+fn print_list(header: &str, items: &[String], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for item in items {
+                println!("{}", item);
+            }
+        }
+        OutputFormat::Table => {
+            let rows: Vec<Vec<String>> = items.iter().map(|item| vec![item.clone()]).collect();
+            print_table(&[header], &rows);
+        }
+        OutputFormat::Json => print_json(items),
+    }
+}
+
 pub fn create_darwin(
     project: &Project,
     project_skeleton: &Path,
-    moodle_submissions_zipfile: &Path,
+    submission_source: SubmissionSource,
+    submissions_path: &Path,
     copy_ignore_set: &HashSet<&str>,
 ) -> bool {
     if darwin_root().exists() {
@@ -39,7 +69,8 @@ pub fn create_darwin(
     if let Err(e) = create_darwin::create_darwin(
         project,
         project_skeleton,
-        moodle_submissions_zipfile,
+        submission_source,
+        submissions_path,
         copy_ignore_set,
     ) {
         eprintln!("Error while creating darwin project: {}", e);
@@ -48,6 +79,40 @@ pub fn create_darwin(
     true
 }
 
+pub fn add_submissions(project: &Project, submission_source: Option<SubmissionSource>, submissions_path: &Path, copy_ignore_set: &HashSet<&str>) {
+    let submission_source = match submission_source {
+        Some(source) => source,
+        None => match read_config() {
+            Ok(config) => config.submission_source,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+    };
+    match create_darwin::add_submissions(project, submission_source, submissions_path, copy_ignore_set) {
+        Ok(students) => {
+            for student in students {
+                println!("Updated {}", student);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error while adding submissions: {}", e);
+        }
+    }
+}
+
+pub fn add_student_submission(project: &Project, student: &str, submission_path: &Path, copy_ignore_set: &HashSet<&str>) {
+    match create_darwin::add_student_submission(project, student, submission_path, copy_ignore_set) {
+        Ok(()) => {
+            println!("Updated {}", student);
+        }
+        Err(e) => {
+            eprintln!("Error while adding {}'s submission: {}", student, e);
+        }
+    }
+}
+
 pub fn auto(
     project: &Project,
     project_skeleton: &Path,
@@ -58,13 +123,14 @@ pub fn auto(
     if !create_darwin(
         project,
         project_skeleton,
+        SubmissionSource::Moodle,
         moodle_submissions_zipfile,
         copy_ignore_set,
     ) {
         return;
     }
 
-    let tests: Vec<String> = darwin_config::list_tests().iter().cloned().collect();
+    let tests: Vec<String> = darwin_config::list_tests().to_vec();
     let selected_tests = auto_select_tests(&tests).unwrap();
     if selected_tests.is_empty() {
         eprintln!("No tests selected. Exiting...");
@@ -81,10 +147,8 @@ pub fn auto(
 
     let num_threads = num_threads.clamp(1, 8);
 
-    for selected_test in selected_tests.iter() {
-        println!("Running test: {}", selected_test);
-        run_tests(project, selected_test, num_threads);
-    }
+    println!("Running tests: {}", selected_tests.join(", "));
+    run_tests(project, &selected_tests.join(","), num_threads);
 
     let num_sections = match prompt_digit::<usize>("How many TA's will be grading? This will determine how many sections the report will be split into.") {
         Ok(n) => {
@@ -106,7 +170,9 @@ pub fn auto(
     };
 
     create_report(project, Path::new("report"), num_sections as u8, &selected_tests);
-    plagiarism_check(Path::new("plagiarism.html"));
+    let report = Path::new("report");
+    let options = PlagiarismCheckOptions { report: report.is_dir().then_some(report), ..Default::default() };
+    plagiarism_check(project, Path::new("plagiarism.html"), &options, None);
 }
 
 fn auto_select_tests(tests: &[String]) -> std::io::Result<Vec<String>> {
@@ -161,20 +227,50 @@ fn auto_select_tests(tests: &[String]) -> std::io::Result<Vec<String>> {
         .collect();
     Ok(selected_tests)
 }
-pub fn list_students() {
-    for student in list_students::list_students() {
-        println!("{}", student);
+pub fn list_students(format: OutputFormat) {
+    print_list("Student", &list_students::list_students(), format);
+}
+
+pub fn list_tests(_project: &Project, format: OutputFormat) {
+    print_list("Test", &darwin_config::list_tests(), format);
+}
+
+pub fn set_sandbox(sandbox: SandboxLevel) {
+    let result = read_config().and_then(|mut config| {
+        config.sandbox = sandbox;
+        write_config(config)
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to set sandbox: {}", e);
+    }
+}
+
+pub fn cache_dependencies(project: &Project) {
+    println!("Resolving the skeleton's dependencies...");
+    match project.cache_dependencies() {
+        Ok(()) => println!("Cached dependencies in {:?}. Student builds now run offline", dependency_cache_dir()),
+        Err(e) => eprintln!("Failed to cache dependencies: {}", e),
     }
 }
 
-pub fn list_tests(_project: &Project) {
-    for test in darwin_config::list_tests() {
-        println!("{}", test);
+pub fn set_timeouts(compile_timeout: Option<u64>, test_timeout: Option<u64>) {
+    let result = read_config().and_then(|mut config| {
+        config.compile_timeout = compile_timeout.unwrap_or(config.compile_timeout);
+        config.test_timeout = test_timeout.unwrap_or(config.test_timeout);
+        write_config(config)
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to set timeouts: {}", e);
     }
 }
 
-pub fn run_test_for_student(project: &Project, student: &str, test: &str) {
-    match run_tests::run_test_for_student(project, student, test) {
+/// `tests` is a comma separated list of tests
+pub fn run_test_for_student(project: &Project, student: &str, tests: &str) {
+    if let Err(e) = sandbox::check(project) {
+        eprintln!("{}", e);
+        return;
+    }
+    match run_tests::run_test_for_student(project, student, &split_tests(tests)) {
         Ok(()) => {}
         Err(e) => {
             eprintln!("{}", e);
@@ -182,29 +278,78 @@ pub fn run_test_for_student(project: &Project, student: &str, test: &str) {
     }
 }
 
-pub fn run_tests(project: &Project, test: &str, num_threads: usize) {
-    match run_tests::concurrent_run_test(
-        project,
-        test,
-        num_threads,
-        |s| println!("Processing: {}", s),
-        |s, e| eprintln!("Error processing {}: {}", s, e),
-        |_| {},
-    ) {
-        Ok(()) => {}
+/// `tests` is a comma separated list of tests, which run together
+pub fn run_tests(project: &Project, tests: &str, num_threads: usize) {
+    let progress = ProgressReporter::new();
+    let result = run_tests::concurrent_run_test(project, &split_tests(tests), num_threads, &progress);
+    progress.finish();
+    match result {
+        Ok(summary) => print_summary(&summary),
         Err(e) => {
             eprintln!("Error: {}", e);
         }
     }
 }
 
+/// `a, b,a` -> `[a, b]`
+fn split_tests(tests: &str) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for test in tests.split(',').map(str::trim).filter(|test| !test.is_empty()) {
+        if !out.iter().any(|t| t == test) {
+            out.push(test.to_string());
+        }
+    }
+    out
+}
+
+pub fn resume(project: &Project, num_threads: usize) {
+    let progress = ProgressReporter::new();
+    let result = run_tests::resume(project, num_threads, &progress);
+    progress.finish();
+    match result {
+        Ok(summary) if summary.runs == 0 => println!("No interrupted test runs to resume"),
+        Ok(summary) => {
+            println!("Resumed {} test runs", summary.runs);
+            print_summary(&summary);
+        }
+        Err(e) => eprintln!("Error: {}", e),
+    }
+}
+
+pub fn retry_failed(project: &Project, test: Option<&str>, num_threads: usize) {
+    let progress = ProgressReporter::new();
+    let result = run_tests::retry_failed(project, test, num_threads, &progress);
+    progress.finish();
+    match result {
+        Ok(summary) if summary.runs == 0 => println!("No failed test runs to retry"),
+        Ok(summary) => {
+            println!("Retried {} test runs", summary.runs);
+            print_summary(&summary);
+        }
+        Err(e) => eprintln!("Error: {}", e),
+    }
+}
+
+pub fn serve(project: &Project, address: &str, allowed_hosts: &[String], num_threads: usize) {
+    if let Err(e) = serve::serve(project, address, allowed_hosts, num_threads) {
+        eprintln!("Failed to serve on {}: {}", address, e);
+    }
+}
+
 pub enum ViewMode {
     Summarize,
     ClassName,
     Everything,
 }
 
-pub fn view_student_result(project: &Project, student: &str, test: &str, view_mode: &ViewMode) {
+pub fn view_student_result(project: &Project, student: &str, test: &str, view_mode: &ViewMode, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => print_student_result(project, student, test, view_mode),
+        _ => print_results(project, &[student.to_string()], test, view_mode, format, false),
+    }
+}
+
+fn print_student_result(project: &Project, student: &str, test: &str, view_mode: &ViewMode) {
     match view_student_results::parse_test_results(project, student, test) {
         Ok(result) => match view_mode {
             ViewMode::Summarize => {
@@ -215,6 +360,9 @@ pub fn view_student_result(project: &Project, student: &str, test: &str, view_mo
             }
             ViewMode::Everything => {
                 println!("{}", result.everything());
+                if matches!(result.state, TestState::CompilationError) {
+                    print_compile_output(student);
+                }
             }
         },
         Err(e) => match e {
@@ -228,17 +376,154 @@ pub fn view_student_result(project: &Project, student: &str, test: &str, view_mo
     }
 }
 
-pub fn view_all_results(project: &Project, test: &str, summarize: &ViewMode) {
+fn print_compile_output(student: &str) {
+    match view_student_results::parse_compile_output(student) {
+        Ok(Some(compile_error)) if compile_error.diagnostics.is_empty() => {
+            println!("{}", compile_error.output);
+        }
+        Ok(Some(compile_error)) => {
+            for diagnostic in compile_error.diagnostics {
+                println!("{}", diagnostic);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+pub fn view_all_results(project: &Project, test: &str, summarize: &ViewMode, format: OutputFormat) {
     if !is_test(project, test) {
         eprintln!("Test '{}' not recognized", test);
         return;
     }
-    list_students::list_students().iter().for_each(|student| {
+    let students = list_students::list_students();
+    if format != OutputFormat::Text {
+        print_results(project, &students, test, summarize, format, true);
+        return;
+    }
+    students.iter().for_each(|student| {
         println!("Processing '{}'", student);
-        view_student_result(project, student, test, summarize);
+        print_student_result(project, student, test, summarize);
     });
 }
 
+/// The students' results as a table, or as json: an array if `list`, otherwise the only student's object.
+/// Students whose results can not be read are reported and left out, as a json error object in place of the only
+/// student's
+fn print_results(project: &Project, students: &[String], test: &str, view_mode: &ViewMode, format: OutputFormat, list: bool) {
+    // Errors of a list go to stderr, the array has the students that could be read
+    let error_format = if list { OutputFormat::Text } else { format };
+    match view_mode {
+        ViewMode::Summarize => {
+            let summaries = collect_results(students, error_format, |student| view_student_results::result_summary(project, student, test));
+            print_rows(&summaries, format, list, &["Student", "Test", "State", "Correct", "Errored", "Failed"], |s: &ResultSummary| {
+                vec![vec![
+                    s.student.clone(),
+                    s.test.clone(),
+                    s.state.to_string(),
+                    s.correct.to_string(),
+                    s.errored.to_string(),
+                    s.failed.to_string(),
+                ]]
+            });
+        }
+        ViewMode::ClassName => {
+            let summaries = collect_results(students, error_format, |student| view_student_results::classname_summary(project, student, test));
+            print_rows(&summaries, format, list, &["Student", "Test", "State", "Class", "Correct", "Errored", "Failed"], |s: &ClassNameSummary| {
+                if s.classes.is_empty() {
+                    return vec![vec![s.student.clone(), s.test.clone(), s.state.to_string()]];
+                }
+                s.classes
+                    .iter()
+                    .map(|class| {
+                        vec![
+                            s.student.clone(),
+                            s.test.clone(),
+                            s.state.to_string(),
+                            class.classname.clone(),
+                            class.correct.to_string(),
+                            class.errored.to_string(),
+                            class.failed.to_string(),
+                        ]
+                    })
+                    .collect()
+            });
+        }
+        ViewMode::Everything => {
+            let results = collect_results(students, error_format, |student| view_student_results::verbose_result(project, student, test));
+            print_rows(&results, format, list, &["Student", "Test", "State", "Class", "Name", "Status", "Time", "Message"], verbose_rows);
+        }
+    }
+}
+
+fn collect_results<T>(students: &[String], format: OutputFormat, result: impl Fn(&str) -> std::io::Result<T>) -> Vec<T> {
+    students
+        .iter()
+        .filter_map(|student| match result(student) {
+            Ok(result) => Some(result),
+            Err(e) => {
+                print_error(&e.to_string(), format);
+                None
+            }
+        })
+        .collect()
+}
+
+fn print_rows<T: Serialize>(
+    results: &[T],
+    format: OutputFormat,
+    list: bool,
+    header: &[&str],
+    rows: impl Fn(&T) -> Vec<Vec<String>>,
+) {
+    match format {
+        OutputFormat::Json if list => print_json(results),
+        OutputFormat::Json => {
+            if let Some(result) = results.first() {
+                print_json(result);
+            }
+        }
+        _ => print_table(header, &results.iter().flat_map(rows).collect::<Vec<_>>()),
+    }
+}
+
+/// A row per test, or the reason there are none
+fn verbose_rows(result: &VerboseResult) -> Vec<Vec<String>> {
+    match result {
+        VerboseResult::Parsed { results, compile_output } => {
+            let state = results.result_state().to_string();
+            let TestState::Ok { results: tests } = &results.state else {
+                let message = compile_output.as_ref().map(|e| e.summary.clone()).unwrap_or_default();
+                return vec![vec![results.student.clone(), results.test.clone(), state, String::new(), String::new(), String::new(), String::new(), message]];
+            };
+            tests
+                .iter()
+                .map(|test| {
+                    let (status, message) = match &test.msg {
+                        StatusMsg::None => ("Passed", String::new()),
+                        StatusMsg::Failure { message, type_, .. } => ("Failure", message.clone().unwrap_or_else(|| type_.clone())),
+                        StatusMsg::Error { message, type_, .. } => ("Error", message.clone().unwrap_or_else(|| type_.clone())),
+                    };
+                    vec![
+                        results.student.clone(),
+                        results.test.clone(),
+                        state.clone(),
+                        test.classname.clone(),
+                        test.name.clone(),
+                        status.to_string(),
+                        format!("{:.3}", test.time.as_secs_f64()),
+                        // Tables are a line per test
+                        message.lines().next().unwrap_or_default().to_string(),
+                    ]
+                })
+                .collect()
+        }
+        VerboseResult::NotRun { student, test, state } => vec![vec![student.clone(), test.clone(), state.to_string()]],
+    }
+}
+
 pub fn download_results_summary(project: &Project, test: &str, outfile: &str) {
     let out_file_path = Path::new(outfile);
     if out_file_path.exists()
@@ -264,6 +549,94 @@ pub fn download_results_by_classname(project: &Project, test: &str, outfile: &st
     download_results::download_results_by_classname(project, out_file, test).unwrap();
 }
 
+pub fn set_rubric(rubric: &Path) {
+    if let Err(e) = grade::set_rubric(rubric) {
+        eprintln!("Failed to set rubric: {}", e);
+    }
+}
+
+pub fn grade(project: &Project, outfile: Option<&Path>, format: OutputFormat) {
+    let rubric = match grade::read_rubric() {
+        Ok(Some(rubric)) => rubric,
+        Ok(None) => {
+            print_error("No rubric. Set one with set-rubric first", format);
+            return;
+        }
+        Err(e) => {
+            print_error(&e.to_string(), format);
+            return;
+        }
+    };
+    let grades = match grade::grade_students(project, &rubric, &list_students::list_students()) {
+        Ok(grades) => grades,
+        Err(e) => {
+            print_error(&format!("Failed to grade: {}", e), format);
+            return;
+        }
+    };
+    match format {
+        OutputFormat::Text => {
+            for grade in grades.iter() {
+                println!("{}: {}", grade.student, grade);
+            }
+        }
+        OutputFormat::Table => {
+            let rows: Vec<Vec<String>> = grades
+                .iter()
+                .map(|grade| {
+                    vec![
+                        grade.student.clone(),
+                        grade.score.map(|score| score.to_string()).unwrap_or_else(|| String::from("Manual")),
+                        grade.possible.to_string(),
+                        grade.compile_error.to_string(),
+                    ]
+                })
+                .collect();
+            print_table(&["Student", "Score", "Possible", "Compile Error"], &rows);
+        }
+        OutputFormat::Json => print_json(&grades),
+    }
+
+    let Some(outfile) = outfile else {
+        return;
+    };
+    if outfile.exists()
+        && !prompt_yn(&format!("{} Exists. Continue? (y/n)", outfile.display())).unwrap_or(false)
+    {
+        return;
+    }
+    let result = File::create(outfile).and_then(|f| grade::download_grades(&rubric, &grades, f));
+    if let Err(e) = result {
+        eprintln!("Failed to write grades: {}", e);
+    }
+}
+
+pub fn export_moodle_grades(project: &Project, worksheet: &Path, feedback_zip: &Path, tests: &[String]) {
+    for path in [worksheet, feedback_zip] {
+        if path.exists()
+            && !prompt_yn(&format!("{} Exists. Continue? (y/n)", path.display())).unwrap_or(false)
+        {
+            return;
+        }
+    }
+    let tests = if tests.is_empty() { darwin_config::list_tests() } else { tests.to_vec() };
+    match moodle_export::export_moodle_grades(project, worksheet, feedback_zip, &tests) {
+        Ok(missing) => {
+            for student in &missing {
+                eprintln!("Left out {}, who has no moodle participant id", student);
+            }
+            if !missing.is_empty() {
+                eprintln!("Projects created before participant ids were recorded have none. Import the Moodle zip again \
+                with add-submissions to record them, which queues the students' tests to run again");
+            }
+            println!("Grades written to {} and feedback files to {}", worksheet.display(), feedback_zip.display());
+        }
+        Err(e) => {
+            eprintln!("Failed to export grades: {}", e);
+        }
+    }
+}
+
 pub fn view_student_submission(project: &Project, student: &str) {
     let dest = Path::new(student);
     if dest.exists()
@@ -315,12 +688,29 @@ pub fn create_report(project: &Project, report_path: &Path, parts: u8, tests: &V
     }
 }
 
-pub fn plagiarism_check(dest_path: &Path) {
+pub fn plagiarism_check(
+    project: &Project,
+    dest_path: &Path,
+    options: &PlagiarismCheckOptions,
+    pairs_path: Option<&Path>,
+) {
+    let algorithm = options.algorithm;
+    if let Some(Err(e)) = pairs_path.map(plagiarism_checker::pairs_format) {
+        eprintln!("{}", e);
+        return;
+    }
     if dest_path.exists()
         && !prompt_yn(&format!("{:?} Exists. Continue? (y/n)", dest_path)).unwrap_or(false)
     {
         return;
     }
+    if let Some(pairs_path) = pairs_path {
+        if pairs_path.exists()
+            && !prompt_yn(&format!("{} Exists. Continue? (y/n)", pairs_path.display())).unwrap_or(false)
+        {
+            return;
+        }
+    }
 
     if (dest_path.is_file() && remove_file(dest_path).is_err())
         || (dest_path.is_dir() && remove_dir_all(dest_path).is_err())
@@ -329,23 +719,193 @@ pub fn plagiarism_check(dest_path: &Path) {
         return;
     }
 
-    match plagiarism_checker::plagiarism_check(dest_path) {
-        Ok(_) => {
-            println!("Plagiarism report generated at {:?}", dest_path);
-        }
+    let result = match plagiarism_checker::plagiarism_check(project, dest_path, options) {
+        Ok(result) => result,
         Err(e) => {
             eprintln!("{}", e);
+            return;
+        }
+    };
+    println!("Plagiarism report generated at {:?}", dest_path);
+
+    println!("Suspicious pairs, at most {} apart:", format_distance(algorithm, result.threshold));
+    if result.pairs.is_empty() {
+        println!("  None");
+    }
+    for pair in &result.pairs {
+        println!(
+            "  {}. {}  {}  {}  (cluster {})",
+            pair.rank,
+            format_distance(algorithm, pair.distance),
+            pair.student1,
+            pair.student2,
+            pair.cluster
+        );
+    }
+    if !result.clusters.is_empty() {
+        println!("Clusters:");
+    }
+    for cluster in &result.clusters {
+        println!("  {}. {}", cluster.cluster, cluster.students.join(", "));
+    }
+
+    if !options.archives.is_empty() {
+        println!("Closest archived submissions:");
+        if result.archive_matches.is_empty() {
+            println!("  None share any code");
+        }
+        for archive_match in &result.archive_matches {
+            println!(
+                "  {:.0}%  {}  {}",
+                archive_match.similarity * 100.0,
+                archive_match.student,
+                archive_match.archived
+            );
+        }
+    }
+
+    if let Some(pairs_path) = pairs_path {
+        if let Err(e) = plagiarism_checker::write_pairs(pairs_path, algorithm, result.threshold, &result.pairs, &result.clusters) {
+            eprintln!("Failed to write pairs: {}", e);
         }
     }
 }
 
-pub fn plagiarism_check_students(student1: String, student2: String) {
-    match plagiarism_checker::plagiarism_check_students(&student1, &student2) {
-        Ok(score) => {
+/// Winnowing distances are fractions, TLSH's whole numbers
+fn format_distance(algorithm: PlagiarismAlgorithm, distance: f64) -> String {
+    match algorithm {
+        PlagiarismAlgorithm::Winnowing => format!("{:.3}", distance),
+        PlagiarismAlgorithm::Tlsh => format!("{:.0}", distance),
+    }
+}
+
+/// `plagiarism-check-students --format json`. `similarity` and the containments are only set by winnowing, and
+/// `distance` by TLSH
+#[derive(Serialize)]
+struct StudentsSimilarity<'a> {
+    student1: &'a str,
+    student2: &'a str,
+    algorithm: PlagiarismAlgorithm,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distance: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    similarity: Option<f64>,
+    /// Fraction of student1's code found in student2's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    containment1: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    containment2: Option<f64>,
+    matches: Vec<MatchLocation>,
+}
+
+#[derive(Serialize)]
+struct MatchLocation {
+    /// `path:first-last` in student1's project
+    location1: String,
+    location2: String,
+    tokens: usize,
+}
+
+pub fn plagiarism_check_students(project: &Project, student1: String, student2: String, algorithm: PlagiarismAlgorithm, format: OutputFormat) {
+    let mut similarity = StudentsSimilarity {
+        student1: &student1,
+        student2: &student2,
+        algorithm,
+        distance: None,
+        similarity: None,
+        containment1: None,
+        containment2: None,
+        matches: Vec::new(),
+    };
+    if algorithm == PlagiarismAlgorithm::Tlsh {
+        match plagiarism_checker::plagiarism_check_students(&student1, &student2) {
+            Ok(score) if format == OutputFormat::Text => {
+                println!(
+                    "{} and {} have a similarity score of {}",
+                    student1, student2, score
+                );
+            }
+            Ok(score) => {
+                similarity.distance = Some(score);
+                print_students_similarity(&similarity, format);
+            }
+            Err(e) => {
+                print_error(&e.to_string(), format);
+            }
+        }
+        return;
+    }
+
+    match plagiarism_checker::compare_students(project, &student1, &student2) {
+        Ok(comparison) if format != OutputFormat::Text => {
+            similarity.similarity = Some(comparison.similarity());
+            similarity.containment1 = Some(comparison.containment1);
+            similarity.containment2 = Some(comparison.containment2);
+            similarity.matches = comparison
+                .match_locations()
+                .into_iter()
+                .map(|(location1, location2, tokens)| MatchLocation { location1, location2, tokens })
+                .collect();
+            print_students_similarity(&similarity, format);
+        }
+        Ok(comparison) => {
             println!(
-                "{} and {} have a similarity score of {}",
-                student1, student2, score
+                "{} and {} are {:.0}% similar: {:.0}% of {}'s code is in {}'s, {:.0}% of {}'s is in {}'s",
+                student1,
+                student2,
+                comparison.similarity() * 100.0,
+                comparison.containment1 * 100.0,
+                student1,
+                student2,
+                comparison.containment2 * 100.0,
+                student2,
+                student1,
             );
+            for (location1, location2, tokens) in comparison.match_locations() {
+                println!("  {}  {}  ({} tokens)", location1, location2, tokens);
+            }
+        }
+        Err(e) => {
+            print_error(&e.to_string(), format);
+        }
+    }
+}
+
+/// A table of the matches, or of the TLSH distance
+fn print_students_similarity(similarity: &StudentsSimilarity, format: OutputFormat) {
+    if format == OutputFormat::Json {
+        print_json(similarity);
+        return;
+    }
+    if let Some(distance) = similarity.distance {
+        print_table(&["Student 1", "Student 2", "Distance"], &[vec![similarity.student1.to_string(), similarity.student2.to_string(), distance.to_string()]]);
+        return;
+    }
+    let rows: Vec<Vec<String>> = similarity
+        .matches
+        .iter()
+        .map(|m| vec![m.location1.clone(), m.location2.clone(), m.tokens.to_string()])
+        .collect();
+    print_table(&[similarity.student1, similarity.student2, "Tokens"], &rows);
+}
+
+pub fn plagiarism_report_students(project: &Project, student1: String, student2: String, dest_path: &Path) {
+    if dest_path.exists()
+        && !prompt_yn(&format!("{:?} Exists. Continue? (y/n)", dest_path)).unwrap_or(false)
+    {
+        return;
+    }
+
+    if (dest_path.is_file() && remove_file(dest_path).is_err())
+        || (dest_path.is_dir() && remove_dir_all(dest_path).is_err())
+    {
+        eprintln!("Failed to remove {:?}", dest_path);
+        return;
+    }
+
+    match plagiarism_checker::plagiarism_report_students(project, &student1, &student2, dest_path) {
+        Ok(_) => {
+            println!("Comparison of {} and {} generated at {:?}", student1, student2, dest_path);
         }
         Err(e) => {
             eprintln!("{}", e);
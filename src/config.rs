@@ -1,9 +1,14 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub fn darwin_root() -> PathBuf {
     PathBuf::from(".darwin")
 }
 
+/// Where one of the paths below is in a .darwin folder other than the current one, eg. an archived term's
+pub fn in_darwin_root(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix(darwin_root()).unwrap_or(path))
+}
+
     pub fn darwin_config() -> PathBuf {
         darwin_root().join("darwin.json")
     }
@@ -16,6 +21,24 @@ pub fn darwin_root() -> PathBuf {
             diff_dir().join(student)
         }
 
+    /// Content addressed store of the submission files diffs can not hold, see submission_blobs.rs
+    pub fn blobs_dir() -> PathBuf {
+        darwin_root().join("blobs")
+    }
+
+        pub fn blob_file(hash: &str) -> PathBuf {
+            blobs_dir().join(hash)
+        }
+
+    /// Which blobs make up each student's submission, next to their diff
+    pub fn submission_blobs_dir() -> PathBuf {
+        darwin_root().join("submission_blobs")
+    }
+
+        pub fn student_blobs_file(student: &str) -> PathBuf {
+            submission_blobs_dir().join(student)
+        }
+
     pub fn projects_dir() -> PathBuf {
         darwin_root().join("projects")
     }
@@ -24,17 +47,56 @@ pub fn darwin_root() -> PathBuf {
             projects_dir().join(student)
         }
 
-    pub fn results_dir() -> PathBuf {
+    /// Every student's test results, compile errors and timeouts, see result_store.rs
+    pub fn result_store_file() -> PathBuf {
+        darwin_root().join("results.json")
+    }
+
+        /// Locked while results.json is updated
+        pub fn result_store_lock_file() -> PathBuf {
+            darwin_root().join("results.lock")
+        }
+
+    /// Test reports and build output the result store refers to, named by the sha256 of their content
+    pub fn reports_dir() -> PathBuf {
+        darwin_root().join("reports")
+    }
+
+        pub fn report_file(hash: &str) -> PathBuf {
+            reports_dir().join(hash)
+        }
+
+    /// Where results were kept before results.json. Only read to migrate them
+    pub fn legacy_results_dir() -> PathBuf {
         darwin_root().join("results")
     }
-        pub fn student_result_file(student: &str, test: &str) -> PathBuf {
-            results_dir().join(format!("{}_{}", student, test))
+        pub fn legacy_student_result_file(student: &str, test: &str) -> PathBuf {
+            legacy_results_dir().join(format!("{}_{}", student, test))
         }
 
-    pub fn compile_errors_file() -> PathBuf {
+    /// `student:reason` lines
+    pub fn legacy_compile_errors_file() -> PathBuf {
         darwin_root().join("compile_errors")
     }
 
+    pub fn legacy_compile_output_dir() -> PathBuf {
+        darwin_root().join("compile_output")
+    }
+
+        pub fn legacy_student_compile_output_file(student: &str) -> PathBuf {
+            legacy_compile_output_dir().join(format!("{}.json", student))
+        }
+
+    /// `student:test` lines
+    pub fn legacy_timeouts_file() -> PathBuf {
+        darwin_root().join("timeouts")
+    }
+
+    /// Points awarded for passing tests, see grade.rs
+    pub fn rubric_file() -> PathBuf {
+        darwin_root().join("rubric.json")
+    }
+
     pub fn skel_dir() -> PathBuf {
         darwin_root().join("skel")
     }
@@ -42,3 +104,8 @@ pub fn darwin_root() -> PathBuf {
     pub fn diff_exclude_dir() -> PathBuf {
         darwin_root().join("diff_exclude")
     }
+
+    /// The skeleton's dependencies, resolved once by cache-dependencies. Student builds run offline against it
+    pub fn dependency_cache_dir() -> PathBuf {
+        darwin_root().join("dependencies")
+    }
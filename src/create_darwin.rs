@@ -1,20 +1,24 @@
 use crate::config::{
-    compile_errors_file, darwin_root, diff_dir, projects_dir, results_dir, skel_dir, student_diff_file
+    darwin_root, diff_dir, projects_dir, skel_dir, student_diff_file
 };
-use crate::darwin_config::{write_config, DarwinConfig};
-use crate::util::{create_diff, extract_file};
+use crate::clean::clean_student;
+use crate::result_store::{clear_result_store, update_result_store, RunState};
+use crate::darwin_config::{read_config, write_config, DarwinConfig, SubmissionSource};
+use crate::submission_blobs::{store_blobs, write_submission_blobs};
+use crate::submission_source::{self, student_submission, Submission};
+use crate::util::create_diff;
 use std::collections::HashMap;
-use std::fs::{remove_dir_all, File};
+use std::fs::remove_dir_all;
 use std::io::{Error, ErrorKind, Result};
-use std::{collections::HashSet, fs, path::Path};
-use tempfile::{tempdir, tempfile};
-use zip::ZipArchive;
+use std::{collections::HashSet, fs, path::{Path, PathBuf}};
+use tempfile::{tempdir, NamedTempFile};
 use crate::project_runner::Project;
 
 pub fn create_darwin(
     project: &Project,
     project_skeleton: &Path,
-    moodle_submissions_zipfile: &Path,
+    submission_source: SubmissionSource,
+    submissions_path: &Path,
     copy_ignore_set: &HashSet<&str>,
 ) -> Result<()> {
     if darwin_root().exists() {
@@ -29,26 +33,12 @@ pub fn create_darwin(
             "skeleton_path must be a directory",
         ));
     }
-    if !moodle_submissions_zipfile.is_file() {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            "moodle_submissions_zipfile path is not a zipfile",
-        ));
-    }
-    if !moodle_submissions_zipfile
-        .extension()
-        .map_or(true, |ext| ext == "zip")
-    {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            "moodle_submissions_zipfile path is not a zipfile",
-        ));
-    }
 
     let status = _create_darwin(
         project,
         project_skeleton,
-        moodle_submissions_zipfile,
+        submission_source,
+        submissions_path,
         copy_ignore_set,
     );
 
@@ -62,79 +52,175 @@ pub fn create_darwin(
 fn _create_darwin(
     project: &Project,
     skeleton_path: &Path,
-    submission_zipfile_path: &Path,
+    submission_source: SubmissionSource,
+    submissions_path: &Path,
     copy_ignore_set: &HashSet<&str>,
 ) -> Result<()> {
     fs::create_dir_all(darwin_root())?;
     fs::create_dir_all(diff_dir())?;
     fs::create_dir_all(projects_dir())?;
-    fs::create_dir_all(results_dir())?;
-    File::create(compile_errors_file())?;
+    clear_result_store()?;
 
     project.init_skeleton(skeleton_path)?;
 
 
     let mut extraction_errors: HashMap<String, String> = HashMap::new();
+    let mut project_roots: HashMap<String, PathBuf> = HashMap::new();
+    let mut student_ids: HashMap<String, String> = HashMap::new();
 
-    submissions_to_diffs(project, submission_zipfile_path, copy_ignore_set, &mut |s, e| {
+    submissions_to_diffs(project, submission_source, submissions_path, copy_ignore_set, &mut |s, e| {
         eprintln!("Error parsing {}'s submission: {}", s, e);
         extraction_errors.insert(s.to_string(), e.to_string());
+    }, &mut |s, id, root| {
+        project_roots.insert(s.to_string(), root);
+        if let Some(id) = id {
+            student_ids.insert(s.to_string(), id.to_string());
+        }
+    })?;
+
+    create_config(project, submission_source, extraction_errors, project_roots, student_ids)?;
+    Ok(())
+}
+
+/// Imports every submission in a source into an existing project, replacing the submissions of students already
+/// in it. Returns the students whose submission was added or replaced
+pub fn add_submissions(
+    project: &Project,
+    submission_source: SubmissionSource,
+    submissions_path: &Path,
+    copy_ignore_set: &HashSet<&str>,
+) -> Result<Vec<String>> {
+    validate_existing_project()?;
+
+    let mut extraction_errors: HashMap<String, String> = HashMap::new();
+    let mut project_roots: HashMap<String, PathBuf> = HashMap::new();
+    let mut student_ids: HashMap<String, String> = HashMap::new();
+    submissions_to_diffs(project, submission_source, submissions_path, copy_ignore_set, &mut |s, e| {
+        eprintln!("Error parsing {}'s submission, keeping any previous submission: {}", s, e);
+        extraction_errors.insert(s.to_string(), e.to_string());
+    }, &mut |s, id, root| {
+        project_roots.insert(s.to_string(), root);
+        if let Some(id) = id {
+            student_ids.insert(s.to_string(), id.to_string());
+        }
     })?;
 
-    create_config(project, extraction_errors)?;
+    let mut config = read_config()?;
+    config.extraction_errors.extend(extraction_errors);
+    config.student_ids.extend(student_ids);
+    let mut students: Vec<String> = project_roots.keys().cloned().collect();
+    students.sort();
+    for (student, root) in project_roots {
+        clean_student(&student)?;
+        config.extraction_errors.remove(&student);
+        config.project_roots.insert(student, root);
+    }
+    queue_tests_run(&students, &config.tests_run)?;
+    write_config(config)?;
+    Ok(students)
+}
+
+/// Imports a single student's submission zip, tarball or directory into an existing project, replacing their previous
+/// submission if any
+pub fn add_student_submission(
+    project: &Project,
+    student: &str,
+    submission_path: &Path,
+    copy_ignore_set: &HashSet<&str>,
+) -> Result<()> {
+    validate_existing_project()?;
+    if student.is_empty() || student.contains(['/', ':', '\n']) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("'{}' is not a valid student name", student),
+        ));
+    }
+
+    let submission = student_submission(submission_path)?;
+    let mut config = read_config()?;
+    match submission_to_diff(project, student, submission, copy_ignore_set) {
+        Ok(root) => {
+            clean_student(student)?;
+            config.extraction_errors.remove(student);
+            config.project_roots.insert(student.to_string(), root);
+            queue_tests_run(&[student.to_string()], &config.tests_run)?;
+            write_config(config)
+        }
+        Err(e) => {
+            config.extraction_errors.insert(student.to_string(), e.to_string());
+            write_config(config)?;
+            Err(e)
+        }
+    }
+}
+
+/// The tests already run stay run for the rest of the class. Updated students, whose results were discarded, are
+/// queued for them so `resume` runs only those students
+fn queue_tests_run(students: &[String], tests_run: &[String]) -> Result<()> {
+    if students.is_empty() || tests_run.is_empty() {
+        return Ok(());
+    }
+    update_result_store(|store| {
+        for student in students {
+            for test in tests_run {
+                store.set_run_state(student, test, RunState::Queued, None);
+            }
+        }
+    })
+}
+
+fn validate_existing_project() -> Result<()> {
+    if !darwin_root().is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "darwin project not initialized in this directory",
+        ));
+    }
     Ok(())
 }
 
-fn create_config(project: &Project, extraction_errors: HashMap<String, String>) -> Result<()> {
+fn create_config(project: &Project, submission_source: SubmissionSource, extraction_errors: HashMap<String, String>, project_roots: HashMap<String, PathBuf>, student_ids: HashMap<String, String>) -> Result<()> {
     // Expensive list tests
     let tests: Vec<String> = project.list_tests().iter().cloned().collect();
-    let config = DarwinConfig { version: String::from("1.0.0"), project_type: project.project_type.clone(), tests, tests_run: Vec::new(), extraction_errors };
+    let config = DarwinConfig { version: String::from("1.0.0"), project_type: project.project_type.clone(), tests, tests_run: Vec::new(), extraction_errors, project_roots, sandbox: project.sandbox, compile_timeout: project.compile_timeout.as_secs(), test_timeout: project.test_timeout.as_secs(), submission_source, student_ids };
     write_config(config)?;
     Ok(())
 }
 
-fn submissions_to_diffs<F>(
+fn submissions_to_diffs<F, G>(
     project: &Project,
-    submission_zipfile_path: &Path,
+    submission_source: SubmissionSource,
+    submissions_path: &Path,
     copy_ignore_set: &HashSet<&str>,
     on_submission_extraction_error: &mut F, // Student name
+    on_submission_extracted: &mut G, // Student name, their id in the source, project root within their zip
 ) -> Result<()> 
-where F: for<'a> FnMut(&'a str, &'a std::io::Error)
+where F: for<'a> FnMut(&'a str, &'a std::io::Error), G: for<'a> FnMut(&'a str, Option<&'a str>, PathBuf)
 {
-    let zip = File::open(submission_zipfile_path)?;
-    let mut zip = ZipArchive::new(zip)?;
-
-    let file_names: Vec<String> = zip.file_names().map(String::from).collect();
-    for file_name in file_names {
-        if Path::new(&file_name)
-            .extension()
-            .map_or(true, |x| x != "zip")
-        {
-            continue;
-        }
-
-        let student_name = &file_name[0..file_name.find('_').expect("Moodle submission zipfile must delimit all contained student submission zipfiles with '_'. Perhaps moodle changed its naming scheme or this isn't a moodle submission zipfile.")];
-
-        let mut student_submission_file = tempfile()?;
-
-        if let Err(e) = extract_file(&mut zip, &file_name, &mut student_submission_file) {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Error extracting {}'s submission: {}", student_name, e),
-            ));
-        }
-
-        let mut student_project_zip = ZipArchive::new(student_submission_file)?;
-        let normalized_project = tempdir()?;
-        if let Err(e) = project.zip_submission_to_normalized_form(&mut student_project_zip, normalized_project.path(), Some(copy_ignore_set)) {
-            on_submission_extraction_error(student_name, &e);
-            continue;
-        }
-        if let Err(e) = create_diff(&skel_dir(), normalized_project.path(), &student_diff_file(student_name)) {
-            on_submission_extraction_error(student_name, &e);
-            continue;
+    submission_source::for_each_submission(submission_source, submissions_path, &mut |student_name, id, submission| {
+        match submission.and_then(|submission| submission_to_diff(project, student_name, submission, copy_ignore_set)) {
+            Ok(root) => on_submission_extracted(student_name, id, root),
+            Err(e) => on_submission_extraction_error(student_name, &e),
         }
-    }
+    })
+}
 
-    Ok(())
+/// Normalizes a student's submission and writes its diff against the skeleton, replacing any previous diff.
+/// The previous diff is left untouched if this fails. Returns the project root within the submission
+fn submission_to_diff(
+    project: &Project,
+    student: &str,
+    submission: Submission,
+    copy_ignore_set: &HashSet<&str>,
+) -> Result<PathBuf> {
+    let mut student_project_zip = submission.into_zip()?;
+    let normalized_project = tempdir()?;
+    let root = project.zip_submission_to_normalized_form(&mut student_project_zip, normalized_project.path(), Some(copy_ignore_set))?;
+    let blobs = store_blobs(&darwin_root(), &skel_dir(), normalized_project.path())?;
+    // Outside of diff_dir, where every file is a student
+    let diff = NamedTempFile::new_in(darwin_root())?;
+    create_diff(&skel_dir(), normalized_project.path(), diff.path())?;
+    diff.persist(student_diff_file(student))?;
+    write_submission_blobs(&darwin_root(), student, &blobs)?;
+    Ok(root)
 }
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fs::{self, create_dir, create_dir_all, remove_dir_all},
     io::{Error, ErrorKind, Result},
     path::{Path, PathBuf},
@@ -6,12 +7,12 @@ use std::{
 
 use handlebars::Handlebars;
 use serde::Serialize;
-use tempfile::tempdir;
+use tempfile::{tempdir, TempDir};
 
 use crate::{
-    config::{darwin_root, student_diff_file}, darwin_config::{self, read_config}, list_students::list_students, project_runner::Project, types::{StatusMsg, TestResult, TestResultError, TestResults}, util::{
+    config::darwin_root, darwin_config::{self, read_config}, grade::{grade_students, read_rubric}, list_students::list_students, project_runner::Project, types::{StatusMsg, TestResult, TestResultError, TestResults, TestState}, util::{
         flatten_move_recursive, dir_list_absolute_file_paths_recursively,
-    }, view_student_results::parse_test_results
+    }, view_student_results::{parse_compile_output, parse_test_results}
 };
 
 #[derive(Serialize)]
@@ -19,21 +20,43 @@ struct StudentTemplateContext<'a> {
     file: &'a str,
     files: &'a Vec<StudentTemplateFile>,
     code: &'a str,
-    test_contexts: &'a Vec<TestPackageContext<'a>>,
+    test_contexts: &'a Vec<TestPackageContext>,
+    compile_errors: &'a CompileErrorsContext,
+    /// Compile errors in `file`, marked in the source view
+    file_diagnostics: Vec<&'a CompileDiagnosticContext>,
     prev_student: &'a str,
     student: &'a str,
     next_student: &'a str,
+    /// Rubric score, shown in the header
+    grade: Option<&'a str>,
 }
 
 #[derive(Serialize)]
-struct TestPackageContext<'a> {
-    test_package_name: &'a str,
+struct TestPackageContext {
+    test_package_name: String,
     subpackages: Vec<TestSubpackageContext>,
     compile_error: bool,
+    timeout: bool,
     other_error: bool,
     not_ran: bool,
 }
 
+#[derive(Serialize, Default)]
+struct CompileErrorsContext {
+    diagnostics: Vec<CompileDiagnosticContext>,
+    output: String,
+}
+
+#[derive(Serialize)]
+struct CompileDiagnosticContext {
+    /// Path in the student's original project, as listed in the file list
+    file: String,
+    html_path: String,
+    line: usize,
+    column: String,
+    message: String,
+}
+
 #[derive(Serialize)]
 struct TestSubpackageContext {
     subpackage_name: String,
@@ -79,9 +102,35 @@ struct StudentIndexTemplateContext<'a> {
     prev_student: &'a str,
     next_student: &'a str,
     files: &'a Vec<StudentTemplateFile>,
-    test_contexts: &'a Vec<TestPackageContext<'a>>,
+    test_contexts: &'a Vec<TestPackageContext>,
+    compile_errors: &'a CompileErrorsContext,
+    grade: Option<&'a str>,
 }
 
+/// Everything a student's pages are rendered from. The original project is kept in `tmpdir`, so the pages can be
+/// written over its files
+struct StudentReport {
+    tmpdir: TempDir,
+    file_paths: Vec<PathBuf>,
+    files: Vec<StudentTemplateFile>,
+    test_packages: Vec<TestPackageContext>,
+    compile_errors: CompileErrorsContext,
+}
+
+/// Styles, scripts and fonts the pages refer to under `styles/`
+pub const REPORT_STYLES: [(&str, &[u8]); 10] = [
+    ("global.css", include_bytes!("../template/global.css")),
+    ("index.css", include_bytes!("../template/index.css")),
+    ("sidebars.js", include_bytes!("../template/sidebars.js")),
+    ("compile_errors.js", include_bytes!("../template/compile_errors.js")),
+    ("student_index.css", include_bytes!("../template/student_index.css")),
+    ("student.css", include_bytes!("../template/student.css")),
+    ("LibreBaskerville-Regular.ttf", include_bytes!("../template/LibreBaskerville-Regular.ttf")),
+    ("LibreBaskerville-Italic.ttf", include_bytes!("../template/LibreBaskerville-Italic.ttf")),
+    ("LibreBaskerville-Bold.ttf", include_bytes!("../template/LibreBaskerville-Bold.ttf")),
+    ("OFL.txt", include_bytes!("../template/OFL.txt")),
+];
+
 pub fn create_report(project: &Project, report_path: &Path, tests: &Vec<String>, parts: u8) -> Result<()> {
     if !darwin_root().is_dir() {
         return Err(Error::new(
@@ -132,8 +181,17 @@ fn _create_report(project: &Project, report_root: &Path, tests: &[String], parts
     let mut handlebars = Handlebars::new();
     initialize_handlebars(&mut handlebars)?;
 
+    // Graded across all students, so every part agrees on the points possible
+    let grades: HashMap<String, String> = match read_rubric()? {
+        Some(rubric) => grade_students(project, &rubric, &students)?
+            .into_iter()
+            .map(|grade| (grade.student.clone(), grade.to_string()))
+            .collect(),
+        None => HashMap::new(),
+    };
+
     if parts == 1 {
-        _create_report_of_certain_students(project, report_root, tests, &students, &handlebars)?;
+        _create_report_of_certain_students(project, report_root, tests, &students, &grades, &handlebars)?;
     } else {
         create_dir_all(report_root)?;
 
@@ -147,6 +205,7 @@ fn _create_report(project: &Project, report_root: &Path, tests: &[String], parts
                     &report_root.join(i.to_string()),
                     tests,
                     students_section,
+                    &grades,
                     &handlebars,
                 )?;
             }
@@ -156,43 +215,56 @@ fn _create_report(project: &Project, report_root: &Path, tests: &[String], parts
     Ok(())
 }
 
+/// The index page of a student in a report created by create_report, which may be split into parts
+pub fn student_report_page(report_root: &Path, student: &str) -> Option<PathBuf> {
+    let page = |root: &Path| root.join("students").join(student).join("index.html");
+    if page(report_root).is_file() {
+        return Some(page(report_root));
+    }
+    fs::read_dir(report_root)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| page(&entry.path()))
+        .find(|page| page.is_file())
+}
+
 fn _create_report_of_certain_students(
     project: &Project,
     report_root: &Path,
     tests: &[String],
     students: &[String],
+    grades: &HashMap<String, String>,
     handlebars: &Handlebars,
 ) -> Result<()> {
     report_initialize(report_root).map_err(|e| {
-        Error::new(
-            ErrorKind::Other,
+        Error::other(
             format!("Failed to initialize report: {}", e),
         )
     })?;
 
     create_static_page_html(project, &report_root.join("tests.html"), handlebars)?;
     create_report_student_list(&report_root.join("index.html"), students, handlebars)?;
-    create_student_reports(project, report_root, tests, students, handlebars)?;
+    create_student_reports(project, report_root, tests, students, grades, handlebars)?;
 
     Ok(())
 }
 
-fn initialize_handlebars(handlebars: &mut Handlebars) -> Result<()> {
+pub fn initialize_handlebars(handlebars: &mut Handlebars) -> Result<()> {
     handlebars
         .register_template_string("student_list", include_str!("../template/index.hbs"))
-        .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| Error::other(e.to_string()))?;
     handlebars
         .register_template_string(
             "student_index_template",
             include_str!("../template/student_index.hbs"),
         )
-        .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| Error::other(e.to_string()))?;
     handlebars
         .register_template_string("tests_template", include_str!("../template/tests.hbs"))
-        .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| Error::other(e.to_string()))?;
     handlebars
         .register_template_string("student_template", include_str!("../template/student.hbs"))
-        .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| Error::other(e.to_string()))?;
     Ok(())
 }
 
@@ -201,46 +273,9 @@ fn report_initialize(report_root: &Path) -> Result<()> {
     create_dir(report_root.join("students"))?;
     create_dir(report_root.join("styles"))?;
 
-    fs::write(
-        report_root.join("styles").join("global.css"),
-        include_bytes!("../template/global.css"),
-    )?;
-    fs::write(
-        report_root.join("styles").join("index.css"),
-        include_str!("../template/index.css"),
-    )?;
-    fs::write(
-        report_root.join("styles").join("sidebars.js"),
-        include_bytes!("../template/sidebars.js"),
-    )?;
-    fs::write(
-        report_root.join("styles").join("student_index.css"),
-        include_str!("../template/student_index.css"),
-    )?;
-    fs::write(
-        report_root.join("styles").join("student.css"),
-        include_str!("../template/student.css"),
-    )?;
-    fs::write(
-        report_root
-            .join("styles")
-            .join("LibreBaskerville-Regular.ttf"),
-        include_bytes!("../template/LibreBaskerville-Regular.ttf"),
-    )?;
-    fs::write(
-        report_root
-            .join("styles")
-            .join("LibreBaskerville-Italic.ttf"),
-        include_bytes!("../template/LibreBaskerville-Italic.ttf"),
-    )?;
-    fs::write(
-        report_root.join("styles").join("LibreBaskerville-Bold.ttf"),
-        include_bytes!("../template/LibreBaskerville-Bold.ttf"),
-    )?;
-    fs::write(
-        report_root.join("styles").join("OFL.txt"),
-        include_bytes!("../template/OFL.txt"),
-    )?;
+    for (name, content) in REPORT_STYLES {
+        fs::write(report_root.join("styles").join(name), content)?;
+    }
     Ok(())
 }
 
@@ -249,10 +284,13 @@ fn create_report_student_list(
     students: &[String],
     handlebars: &Handlebars,
 ) -> Result<()> {
-    let rendered = handlebars
+    fs::write(dest, render_student_list(students, handlebars)?)
+}
+
+pub fn render_student_list(students: &[String], handlebars: &Handlebars) -> Result<String> {
+    handlebars
         .render("student_list", &StudentListContext { students })
-        .map_err(|e| Error::new(ErrorKind::Other, format!("could not open template: {}", e)))?;
-    fs::write(dest, rendered)
+        .map_err(|e| Error::other(format!("could not open template: {}", e)))
 }
 
 fn create_student_reports(
@@ -260,6 +298,7 @@ fn create_student_reports(
     report_root: &Path,
     tests: &[String],
     students: &[String],
+    grades: &HashMap<String, String>,
     handlebars: &Handlebars,
 ) -> Result<()> {
     let mut prev_student = "";
@@ -272,6 +311,7 @@ fn create_student_reports(
             prev_student,
             student,
             &students[i + 1],
+            grades.get(student).map(String::as_str),
             handlebars,
         )?;
         prev_student = student;
@@ -283,11 +323,13 @@ fn create_student_reports(
         prev_student,
         &students[students.len() - 1],
         "",
+        grades.get(&students[students.len() - 1]).map(String::as_str),
         handlebars,
     )?;
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_student_report(
     project: &Project,
     report_root: &Path,
@@ -295,6 +337,7 @@ fn create_student_report(
     prev_student: &str,
     student: &str,
     next_student: &str,
+    grade: Option<&str>,
     handlebars: &Handlebars,
 ) -> Result<()> {
     _create_student_report(
@@ -304,10 +347,12 @@ fn create_student_report(
         prev_student,
         student,
         next_student,
+        grade,
         handlebars,
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn _create_student_report(
     project: &Project,
     report_root: &Path,
@@ -315,14 +360,112 @@ fn _create_student_report(
     prev_student: &str,
     student: &str,
     next_student: &str,
+    grade: Option<&str>,
     student_template: &Handlebars<'_>,
 ) -> Result<()> {
     let student_dir = &report_root.join("students").join(student);
+    let report = student_report(project, tests, student)?;
+
+    let student_root_file = create_student_index(
+        student,
+        &report.files,
+        student_template,
+        prev_student,
+        next_student,
+        &report.test_packages,
+        &report.compile_errors,
+        grade,
+    )?;
+    fs::write(report.tmpdir.path().join("index.html"), student_root_file)?;
+    for (i, file) in report.file_paths.iter().enumerate() {
+        let student_report = create_student_report_html(
+            &report.files[i].java_path,
+            file_code(file)?,
+            &report.files,
+            &report.test_packages,
+            &report.compile_errors,
+            prev_student,
+            student,
+            next_student,
+            grade,
+            student_template,
+        )
+        .inspect_err(|e| {
+            eprintln!("Failed to create report for {}: {}", student, e);
+        })?;
+        fs::write(file, student_report)?;
+    }
+    flatten_move_recursive(report.tmpdir.path(), student_dir, None)?;
+
+    Ok(())
+}
+
+/// Renders one page of a student's report, `index.html` or a file's page as linked from it, without writing the
+/// others. `students` is the order the previous and next buttons follow. None if the student has no such page
+pub fn render_student_page(
+    project: &Project,
+    tests: &[String],
+    students: &[String],
+    student: &str,
+    page: &str,
+    grade: Option<&str>,
+    handlebars: &Handlebars,
+) -> Result<Option<String>> {
+    let Some(i) = students.iter().position(|s| s == student) else {
+        return Ok(None);
+    };
+    let prev_student = match i {
+        0 => "",
+        i => students[i - 1].as_str(),
+    };
+    let next_student = students.get(i + 1).map(String::as_str).unwrap_or("");
+    let report = student_report(project, tests, student)?;
+
+    if page == "index.html" {
+        return create_student_index(
+            student,
+            &report.files,
+            handlebars,
+            prev_student,
+            next_student,
+            &report.test_packages,
+            &report.compile_errors,
+            grade,
+        )
+        .map(Some);
+    }
+    let Some(i) = report.files.iter().position(|f| f.html_path == page) else {
+        return Ok(None);
+    };
+    create_student_report_html(
+        &report.files[i].java_path,
+        file_code(&report.file_paths[i])?,
+        &report.files,
+        &report.test_packages,
+        &report.compile_errors,
+        prev_student,
+        student,
+        next_student,
+        grade,
+        handlebars,
+    )
+    .map(Some)
+}
+
+/// Binary files are kept with the submission, but can not be shown
+fn file_code(file: &Path) -> Result<String> {
+    Ok(String::from_utf8(fs::read(file)?)
+        .unwrap_or_else(|e| format!("Binary file, {} bytes", e.as_bytes().len())))
+}
+
+/// Recreates the student's original project and gathers their results
+fn student_report(project: &Project, tests: &[String], student: &str) -> Result<StudentReport> {
     let tmpdir = tempdir()?;
-    // project.recreate_original_project(&diff_path, tmpdir.path())?;
-    project.recreate_normalized_project(&student_diff_file(student), tmpdir.path())?;
-    project.recreate_original_project(tmpdir.path(), false)?;
-    let file_paths = dir_list_absolute_file_paths_recursively(tmpdir.path());
+    let project_path = tmpdir.path().join(student);
+    project.recreate_normalized_project(student, &project_path)?;
+    // Copied since the files are rewritten into html below
+    project.recreate_original_project(student, &project_path, false)?;
+    let file_paths = dir_list_absolute_file_paths_recursively(&project_path);
 
     let mut files = Vec::new();
     file_paths.iter().for_each(|file_path| {
@@ -333,10 +476,9 @@ fn _create_student_report(
             .unwrap_or_else(|| panic!("File name should exist on {:?}", html_path));
         let html_path = html_path.to_string_lossy().to_string();
         let java_path = file_path
-            .strip_prefix(tmpdir.path())
+            .strip_prefix(&project_path)
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
+                Error::other(
                     "Could not strip tmpdir path from file path",
                 )
             })
@@ -348,6 +490,8 @@ fn _create_student_report(
         });
     });
 
+    let compile_errors = compile_errors_context(project, student, &files)?;
+
     let mut test_packages: Vec<TestPackageContext> = Vec::new();
     let test_packages_results: Vec<std::result::Result<TestResults, TestResultError>> = tests
         .iter()
@@ -358,9 +502,10 @@ fn _create_student_report(
             Ok(test_package_result) => match test_package_result.group_by_classname() {
                 None => {
                     test_packages.push(TestPackageContext {
-                        test_package_name: &test_package_result.test,
+                        test_package_name: test_package_result.test.clone(),
                         subpackages: Vec::new(),
-                        compile_error: true,
+                        compile_error: matches!(test_package_result.state, TestState::CompilationError),
+                        timeout: matches!(test_package_result.state, TestState::Timeout),
                         other_error: false,
                         not_ran: false,
                     });
@@ -387,9 +532,10 @@ fn _create_student_report(
                         });
                     }
                     test_packages.push(TestPackageContext {
-                        test_package_name: &test_package_result.test,
+                        test_package_name: test_package_result.test.clone(),
                         subpackages: test_subpackage,
                         compile_error: false,
+                        timeout: false,
                         other_error: false,
                         not_ran: false,
                     });
@@ -398,18 +544,20 @@ fn _create_student_report(
             Err(e) => match e {
                 TestResultError::IOError(_) => {
                     test_packages.push(TestPackageContext {
-                        test_package_name: &tests[i],
+                        test_package_name: tests[i].clone(),
                         subpackages: Vec::new(),
                         compile_error: false,
+                        timeout: false,
                         other_error: true,
                         not_ran: false,
                     });
                 }
                 TestResultError::TestsNotRun => {
                     test_packages.push(TestPackageContext {
-                        test_package_name: &tests[i],
+                        test_package_name: tests[i].clone(),
                         subpackages: Vec::new(),
                         compile_error: false,
+                        timeout: false,
                         other_error: false,
                         not_ran: true,
                     });
@@ -418,37 +566,16 @@ fn _create_student_report(
         };
     }
 
-    let student_root_file = create_student_index(
-        student,
-        &files,
-        student_template,
-        prev_student,
-        next_student,
-        &test_packages,
-    )?;
-    fs::write(tmpdir.path().join("index.html"), student_root_file)?;
-    for (i, file) in file_paths.iter().enumerate() {
-        let code = fs::read_to_string(file)?;
-        let student_report = create_student_report_html(
-            &files[i].java_path,
-            code,
-            &files,
-            &test_packages,
-            prev_student,
-            student,
-            next_student,
-            student_template,
-        )
-        .inspect_err(|e| {
-            eprintln!("Failed to create report for {}: {}", student, e);
-        })?;
-        fs::write(file, student_report)?;
-    }
-    flatten_move_recursive(tmpdir.path(), student_dir, None)?;
-
-    Ok(())
+    Ok(StudentReport {
+        tmpdir,
+        file_paths,
+        files,
+        test_packages,
+        compile_errors,
+    })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_student_index(
     student: &str,
     files: &Vec<StudentTemplateFile>,
@@ -456,6 +583,8 @@ fn create_student_index(
     prev_student: &str,
     next_student: &str,
     test_contexts: &Vec<TestPackageContext>,
+    compile_errors: &CompileErrorsContext,
+    grade: Option<&str>,
 ) -> Result<String> {
     handlebars
         .render(
@@ -466,9 +595,42 @@ fn create_student_index(
                 prev_student,
                 next_student,
                 test_contexts,
+                compile_errors,
+                grade,
             },
         )
-        .map_err(|e| Error::new(ErrorKind::Other, e))
+        .map_err(Error::other)
+}
+
+/// Diagnostics refer to the normalized project, the report shows the original one
+fn compile_errors_context(
+    project: &Project,
+    student: &str,
+    files: &[StudentTemplateFile],
+) -> Result<CompileErrorsContext> {
+    let Some(compile_error) = parse_compile_output(student)? else {
+        return Ok(CompileErrorsContext::default());
+    };
+    let mut diagnostics = Vec::new();
+    for diagnostic in compile_error.diagnostics {
+        let file = project.original_path(student, &diagnostic.file)?.to_string_lossy().to_string();
+        let html_path = files
+            .iter()
+            .find(|f| f.java_path == file)
+            .map(|f| f.html_path.clone())
+            .unwrap_or_default();
+        diagnostics.push(CompileDiagnosticContext {
+            file,
+            html_path,
+            line: diagnostic.line,
+            column: diagnostic.column.map(|c| c.to_string()).unwrap_or_default(),
+            message: diagnostic.message,
+        });
+    }
+    Ok(CompileErrorsContext {
+        diagnostics,
+        output: compile_error.output,
+    })
 }
 
 impl TestContext {
@@ -509,16 +671,20 @@ impl TestContext {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_student_report_html(
     file: &str,
     code: String,
     files: &Vec<StudentTemplateFile>,
     test_contexts: &Vec<TestPackageContext>,
+    compile_errors: &CompileErrorsContext,
     prev_student: &str,
     student: &str,
     next_student: &str,
+    grade: Option<&str>,
     handlebars: &Handlebars,
 ) -> Result<String> {
+    let file_diagnostics = compile_errors.diagnostics.iter().filter(|d| d.file == file).collect();
     handlebars
         .render(
             "student_template",
@@ -527,15 +693,22 @@ fn create_student_report_html(
                 files,
                 code: &code,
                 test_contexts,
+                compile_errors,
+                file_diagnostics,
                 prev_student,
                 student,
                 next_student,
+                grade,
             },
         )
-        .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))
+        .map_err(|e| Error::other(e.to_string()))
 }
 
 fn create_static_page_html(project: &Project, dest: &Path, handlebars: &Handlebars) -> Result<()> {
+    fs::write(dest, render_tests_page(project, handlebars)?)
+}
+
+pub fn render_tests_page(project: &Project, handlebars: &Handlebars) -> Result<String> {
     let mut files: Vec<PathBuf> = Vec::new();
     for item in project.diff_exclude.iter() {
         if item.is_dir() {
@@ -550,7 +723,7 @@ fn create_static_page_html(project: &Project, dest: &Path, handlebars: &Handleba
             let test_file_name = f
                 .file_name()
                 .ok_or(0)
-                .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?
+                .map_err(|e| Error::other(e.to_string()))?
                 .to_string_lossy()
                 .to_string();
             let test_file_contents = fs::read_to_string(f)?;
@@ -561,11 +734,7 @@ fn create_static_page_html(project: &Project, dest: &Path, handlebars: &Handleba
         })
         .collect();
 
-    let s = handlebars
+    handlebars
         .render("tests_template", &TestPageContext { files })
-        .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
-
-    fs::write(dest, s)?;
-
-    Ok(())
+        .map_err(|e| Error::other(e.to_string()))
 }
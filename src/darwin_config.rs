@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fs::{File, OpenOptions}, io::{ErrorKind, Write}};
+use std::{collections::HashMap, fs::{File, OpenOptions}, io::Write, path::PathBuf};
 use std::io::{Result, Error};
 
 use serde::{Serialize, Deserialize};
@@ -6,11 +6,46 @@ use serde::{Serialize, Deserialize};
 use crate::config::darwin_config;
 use strum::EnumIter;
 
+pub const DEFAULT_COMPILE_TIMEOUT_SECS: u64 = 300;
+pub const DEFAULT_TEST_TIMEOUT_SECS: u64 = 600;
+
 #[derive(Serialize, Deserialize, Debug, Clone, clap::ValueEnum, EnumIter)]
 pub enum ProjectType {
     None,
     MavenSurefire,
+    Gradle,
     Go,
+    Pytest,
+}
+
+/// How student code is isolated while compiling and running tests
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, EnumIter)]
+pub enum SandboxLevel {
+    /// Run as the current user with full access to the filesystem and network. Only for machines without user
+    /// namespaces
+    None,
+    /// No network access
+    Network,
+    /// No network access, only the project directory is writable, and the home directory is replaced by a scratch one.
+    /// Uses bubblewrap when available, otherwise user and mount namespaces
+    #[default]
+    Full,
+}
+
+/// Where the student submissions are imported from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum, EnumIter)]
+pub enum SubmissionSource {
+    /// Moodle's "Download all submissions" zip, containing a zip per student
+    #[default]
+    Moodle,
+    /// Canvas' "Download Submissions" zip
+    Canvas,
+    /// Gradescope's "Export Submissions" zip, or the directory it extracts to
+    Gradescope,
+    /// A directory with an entry per student named after them: a project directory, a zip, or a tarball
+    Directory,
+    /// A tarball laid out like a directory source
+    Tarball,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -20,21 +55,56 @@ pub struct DarwinConfig {
     pub tests: Vec<String>,
     pub tests_run: Vec<String>,
     pub extraction_errors: HashMap<String, String>,
+    /// Directory within each student's submission zip the project was found in. Used to recreate the original project structure
+    #[serde(default)]
+    pub project_roots: HashMap<String, PathBuf>,
+    /// Projects created before sandboxing existed keep running unsandboxed
+    #[serde(default = "legacy_sandbox")]
+    pub sandbox: SandboxLevel,
+    /// Seconds a student's project may spend compiling before it is killed
+    #[serde(default = "default_compile_timeout")]
+    pub compile_timeout: u64,
+    /// Seconds a single test may run before it is killed
+    #[serde(default = "default_test_timeout")]
+    pub test_timeout: u64,
+    /// Source used when adding submissions without specifying one
+    #[serde(default)]
+    pub submission_source: SubmissionSource,
+    /// Id the submission source knows each student by, eg. Moodle's participant id. Needed to export grades back
+    #[serde(default)]
+    pub student_ids: HashMap<String, String>,
+}
+
+fn legacy_sandbox() -> SandboxLevel {
+    SandboxLevel::None
+}
+
+fn default_compile_timeout() -> u64 {
+    DEFAULT_COMPILE_TIMEOUT_SECS
+}
+
+fn default_test_timeout() -> u64 {
+    DEFAULT_TEST_TIMEOUT_SECS
 }
 
 pub fn read_config() -> Result<DarwinConfig> {
     let file = OpenOptions::new().read(true).create_new(false).open(darwin_config())
-        .map_err(|e|Error::new(ErrorKind::Other, format!("Failed to open darwin config file for reading: {}", e)))?;
-    serde_json::from_reader(file).map_err(|e|Error::new(ErrorKind::Other, format!("Failed to parse darwin config: {}", e)))
+        .map_err(|e|Error::other(format!("Failed to open darwin config file for reading: {}", e)))?;
+    serde_json::from_reader(file).map_err(|e|Error::other(format!("Failed to parse darwin config: {}", e)))
 }
 
 pub fn write_config(config: DarwinConfig) -> Result<()> {
-    let mut file = File::create(darwin_config()).map_err(|e|Error::new(ErrorKind::Other, format!("Failed to create darwin_config file: {}", e)))?;
+    let mut file = File::create(darwin_config()).map_err(|e|Error::other(format!("Failed to create darwin_config file: {}", e)))?;
     serde_json::to_writer_pretty(&file, &config)?;
     file.flush()?;
     Ok(())
 }
 
+/// Projects created before roots were recorded are treated as rooted at the top of the zip
+pub fn student_project_root(student: &str) -> Result<PathBuf> {
+    Ok(read_config()?.project_roots.remove(student).unwrap_or_default())
+}
+
 pub fn list_tests() -> Vec<String> {
     read_config().unwrap().tests
 }
\ No newline at end of file
@@ -0,0 +1,543 @@
+//! Line matching that finds the same changes GNU diff does, so the diffs written are byte for byte those of `diff -u`,
+//! including the ones older projects stored. At its core is the linear space variant of Myers' algorithm, from
+//! E. W. Myers, "An O(ND) Difference Algorithm and Its Variations", Algorithmica 1 (1986), with the steps diff takes
+//! around it:
+//!
+//! * identical lines at both ends, apart from the context kept around the changes, are set aside
+//! * lines without a match in the other file, and lines with very many that sit among them, are left out of the search
+//! * the search settles for a good rather than a minimal split once it has taken too long
+//! * runs of changes are slid over identical lines, to merge them and line them up with changes in the other file
+
+use std::collections::HashMap;
+
+/// Lines deleted from the old file starting at line0, replaced by lines inserted from the new file starting at line1.
+/// Line numbers are 0 based
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    pub line0: usize,
+    pub line1: usize,
+    pub deleted: usize,
+    pub inserted: usize,
+}
+
+/// Splits a file into lines including their newline. The last line lacks one if the file does not end with a newline
+pub fn split_lines(buf: &[u8]) -> Vec<&[u8]> {
+    buf.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Changes turning old into new, for a diff showing `context` lines around them
+pub fn diff(old: &[u8], new: &[u8], context: usize) -> Vec<Change> {
+    let lines = [split_lines(old), split_lines(new)];
+    let (prefix, suffix) = identical_ends(&lines, context);
+
+    // Lines are compared by number, equal lines get the same one
+    let mut numbers: HashMap<&[u8], usize> = HashMap::new();
+    let classes = lines.map(|lines| {
+        lines[prefix..lines.len() - suffix]
+            .iter()
+            .map(|line| {
+                let next = numbers.len();
+                *numbers.entry(line).or_insert(next)
+            })
+            .collect::<Vec<usize>>()
+    });
+    let counts = classes.each_ref().map(|classes| {
+        let mut counts = vec![0; numbers.len()];
+        for &class in classes {
+            counts[class] += 1;
+        }
+        counts
+    });
+
+    let mut changed = [vec![false; classes[0].len()], vec![false; classes[1].len()]];
+    let mut searched = [Vec::new(), Vec::new()];
+    for f in 0..2 {
+        for (i, discard) in discards(&classes[f], &counts[1 - f]).into_iter().enumerate() {
+            if discard {
+                changed[f][i] = true;
+            } else {
+                searched[f].push(i);
+            }
+        }
+    }
+
+    let mut search = Search::new(
+        searched[0].iter().map(|&i| classes[0][i]).collect(),
+        searched[1].iter().map(|&i| classes[1][i]).collect(),
+    );
+    search.compare(0, search.a.len() as isize, 0, search.b.len() as isize, false);
+    for f in 0..2 {
+        for (&i, &found) in searched[f].iter().zip(&search.changed[f]) {
+            changed[f][i] |= found;
+        }
+    }
+
+    let [changed0, changed1] = &mut changed;
+    slide_runs(&classes[0], changed0, changed1);
+    slide_runs(&classes[1], changed1, changed0);
+    changes(changed0, changed1)
+        .into_iter()
+        .map(|c| Change { line0: c.line0 + prefix, line1: c.line1 + prefix, ..c })
+        .collect()
+}
+
+/// The number of lines both files start with and end with that are left out of the comparison: those they have in
+/// common, less `context` lines next to the rest. The end is only looked for after the start, so they do not overlap
+fn identical_ends(lines: &[Vec<&[u8]>; 2], context: usize) -> (usize, usize) {
+    let [lines0, lines1] = lines;
+    let start = lines0.iter().zip(lines1).take_while(|(l0, l1)| l0 == l1).count();
+    let prefix = start.saturating_sub(context);
+    let end = lines0
+        .iter()
+        .rev()
+        .zip(lines1.iter().rev())
+        .take(lines0.len().min(lines1.len()) - prefix)
+        .take_while(|(l0, l1)| l0 == l1)
+        .count();
+    (prefix, end.saturating_sub(context))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Keep,
+    Discard,
+    /// Discarded only if among enough discarded lines
+    Provisional,
+}
+
+/// Which lines of a file to leave out of the search, given how often each line occurs in the other file. A line that
+/// never does is changed for sure. One that occurs very often matches something nearly anywhere, so it is left out
+/// where it sits among such sure changes and would only scatter them
+fn discards(classes: &[usize], other_counts: &[usize]) -> Vec<bool> {
+    // Roughly 5 times the square root of the number of lines over 64
+    let mut many = 5;
+    let mut n = (classes.len() / 64) >> 2;
+    while n > 0 {
+        many *= 2;
+        n >>= 2;
+    }
+
+    let mut marks: Vec<Mark> = classes
+        .iter()
+        .map(|&class| match other_counts[class] {
+            0 => Mark::Discard,
+            count if count > many => Mark::Provisional,
+            _ => Mark::Keep,
+        })
+        .collect();
+    let mut i = 0;
+    while i < marks.len() {
+        match marks[i] {
+            Mark::Keep => i += 1,
+            // A run of discards starts with a sure one
+            Mark::Provisional => {
+                marks[i] = Mark::Keep;
+                i += 1;
+            }
+            Mark::Discard => {
+                let mut end = i + marks[i..].iter().take_while(|&&mark| mark != Mark::Keep).count();
+                // and ends with one
+                while marks[end - 1] == Mark::Provisional {
+                    end -= 1;
+                    marks[end] = Mark::Keep;
+                }
+                settle_provisional(&mut marks[i..end]);
+                i = end;
+            }
+        }
+    }
+    marks.into_iter().map(|mark| mark != Mark::Keep).collect()
+}
+
+/// Keeps the provisionally discarded lines of a run of discards that are too many, in too long a stretch, or too near
+/// its ends
+fn settle_provisional(run: &mut [Mark]) {
+    let provisional = run.iter().filter(|&&mark| mark == Mark::Provisional).count();
+    if provisional * 4 > run.len() {
+        for mark in run.iter_mut().filter(|mark| **mark == Mark::Provisional) {
+            *mark = Mark::Keep;
+        }
+        return;
+    }
+
+    // Roughly the square root of the run's length over 4, plus 1
+    let mut longest = 1;
+    let mut n = run.len() >> 4;
+    while n > 0 {
+        longest <<= 1;
+        n >>= 2;
+    }
+    longest += 1;
+    for stretch in run.split_mut(|&mark| mark != Mark::Provisional) {
+        if stretch.len() >= longest {
+            stretch.fill(Mark::Keep);
+        }
+    }
+
+    keep_provisional_near_end(run.iter_mut());
+    keep_provisional_near_end(run.iter_mut().rev());
+}
+
+/// Keeps provisional lines from the end of a run up to 3 sure discards in a row, or the first sure one from 8 lines in
+fn keep_provisional_near_end<'a>(marks: impl Iterator<Item = &'a mut Mark>) {
+    let mut in_a_row = 0;
+    for (i, mark) in marks.enumerate() {
+        if i >= 8 && *mark == Mark::Discard {
+            break;
+        }
+        match *mark {
+            Mark::Provisional => {
+                *mark = Mark::Keep;
+                in_a_row = 0;
+            }
+            Mark::Keep => in_a_row = 0,
+            Mark::Discard => in_a_row += 1,
+        }
+        if in_a_row == 3 {
+            break;
+        }
+    }
+}
+
+/// Where a search splits the lines, and whether each half has to be compared minimally
+struct Split {
+    x: isize,
+    y: isize,
+    minimal_before: bool,
+    minimal_after: bool,
+}
+
+struct Search {
+    a: Vec<usize>,
+    b: Vec<usize>,
+    /// Furthest x reached on each diagonal k = x - y searching from the start, and the least x from the end, at index
+    /// k + offset. Reused by every split
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    /// Edits searched for a split before settling for the best partial paths
+    too_expensive: isize,
+    changed: [Vec<bool>; 2],
+}
+
+impl Search {
+    fn new(a: Vec<usize>, b: Vec<usize>) -> Search {
+        let diagonals = a.len() + b.len() + 3;
+        // Roughly the square root of the number of diagonals, but at least 4096
+        let mut too_expensive = 1;
+        let mut n = diagonals;
+        while n != 0 {
+            too_expensive <<= 1;
+            n >>= 2;
+        }
+        Search {
+            forward: vec![0; diagonals],
+            backward: vec![0; diagonals],
+            offset: b.len() as isize + 1,
+            too_expensive: too_expensive.max(4096),
+            changed: [vec![false; a.len()], vec![false; b.len()]],
+            a,
+            b,
+        }
+    }
+
+    fn same(&self, x: isize, y: isize) -> bool {
+        self.a[x as usize] == self.b[y as usize]
+    }
+
+    /// Marks the lines of a[x0..x1] and b[y0..y1] that are not on the path found through them
+    fn compare(&mut self, mut x0: isize, mut x1: isize, mut y0: isize, mut y1: isize, minimal: bool) {
+        while x0 < x1 && y0 < y1 && self.same(x0, y0) {
+            x0 += 1;
+            y0 += 1;
+        }
+        while x0 < x1 && y0 < y1 && self.same(x1 - 1, y1 - 1) {
+            x1 -= 1;
+            y1 -= 1;
+        }
+        if x0 == x1 {
+            self.changed[1][y0 as usize..y1 as usize].fill(true);
+        } else if y0 == y1 {
+            self.changed[0][x0 as usize..x1 as usize].fill(true);
+        } else {
+            let split = self.split(x0, x1, y0, y1, minimal);
+            self.compare(x0, split.x, y0, split.y, split.minimal_before);
+            self.compare(split.x, x1, split.y, y1, split.minimal_after);
+        }
+    }
+
+    /// Searches from both corners at once, one more edit each round, until the paths meet, which is on a shortest
+    /// path. Unless minimal, gives up after too_expensive rounds and splits where one of the paths got furthest
+    fn split(&mut self, x0: isize, x1: isize, y0: isize, y1: isize, minimal: bool) -> Split {
+        let offset = self.offset;
+        let at = move |k: isize| (k + offset) as usize;
+        let (lowest, highest) = (x0 - y1, x1 - y0);
+        let (forward_start, backward_start) = (x0 - y0, x1 - y1);
+        let (mut forward_low, mut forward_high) = (forward_start, forward_start);
+        let (mut backward_low, mut backward_high) = (backward_start, backward_start);
+        // Whether the paths meet on a round of the forward search
+        let odd = (forward_start - backward_start) & 1 != 0;
+        self.forward[at(forward_start)] = x0;
+        self.backward[at(backward_start)] = x1;
+
+        let mut cost = 1;
+        loop {
+            // Each round reaches one more diagonal on either side, until the edge of the graph
+            if forward_low > lowest {
+                forward_low -= 1;
+                self.forward[at(forward_low - 1)] = -1;
+            } else {
+                forward_low += 1;
+            }
+            if forward_high < highest {
+                forward_high += 1;
+                self.forward[at(forward_high + 1)] = -1;
+            } else {
+                forward_high -= 1;
+            }
+            for k in (forward_low..=forward_high).rev().step_by(2) {
+                let (below, above) = (self.forward[at(k - 1)], self.forward[at(k + 1)]);
+                let mut x = if below < above { above } else { below + 1 };
+                let mut y = x - k;
+                while x < x1 && y < y1 && self.same(x, y) {
+                    x += 1;
+                    y += 1;
+                }
+                self.forward[at(k)] = x;
+                if odd && (backward_low..=backward_high).contains(&k) && self.backward[at(k)] <= x {
+                    return Split { x, y, minimal_before: true, minimal_after: true };
+                }
+            }
+
+            if backward_low > lowest {
+                backward_low -= 1;
+                self.backward[at(backward_low - 1)] = isize::MAX;
+            } else {
+                backward_low += 1;
+            }
+            if backward_high < highest {
+                backward_high += 1;
+                self.backward[at(backward_high + 1)] = isize::MAX;
+            } else {
+                backward_high -= 1;
+            }
+            for k in (backward_low..=backward_high).rev().step_by(2) {
+                let (below, above) = (self.backward[at(k - 1)], self.backward[at(k + 1)]);
+                let mut x = if below < above { below } else { above - 1 };
+                let mut y = x - k;
+                while x0 < x && y0 < y && self.same(x - 1, y - 1) {
+                    x -= 1;
+                    y -= 1;
+                }
+                self.backward[at(k)] = x;
+                if !odd && (forward_low..=forward_high).contains(&k) && x <= self.forward[at(k)] {
+                    return Split { x, y, minimal_before: true, minimal_after: true };
+                }
+            }
+
+            if !minimal && cost >= self.too_expensive {
+                return self.best_split(x0, x1, y0, y1, (forward_low, forward_high), (backward_low, backward_high));
+            }
+            cost += 1;
+        }
+    }
+
+    /// The point furthest along either search, only the part of the path leading to it being a shortest one
+    fn best_split(
+        &self,
+        x0: isize,
+        x1: isize,
+        y0: isize,
+        y1: isize,
+        (forward_low, forward_high): (isize, isize),
+        (backward_low, backward_high): (isize, isize),
+    ) -> Split {
+        let at = |k: isize| (k + self.offset) as usize;
+        // Most of x + y from the start, within the graph
+        let (mut forward_sum, mut forward_x) = (-1, 0);
+        for k in (forward_low..=forward_high).rev().step_by(2) {
+            let mut x = self.forward[at(k)].min(x1);
+            let mut y = x - k;
+            if y > y1 {
+                x = y1 + k;
+                y = y1;
+            }
+            if x + y > forward_sum {
+                (forward_sum, forward_x) = (x + y, x);
+            }
+        }
+        // Least x + y from the end
+        let (mut backward_sum, mut backward_x) = (isize::MAX, 0);
+        for k in (backward_low..=backward_high).rev().step_by(2) {
+            let mut x = self.backward[at(k)].max(x0);
+            let mut y = x - k;
+            if y < y0 {
+                x = y0 + k;
+                y = y0;
+            }
+            if x + y < backward_sum {
+                (backward_sum, backward_x) = (x + y, x);
+            }
+        }
+
+        if (x1 + y1) - backward_sum < forward_sum - (x0 + y0) {
+            Split { x: forward_x, y: forward_sum - forward_x, minimal_before: true, minimal_after: false }
+        } else {
+            Split { x: backward_x, y: backward_sum - backward_x, minimal_before: false, minimal_after: true }
+        }
+    }
+}
+
+/// Slides each run of changed lines of a file over the identical lines around it: as far up as it goes, then as far
+/// down, merging with the runs it meets, until it stops growing. It then goes back up to where it last lined up with
+/// a run of changes in the other file, if it did anywhere. The same lines are changed either way, only which copy of
+/// a repeated line is the changed one differs
+fn slide_runs(classes: &[usize], changed: &mut [bool], other: &[bool]) {
+    let is = |marks: &[bool], i: isize| usize::try_from(i).ok().and_then(|i| marks.get(i)).copied().unwrap_or(false);
+    let end = classes.len() as isize;
+    let class = |i: isize| classes[i as usize];
+    // i and j are on the same line of the file and the other one, outside of runs of changes
+    let (mut i, mut j) = (0, 0);
+    loop {
+        while i < end && !is(changed, i) {
+            while is(other, j) {
+                j += 1;
+            }
+            j += 1;
+            i += 1;
+        }
+        if i == end {
+            break;
+        }
+        let mut start = i;
+        while is(changed, i) {
+            i += 1;
+        }
+        while is(other, j) {
+            j += 1;
+        }
+
+        // Where the run ends when it last lined up with changes in the other file, end if it never did
+        let mut lined_up;
+        loop {
+            let length = i - start;
+
+            while start > 0 && class(start - 1) == class(i - 1) {
+                start -= 1;
+                changed[start as usize] = true;
+                i -= 1;
+                changed[i as usize] = false;
+                while is(changed, start - 1) {
+                    start -= 1;
+                }
+                j -= 1;
+                while is(other, j) {
+                    j -= 1;
+                }
+            }
+
+            lined_up = if is(other, j - 1) { i } else { end };
+            while i < end && class(start) == class(i) {
+                changed[start as usize] = false;
+                start += 1;
+                changed[i as usize] = true;
+                i += 1;
+                while is(changed, i) {
+                    i += 1;
+                }
+                j += 1;
+                while is(other, j) {
+                    j += 1;
+                    lined_up = i;
+                }
+            }
+
+            if i - start == length {
+                break;
+            }
+        }
+
+        while lined_up < i {
+            start -= 1;
+            changed[start as usize] = true;
+            i -= 1;
+            changed[i as usize] = false;
+            j -= 1;
+            while is(other, j) {
+                j -= 1;
+            }
+        }
+    }
+}
+
+/// Pairs up the runs of deleted and inserted lines between the lines both files keep
+fn changes(deleted: &[bool], inserted: &[bool]) -> Vec<Change> {
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < deleted.len() || j < inserted.len() {
+        if i < deleted.len() && j < inserted.len() && !deleted[i] && !inserted[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (line0, line1) = (i, j);
+        while i < deleted.len() && deleted[i] {
+            i += 1;
+        }
+        while j < inserted.len() && inserted[j] {
+            j += 1;
+        }
+        if (i, j) == (line0, line1) {
+            // Only one file has lines left, and they are all kept, which the marks never leave
+            break;
+        }
+        out.push(Change { line0, line1, deleted: i - line0, inserted: j - line1 });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, discards, identical_ends, split_lines, Change};
+
+    fn change(line0: usize, line1: usize, deleted: usize, inserted: usize) -> Change {
+        Change { line0, line1, deleted, inserted }
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff(b"a\nb\nc\n", b"a\nb\nc\n", 3), []);
+        assert_eq!(diff(b"a\nb\nc\n", b"a\nx\nc\n", 3), [change(1, 1, 1, 1)]);
+        assert_eq!(diff(b"", b"a\nb\n", 3), [change(0, 0, 0, 2)]);
+        assert_eq!(diff(b"a\nb", b"a\nb\n", 3), [change(1, 1, 1, 1)]);
+        // Myers' example, 5 edits
+        let changes = diff(b"a\nb\nc\na\nb\nb\na\n", b"c\nb\na\nb\na\nc\n", 3);
+        assert_eq!(changes.iter().map(|c| c.deleted + c.inserted).sum::<usize>(), 5);
+        // Insertions among identical lines go last
+        assert_eq!(diff(b"x\nx\ny\n", b"x\nx\nx\ny\n", 3), [change(2, 2, 0, 1)]);
+        assert_eq!(diff(b"{\n}\n{\n}\n", b"{\n}\n{\n}\n{\n}\n", 3), [change(4, 4, 0, 2)]);
+    }
+
+    #[test]
+    fn test_identical_ends() {
+        let lines = |s: &'static str| split_lines(s.as_bytes());
+        assert_eq!(identical_ends(&[lines("1\n2\n3\n4\n5\nx\n6\n7\n8\n9\n"), lines("1\n2\n3\n4\n5\ny\n6\n7\n8\n9\n")], 3), (2, 1));
+        assert_eq!(identical_ends(&[lines("1\n2\n"), lines("1\n2\n3\n")], 0), (2, 0));
+        // The end starts after the start
+        assert_eq!(identical_ends(&[lines("a\na\n"), lines("a\na\na\n")], 0), (2, 0));
+        assert_eq!(identical_ends(&[lines("a\nb"), lines("a\nb\n")], 0), (1, 0));
+    }
+
+    #[test]
+    fn test_discards() {
+        // Lines without a match in the other file, and a line with too many among them
+        let other_counts = [1, 0, 9];
+        assert_eq!(
+            discards(&[0, 1, 1, 1, 2, 1, 1, 1, 0], &other_counts),
+            [false, true, true, true, true, true, true, true, false]
+        );
+        // but not at the edge of the run
+        assert_eq!(discards(&[0, 2, 1, 1, 0], &other_counts), [false, false, true, true, false]);
+        assert_eq!(discards(&[0, 1, 2, 0], &other_counts), [false, true, false, false]);
+    }
+}
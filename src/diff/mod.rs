@@ -0,0 +1,445 @@
+//! Unified diffs of directory trees, byte for byte what `diff -ruN` prints, and a patch applier for them.
+//! Projects created with the external diff keep loading, and neither diff nor patch needs to be installed
+
+use std::{
+    ffi::OsString,
+    fs::{self, Metadata},
+    io::{ErrorKind, Result, Write},
+    mem::MaybeUninit,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
+    path::Path,
+};
+
+mod analyze;
+mod patch;
+
+pub use patch::apply_patch;
+
+use analyze::{split_lines, Change};
+
+/// Lines of context around each change, as `diff -u`
+const CONTEXT: usize = 3;
+
+/// Writes the recursive unified diff of original and deviant, treating files missing on one side as empty
+pub fn diff_trees(original: &Path, deviant: &Path, out: &mut impl Write) -> Result<()> {
+    compare(original, deviant, out)
+}
+
+fn compare(path0: &Path, path1: &Path, out: &mut impl Write) -> Result<()> {
+    let stat0 = stat(path0)?;
+    let stat1 = stat(path1)?;
+    let is_dir0 = stat0.as_ref().is_some_and(Metadata::is_dir);
+    let is_dir1 = stat1.as_ref().is_some_and(Metadata::is_dir);
+
+    match (&stat0, &stat1) {
+        (None, None) => Ok(()),
+        _ if (is_dir0 || stat0.is_none()) && (is_dir1 || stat1.is_none()) => compare_dirs(path0, path1, out),
+        (Some(s0), Some(s1)) if is_dir0 != is_dir1 || !is_file_or_dir(s0) || !is_file_or_dir(s1) => {
+            out.write_all(b"File ")?;
+            out.write_all(&quote(path0))?;
+            write!(out, " is a {} while file ", file_type(s0))?;
+            out.write_all(&quote(path1))?;
+            writeln!(out, " is a {}", file_type(s1))?;
+            Ok(())
+        }
+        _ => compare_files(path0, stat0.as_ref(), path1, stat1.as_ref(), out),
+    }
+}
+
+fn stat(path: &Path) -> Result<Option<Metadata>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_file_or_dir(metadata: &Metadata) -> bool {
+    metadata.is_file() || metadata.is_dir()
+}
+
+/// Names as in diff's messages
+fn file_type(metadata: &Metadata) -> &'static str {
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() && metadata.len() == 0 {
+        "regular empty file"
+    } else if file_type.is_file() {
+        "regular file"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_char_device() {
+        "character special file"
+    } else if file_type.is_block_device() {
+        "block special file"
+    } else {
+        "weird file"
+    }
+}
+
+/// Both listings merged in byte order, which is diff's order in the C locale
+fn compare_dirs(path0: &Path, path1: &Path, out: &mut impl Write) -> Result<()> {
+    let mut names = list_dir(path0)?;
+    names.extend(list_dir(path1)?);
+    names.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+    names.dedup();
+    for name in names {
+        compare(&path0.join(&name), &path1.join(&name), out)?;
+    }
+    Ok(())
+}
+
+fn list_dir(path: &Path) -> Result<Vec<OsString>> {
+    if !path.is_dir() {
+        return Ok(Vec::new());
+    }
+    fs::read_dir(path)?.map(|entry| entry.map(|e| e.file_name())).collect()
+}
+
+fn compare_files(
+    path0: &Path,
+    stat0: Option<&Metadata>,
+    path1: &Path,
+    stat1: Option<&Metadata>,
+    out: &mut impl Write,
+) -> Result<()> {
+    let file0 = if stat0.is_some() { fs::read(path0)? } else { Vec::new() };
+    let file1 = if stat1.is_some() { fs::read(path1)? } else { Vec::new() };
+    if file0 == file1 {
+        return Ok(());
+    }
+
+    if is_binary(&file0, stat0) || is_binary(&file1, stat1) {
+        out.write_all(b"Binary files ")?;
+        out.write_all(&quote(path0))?;
+        out.write_all(b" and ")?;
+        out.write_all(&quote(path1))?;
+        out.write_all(b" differ\n")?;
+        return Ok(());
+    }
+
+    let lines0 = split_lines(&file0);
+    let lines1 = split_lines(&file1);
+    let changes = analyze::diff(&file0, &file1, CONTEXT);
+
+    out.write_all(b"diff -ruN ")?;
+    out.write_all(&quote(path0))?;
+    out.write_all(b" ")?;
+    out.write_all(&quote(path1))?;
+    out.write_all(b"\n--- ")?;
+    out.write_all(&quote(path0))?;
+    writeln!(out, "\t{}", timestamp(stat0))?;
+    out.write_all(b"+++ ")?;
+    out.write_all(&quote(path1))?;
+    writeln!(out, "\t{}", timestamp(stat1))?;
+
+    for hunk in hunks(&changes) {
+        write_hunk(hunk, &lines0, &lines1, out)?;
+    }
+    Ok(())
+}
+
+/// diff only looks for a null byte in the first buffer it reads, which is a block
+fn is_binary(file: &[u8], metadata: Option<&Metadata>) -> bool {
+    let block_size = metadata.map(|m| m.blksize() as usize).filter(|&size| size > 0).unwrap_or(8);
+    file[..file.len().min(block_size)].contains(&0)
+}
+
+/// Groups changes whose context would overlap or touch
+fn hunks(changes: &[Change]) -> Vec<&[Change]> {
+    let mut hunks = Vec::new();
+    let mut start = 0;
+    for i in 1..=changes.len() {
+        let merge = changes.get(i).is_some_and(|next| {
+            let prev = changes[i - 1];
+            next.line0 - (prev.line0 + prev.deleted) < 2 * CONTEXT + 1
+        });
+        if !merge {
+            hunks.push(&changes[start..i]);
+            start = i;
+        }
+    }
+    hunks
+}
+
+fn write_hunk(hunk: &[Change], lines0: &[&[u8]], lines1: &[&[u8]], out: &mut impl Write) -> Result<()> {
+    let (first, last) = (hunk[0], hunk[hunk.len() - 1]);
+    let context = CONTEXT as isize;
+    let first0 = (first.line0 as isize - context).max(0);
+    let first1 = (first.line1 as isize - context).max(0);
+    let last0 = last.line0 as isize + last.deleted as isize - 1;
+    let last1 = last.line1 as isize + last.inserted as isize - 1;
+    let last0 = if last0 < lines0.len() as isize - context { last0 + context } else { lines0.len() as isize - 1 };
+    let last1 = if last1 < lines1.len() as isize - context { last1 + context } else { lines1.len() as isize - 1 };
+
+    writeln!(out, "@@ -{} +{} @@", range(first0, last0), range(first1, last1))?;
+
+    let (mut i, mut j) = (first0 as usize, first1 as usize);
+    for change in hunk {
+        while i < change.line0 {
+            write_line(b' ', lines0[i], out)?;
+            i += 1;
+            j += 1;
+        }
+        for line in &lines0[i..i + change.deleted] {
+            write_line(b'-', line, out)?;
+        }
+        for line in &lines1[j..j + change.inserted] {
+            write_line(b'+', line, out)?;
+        }
+        i += change.deleted;
+        j += change.inserted;
+    }
+    while i as isize <= last0 {
+        write_line(b' ', lines0[i], out)?;
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Ranges are 1 based. An empty range is given by the line before it
+fn range(first: isize, last: isize) -> String {
+    let (a, b) = (first + 1, last + 1);
+    if b < a {
+        format!("{},0", b)
+    } else if a == b {
+        format!("{}", a)
+    } else {
+        format!("{},{}", a, b - a + 1)
+    }
+}
+
+fn write_line(prefix: u8, line: &[u8], out: &mut impl Write) -> Result<()> {
+    out.write_all(&[prefix])?;
+    out.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        out.write_all(b"\n\\ No newline at end of file\n")?;
+    }
+    Ok(())
+}
+
+/// File names with spaces, quotes, backslashes, control or non ascii bytes are quoted as C strings
+fn quote(path: &Path) -> Vec<u8> {
+    let name = path.as_os_str().as_bytes();
+    let needs_quoting = |&b: &u8| !(0x20..0x80).contains(&b) || b == b' ' || b == b'"' || b == b'\\';
+    if !name.iter().any(needs_quoting) {
+        return name.to_vec();
+    }
+    let mut quoted = vec![b'"'];
+    for &b in name {
+        match b {
+            0x07 => quoted.extend(b"\\a"),
+            0x08 => quoted.extend(b"\\b"),
+            b'\t' => quoted.extend(b"\\t"),
+            b'\n' => quoted.extend(b"\\n"),
+            0x0b => quoted.extend(b"\\v"),
+            0x0c => quoted.extend(b"\\f"),
+            b'\r' => quoted.extend(b"\\r"),
+            b'"' => quoted.extend(b"\\\""),
+            b'\\' => quoted.extend(b"\\\\"),
+            b if !(0x20..0x80).contains(&b) => quoted.extend(format!("\\{:03o}", b).bytes()),
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(b'"');
+    quoted
+}
+
+/// Modification time in local time, eg. `2024-01-31 12:00:00.000000000 +0100`.
+/// Files missing on one side get the epoch, which is how patch knows to create or delete them
+fn timestamp(metadata: Option<&Metadata>) -> String {
+    let (secs, nsecs) = metadata.map(|m| (m.mtime(), m.mtime_nsec())).unwrap_or((0, 0));
+    let mut tm = MaybeUninit::<libc::tm>::zeroed();
+    let time = secs as libc::time_t;
+    // SAFETY: localtime_r only writes to tm, which is zero initialized and so valid even if it fails
+    let tm = unsafe {
+        libc::localtime_r(&time, tm.as_mut_ptr());
+        tm.assume_init()
+    };
+    let offset = tm.tm_gmtoff / 60;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09} {}{:02}{:02}",
+        tm.tm_year as i64 + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        nsecs,
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs, path::Path, process::Command};
+
+    use tempfile::tempdir;
+
+    use super::{apply_patch, diff_trees};
+    use crate::util::copy_dir_all;
+
+    /// xorshift, so failures reproduce
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    const LINES: [&str; 8] = ["a", "b", "c", "{", "}", "", "    int x = 1;", "    return x;"];
+
+    fn random_lines(rng: &mut Rng) -> Vec<String> {
+        let len = if rng.below(4) == 0 { rng.below(400) } else { rng.below(30) };
+        (0..len).map(|_| LINES[rng.below(LINES.len())].to_string()).collect()
+    }
+
+    fn mutate(rng: &mut Rng, lines: &[String]) -> Vec<String> {
+        let mut lines = lines.to_vec();
+        for _ in 0..rng.below(8) {
+            let at = rng.below(lines.len() + 1);
+            match rng.below(3) {
+                0 if at < lines.len() => {
+                    lines.remove(at);
+                }
+                1 if at < lines.len() => lines[at] = LINES[rng.below(LINES.len())].to_string(),
+                _ => lines.insert(at, LINES[rng.below(LINES.len())].to_string()),
+            }
+        }
+        lines
+    }
+
+    fn write_file(rng: &mut Rng, path: &Path, lines: &[String]) {
+        let mut content = lines.join("\n");
+        if !lines.is_empty() && rng.below(5) != 0 {
+            content.push('\n');
+        }
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Two trees with files on both sides, on either side only, in subdirectories, with names that need quoting
+    fn random_trees(rng: &mut Rng, a: &Path, b: &Path) {
+        let names = ["Main.java", "pom.xml", "src/Impl.java", "src/deep/Util.java", "sub dir/we\"ird\tname"];
+        for name in names {
+            let lines = random_lines(rng);
+            match rng.below(4) {
+                0 => write_file(rng, &a.join(name), &lines),
+                1 => write_file(rng, &b.join(name), &lines),
+                _ => {
+                    write_file(rng, &a.join(name), &lines);
+                    let mutated = mutate(rng, &lines);
+                    write_file(rng, &b.join(name), &mutated);
+                }
+            }
+        }
+        fs::create_dir_all(a).unwrap();
+        fs::create_dir_all(b).unwrap();
+    }
+
+    fn assert_matches_gnu_diff(a: &Path, b: &Path) {
+        let expected = Command::new("diff").arg("-ruN").arg(a).arg(b).env("LC_ALL", "C").output().unwrap().stdout;
+        let mut diff = Vec::new();
+        diff_trees(a, b, &mut diff).unwrap();
+        assert_eq!(String::from_utf8_lossy(&diff), String::from_utf8_lossy(&expected));
+    }
+
+    #[test]
+    fn test_diff_matches_gnu_diff() {
+        if Command::new("diff").arg("--version").output().is_err() {
+            return;
+        }
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        for _ in 0..200 {
+            let dir = tempdir().unwrap();
+            let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+            random_trees(&mut rng, &a, &b);
+            fs::write(a.join("binary"), b"class\0file").unwrap();
+            fs::write(b.join("binary"), b"class\0file2").unwrap();
+            assert_matches_gnu_diff(&a, &b);
+        }
+    }
+
+    /// Files with lines the other one lacks, and unrelated files long enough for diff to stop looking for the fewest
+    /// changes
+    #[test]
+    fn test_diff_matches_gnu_diff_large_files() {
+        if Command::new("diff").arg("--version").output().is_err() {
+            return;
+        }
+        let mut rng = Rng(0xD1B54A32D192ED03);
+        for (len, distinct, unique) in [(2000, 40, 10), (3000, 200, 3), (12000, 30, 0)] {
+            let dir = tempdir().unwrap();
+            let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+            let random_file = |path: &Path, rng: &mut Rng| {
+                let lines: Vec<String> = (0..len)
+                    .map(|i| match rng.below(unique.max(1) * 10) {
+                        0 if unique > 0 => format!("unique {} {}", i, rng.below(1000)),
+                        _ => format!("line {}", rng.below(distinct)),
+                    })
+                    .collect();
+                write_file(rng, path, &lines);
+            };
+            random_file(&a.join("f"), &mut rng);
+            random_file(&b.join("f"), &mut rng);
+            assert_matches_gnu_diff(&a, &b);
+
+            // and the same file with some changes
+            let lines: Vec<String> = fs::read_to_string(a.join("f")).unwrap().lines().map(str::to_string).collect();
+            let mut mutated = lines.clone();
+            for _ in 0..len / 20 {
+                mutated = mutate(&mut rng, &mutated);
+            }
+            write_file(&mut rng, &b.join("f"), &mutated);
+            assert_matches_gnu_diff(&a, &b);
+        }
+    }
+
+    #[test]
+    fn test_patch_recreates_deviant() {
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        for _ in 0..200 {
+            let dir = tempdir().unwrap();
+            let (a, b, c) = (dir.path().join("a"), dir.path().join("b"), dir.path().join("c"));
+            random_trees(&mut rng, &a, &b);
+
+            let mut diff = Vec::new();
+            diff_trees(&a, &b, &mut diff).unwrap();
+            copy_dir_all(&a, &c, Some(&HashSet::new())).unwrap();
+            let strip = a.components().count();
+            apply_patch(&diff, &c, strip).unwrap();
+
+            let mut remaining = Vec::new();
+            diff_trees(&c, &b, &mut remaining).unwrap();
+            assert_eq!(String::from_utf8_lossy(&remaining), "");
+        }
+    }
+
+    #[test]
+    fn test_patch_hunk_failure() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("f"), "1\n2\n3\n").unwrap();
+        fs::write(b.join("f"), "1\n4\n3\n").unwrap();
+        let mut diff = Vec::new();
+        diff_trees(&a, &b, &mut diff).unwrap();
+
+        fs::write(a.join("f"), "1\n5\n3\n").unwrap();
+        let err = apply_patch(&diff, &a, a.components().count()).unwrap_err();
+        assert_eq!(err.to_string(), "Hunk #1 of f does not apply");
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{Error, ErrorKind, Result},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+/// The changes diff found in a single file
+struct FilePatch {
+    old_name: Vec<u8>,
+    new_name: Vec<u8>,
+    /// diff dates files missing on one side at the epoch
+    deleted: bool,
+    hunks: Vec<Hunk>,
+}
+
+struct Hunk {
+    old_start: usize,
+    /// Lines of the old file, context and deleted
+    old: Vec<Vec<u8>>,
+    /// Lines of the new file, context and inserted
+    new: Vec<Vec<u8>>,
+}
+
+/// Applies a unified diff to the files under dest, like `patch -p{strip}` run in dest.
+/// Files are found by their old name with its first strip components removed. Files the diff deletes are removed.
+/// Binary files and files replaced by directories, which diff only mentions, are left alone
+///
+/// # Errors
+/// * the diff is malformed
+/// * a hunk does not match the file it applies to
+pub fn apply_patch(diff: &[u8], dest: &Path, strip: usize) -> Result<()> {
+    for file_patch in parse(diff)? {
+        let name = strip_components(&file_patch.old_name, strip)
+            .or_else(|| strip_components(&file_patch.new_name, strip))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Patch names a file outside the destination"))?;
+        let path = dest.join(&name);
+
+        let original = if path.is_file() { fs::read(&path)? } else { Vec::new() };
+        let patched = apply_hunks(&original, &file_patch.hunks).map_err(|hunk| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Hunk #{} of {} does not apply", hunk + 1, name.display()),
+            )
+        })?;
+
+        if patched.is_empty() && file_patch.deleted {
+            if path.is_file() {
+                fs::remove_file(&path)?;
+            }
+            remove_empty_parents(&path, dest);
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, patched)?;
+        }
+    }
+    Ok(())
+}
+
+/// The index of the first hunk that does not apply on error.
+/// Hunks are looked for near their line number first, in case lines were added or removed before them
+fn apply_hunks(original: &[u8], hunks: &[Hunk]) -> std::result::Result<Vec<u8>, usize> {
+    let lines: Vec<&[u8]> = original.split_inclusive(|&b| b == b'\n').collect();
+    let mut patched = Vec::with_capacity(original.len());
+    let mut pos = 0;
+    let mut offset: isize = 0;
+    for (i, hunk) in hunks.iter().enumerate() {
+        // An empty range starts after the line it is given by
+        let line = if hunk.old.is_empty() { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+        let expected = line as isize + offset;
+        let matches_at = |start: isize| {
+            start >= pos as isize
+                && start as usize + hunk.old.len() <= lines.len()
+                && lines[start as usize..start as usize + hunk.old.len()]
+                    .iter()
+                    .zip(&hunk.old)
+                    .all(|(line, old)| *line == old.as_slice())
+        };
+        let start = (0..=lines.len() as isize)
+            .flat_map(|distance| [expected - distance, expected + distance])
+            .find(|&start| matches_at(start))
+            .ok_or(i)? as usize;
+
+        for line in &lines[pos..start] {
+            patched.extend_from_slice(line);
+        }
+        for line in &hunk.new {
+            patched.extend_from_slice(line);
+        }
+        pos = start + hunk.old.len();
+        offset = start as isize - line as isize;
+    }
+    for line in &lines[pos..] {
+        patched.extend_from_slice(line);
+    }
+    Ok(patched)
+}
+
+/// Removes the first strip components, None if nothing is left or the rest leads out of the destination
+fn strip_components(name: &[u8], strip: usize) -> Option<PathBuf> {
+    let mut rest = name;
+    for _ in 0..strip {
+        let slash = rest.iter().position(|&b| b == b'/')?;
+        rest = &rest[slash + 1..];
+        while rest.first() == Some(&b'/') {
+            rest = &rest[1..];
+        }
+    }
+    let path = PathBuf::from(OsStr::from_bytes(rest));
+    let inside = path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    (inside && !rest.is_empty()).then_some(path)
+}
+
+/// Like patch, leaves no empty directories behind when deleting files
+fn remove_empty_parents(path: &Path, dest: &Path) {
+    let mut dir = path.parent();
+    while let Some(parent) = dir.filter(|d| d.starts_with(dest) && *d != dest) {
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+fn parse(diff: &[u8]) -> Result<Vec<FilePatch>> {
+    let malformed = |msg: &str| Error::new(ErrorKind::InvalidData, format!("Malformed diff: {}", msg));
+    let mut lines = diff.split_inclusive(|&b| b == b'\n').peekable();
+    let mut patches = Vec::new();
+
+    while let Some(line) = lines.next() {
+        // Everything else, eg. the diff command or binary files differing, is commentary
+        let Some(old_header) = line.strip_prefix(b"--- ") else {
+            continue;
+        };
+        let Some(new_header) = lines.next_if(|line| line.starts_with(b"+++ ")) else {
+            continue;
+        };
+        let (old_name, _) = parse_header(old_header).ok_or_else(|| malformed("bad --- line"))?;
+        let (new_name, new_time) = parse_header(&new_header[4..]).ok_or_else(|| malformed("bad +++ line"))?;
+
+        let mut hunks = Vec::new();
+        while let Some(header) = lines.next_if(|line| line.starts_with(b"@@ -")) {
+            let (old_start, mut old_len, mut new_len) =
+                parse_hunk_header(header).ok_or_else(|| malformed("bad @@ line"))?;
+            let mut hunk = Hunk { old_start, old: Vec::new(), new: Vec::new() };
+            while old_len > 0 || new_len > 0 {
+                let line = lines.next().ok_or_else(|| malformed("hunk ends early"))?;
+                // Blank context lines may have lost their space
+                let (kind, content) = match line {
+                    b"\n" => (b' ', line),
+                    _ => (line[0], &line[1..]),
+                };
+                let in_old = kind == b' ' || kind == b'-';
+                let in_new = kind == b' ' || kind == b'+';
+                if (!in_old && !in_new) || (in_old && old_len == 0) || (in_new && new_len == 0) {
+                    return Err(malformed("hunk does not match its line counts"));
+                }
+                // The line the marker follows lacks the newline diff added
+                let no_newline = lines.next_if(|line| line.starts_with(b"\\")).is_some();
+                let content = match no_newline {
+                    true => content.strip_suffix(b"\n").unwrap_or(content),
+                    false => content,
+                };
+                if in_old {
+                    hunk.old.push(content.to_vec());
+                    old_len -= 1;
+                }
+                if in_new {
+                    hunk.new.push(content.to_vec());
+                    new_len -= 1;
+                }
+            }
+            hunks.push(hunk);
+        }
+
+        patches.push(FilePatch { old_name, new_name, deleted: new_time.is_some_and(is_epoch), hunks });
+    }
+    Ok(patches)
+}
+
+/// File name, unquoted, and timestamp of a --- or +++ line
+fn parse_header(header: &[u8]) -> Option<(Vec<u8>, Option<&[u8]>)> {
+    let header = header.strip_suffix(b"\n").unwrap_or(header);
+    if header.first() == Some(&b'"') {
+        let (name, len) = unquote(header)?;
+        let time = header[len..].strip_prefix(b"\t");
+        return Some((name, time));
+    }
+    match header.iter().position(|&b| b == b'\t') {
+        Some(tab) => Some((header[..tab].to_vec(), Some(&header[tab + 1..]))),
+        None => Some((header.to_vec(), None)),
+    }
+}
+
+/// Reverses diff's C string quoting of file names. Returns the name and the length of the quoted string
+fn unquote(quoted: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut name = Vec::new();
+    let mut i = 1;
+    loop {
+        match *quoted.get(i)? {
+            b'"' => return Some((name, i + 1)),
+            b'\\' => {
+                let escaped = *quoted.get(i + 1)?;
+                i += 2;
+                name.push(match escaped {
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'v' => 0x0b,
+                    b'f' => 0x0c,
+                    b'r' => b'\r',
+                    b'0'..=b'7' => {
+                        let digits = quoted.get(i - 1..i + 2)?;
+                        i += 1;
+                        digits.iter().try_fold(0u8, |n, &d| match d {
+                            b'0'..=b'7' => Some(n.wrapping_mul(8) + (d - b'0')),
+                            _ => None,
+                        })?
+                    }
+                    other => other,
+                });
+            }
+            b => {
+                name.push(b);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// (old start, old length, new length) of `@@ -a,b +c,d @@`, where a length of 1 may be left out
+fn parse_hunk_header(header: &[u8]) -> Option<(usize, usize, usize)> {
+    let header = std::str::from_utf8(header).ok()?;
+    let mut ranges = header.strip_prefix("@@ -")?.split(" @@").next()?.split(" +");
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = parse_range(ranges.next()?)?;
+    let (_, new_len) = parse_range(ranges.next()?)?;
+    Some((old_start, old_len, new_len))
+}
+
+/// Whether a `2024-01-31 12:00:00.000000000 +0100` timestamp is the epoch in its time zone
+fn is_epoch(time: &[u8]) -> bool {
+    let Ok(time) = std::str::from_utf8(time) else {
+        return false;
+    };
+    let parse = || -> Option<i64> {
+        let mut parts = time.split_whitespace();
+        let mut date = parts.next()?.splitn(3, '-').map(str::parse::<i64>);
+        let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+        let clock = parts.next()?;
+        let (clock, fraction) = clock.split_once('.').unwrap_or((clock, "0"));
+        let mut clock = clock.splitn(3, ':').map(str::parse::<i64>);
+        let (hour, minute, second) = (clock.next()?.ok()?, clock.next()?.ok()?, clock.next()?.ok()?);
+        if fraction.bytes().any(|b| b != b'0') {
+            return Some(1);
+        }
+        let zone = parts.next().unwrap_or("+0000");
+        let (sign, zone) = zone.split_at(1);
+        let zone: i64 = zone.parse().ok()?;
+        let zone = (zone / 100 * 60 + zone % 100) * 60 * if sign == "-" { -1 } else { 1 };
+        Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - zone)
+    };
+    parse() == Some(0)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -5,7 +5,7 @@ use std::{
 };
 
 use crate::{
-    darwin_config, list_students::list_students, project_runner::Project, types::TestResultError, view_student_results::parse_test_results
+    darwin_config, list_students::list_students, project_runner::Project, types::{TestResultError, TestState}, view_student_results::parse_test_results
 };
 
 pub fn download_results_summary(project: &Project, outfile: File, test: &str) -> Result<()> {
@@ -23,11 +23,13 @@ pub fn download_results_summary(project: &Project, outfile: File, test: &str) ->
     for student in list_students() {
         let mut cur_row = vec![String::new(); headers.len()];
         cur_row[0] = student.clone();
-        match parse_test_results(&project, &student, test) {
+        match parse_test_results(project, &student, test) {
             Ok(res) => {
                 let summary = res.summary();
                 if summary.0 {
                     cur_row[1] = String::from("Compile Error")
+                } else if matches!(res.state, TestState::Timeout) {
+                    cur_row[1] = String::from("Timeout")
                 } else {
                     cur_row[2] = format!("{}", summary.1);
                     cur_row[3] = format!("{}", summary.2);
@@ -76,6 +78,9 @@ pub fn download_results_by_classname(project: &Project, out_file: &Path, test: &
                             }
                         }
                     }
+                    None if matches!(res.state, TestState::Timeout) => {
+                        cur_row[1] = String::from("Timeout");
+                    }
                     None => {
                         cur_row[1] = String::from("Compilation Error");
                     }
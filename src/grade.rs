@@ -0,0 +1,307 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Error, ErrorKind, Result},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::rubric_file,
+    darwin_config,
+    project_runner::Project,
+    types::{StatusMsg, TestResultError, TestState},
+    view_student_results::parse_test_results,
+};
+
+/// Assigns points to passing tests. Stored at .darwin/rubric.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rubric {
+    pub items: Vec<RubricItem>,
+    /// Caps the total score
+    #[serde(default)]
+    pub max: Option<f64>,
+    #[serde(default)]
+    pub compile_error: CompileErrorPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RubricItem {
+    /// Class of the test results, eg. `test.test1.MathTests`
+    pub class: String,
+    /// A single test method of class. Takes the method out of any item for the whole class
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Points for the method, or for every passing method of the class
+    pub points: f64,
+    /// Caps the points this item awards
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+/// Score of a student whose project does not compile
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum CompileErrorPolicy {
+    #[default]
+    Zero,
+    /// A fixed number of points
+    Points(f64),
+    /// No score, the student is graded by hand
+    Manual,
+}
+
+/// Test class and method of a test result
+type TestMethod = (String, String);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Grade {
+    pub student: String,
+    /// None when the student has to be graded by hand
+    pub score: Option<f64>,
+    pub possible: f64,
+    pub compile_error: bool,
+    /// Points awarded by each rubric item, in rubric order
+    pub items: Vec<f64>,
+}
+
+impl Display for Grade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.score {
+            Some(score) if self.compile_error => write!(f, "{}/{} (Compile Error)", score, self.possible),
+            Some(score) => write!(f, "{}/{}", score, self.possible),
+            None => write!(f, "Compile Error, grade by hand"),
+        }
+    }
+}
+
+impl RubricItem {
+    pub fn name(&self) -> String {
+        match &self.method {
+            Some(method) => format!("{}#{}", self.class, method),
+            None => self.class.clone(),
+        }
+    }
+}
+
+impl Rubric {
+    fn validate(&self) -> Result<()> {
+        if self.items.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "rubric has no items"));
+        }
+        for item in &self.items {
+            if item.class.is_empty() || !item.points.is_finite() || item.max.is_some_and(|max| !max.is_finite()) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("rubric item {} needs a class and finite points", item.name()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_method_item(&self, method: &TestMethod) -> bool {
+        self.items.iter().any(|item| item.class == method.0 && item.method.as_ref() == Some(&method.1))
+    }
+
+    /// Methods a class item applies to, ie. those without an item of their own
+    fn class_item_methods<'a>(&'a self, item: &'a RubricItem, methods: impl Iterator<Item = &'a TestMethod> + 'a) -> impl Iterator<Item = &'a TestMethod> + 'a {
+        methods.filter(move |method| method.0 == item.class && !self.is_method_item(method))
+    }
+
+    /// Points available, given every test method seen across all students
+    fn possible(&self, all_methods: &HashSet<TestMethod>) -> f64 {
+        let total = self.items.iter().map(|item| {
+            let points = match &item.method {
+                Some(_) => item.points,
+                None => item.points * self.class_item_methods(item, all_methods.iter()).count() as f64,
+            };
+            cap(points, item.max)
+        }).sum();
+        cap(total, self.max)
+    }
+
+    /// passed maps each test method the student ran to whether it passed
+    fn grade(&self, student: &str, passed: &HashMap<TestMethod, bool>, compile_error: bool, possible: f64) -> Grade {
+        if compile_error {
+            let score = match self.compile_error {
+                CompileErrorPolicy::Zero => Some(0.0),
+                CompileErrorPolicy::Points(points) => Some(points),
+                CompileErrorPolicy::Manual => None,
+            };
+            return Grade { student: student.to_string(), score, possible, compile_error, items: vec![0.0; self.items.len()] };
+        }
+
+        let passing = passed.iter().filter(|(_, passed)| **passed).map(|(method, _)| method);
+        let items: Vec<f64> = self.items.iter().map(|item| {
+            let points = match &item.method {
+                Some(method) => {
+                    if passed.get(&(item.class.clone(), method.clone())) == Some(&true) { item.points } else { 0.0 }
+                }
+                None => item.points * self.class_item_methods(item, passing.clone()).count() as f64,
+            };
+            cap(points, item.max)
+        }).collect();
+        let score = cap(items.iter().sum(), self.max);
+        Grade { student: student.to_string(), score: Some(score), possible, compile_error, items }
+    }
+}
+
+fn cap(points: f64, max: Option<f64>) -> f64 {
+    max.map_or(points, |max| points.min(max))
+}
+
+pub fn read_rubric() -> Result<Option<Rubric>> {
+    let path = rubric_file();
+    if !path.is_file() {
+        return Ok(None);
+    }
+    parse_rubric(&path).map(Some)
+}
+
+fn parse_rubric(path: &Path) -> Result<Rubric> {
+    let file = File::open(path)?;
+    let rubric: Rubric = serde_json::from_reader(file)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Failed to parse rubric: {}", e)))?;
+    rubric.validate()?;
+    Ok(rubric)
+}
+
+/// Validates the rubric at path and stores it in the project
+pub fn set_rubric(path: &Path) -> Result<()> {
+    let rubric = parse_rubric(path)?;
+    let file = File::create(rubric_file())?;
+    serde_json::to_writer_pretty(file, &rubric)?;
+    Ok(())
+}
+
+/// Scores each student across every test that has been run for them
+pub fn grade_students(project: &Project, rubric: &Rubric, students: &[String]) -> Result<Vec<Grade>> {
+    let tests = darwin_config::list_tests();
+    let mut all_methods: HashSet<TestMethod> = HashSet::new();
+    let mut student_results = Vec::new();
+    for student in students {
+        let mut passed: HashMap<TestMethod, bool> = HashMap::new();
+        let mut compile_error = false;
+        for test in &tests {
+            match parse_test_results(project, student, test) {
+                Ok(results) => match results.state {
+                    TestState::CompilationError => compile_error = true,
+                    // Nothing passed
+                    TestState::Timeout => {}
+                    TestState::Ok { results } => {
+                        for result in results {
+                            let is_passing = result.msg == StatusMsg::None;
+                            // A method run by several tests, eg. a suite and the class itself, has to pass in all of them
+                            passed.entry((result.classname, result.name))
+                                .and_modify(|p| *p &= is_passing)
+                                .or_insert(is_passing);
+                        }
+                    }
+                },
+                Err(TestResultError::TestsNotRun) => {}
+                Err(TestResultError::IOError(e)) => return Err(e),
+            }
+        }
+        all_methods.extend(passed.keys().cloned());
+        student_results.push((student, passed, compile_error));
+    }
+
+    let possible = rubric.possible(&all_methods);
+    Ok(student_results
+        .into_iter()
+        .map(|(student, passed, compile_error)| rubric.grade(student, &passed, compile_error, possible))
+        .collect())
+}
+
+pub fn download_grades(rubric: &Rubric, grades: &[Grade], outfile: File) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(BufWriter::new(outfile));
+    let mut headers = vec![
+        String::from("Name"),
+        String::from("Score"),
+        String::from("Possible"),
+        String::from("Compile Error"),
+    ];
+    headers.extend(rubric.items.iter().map(RubricItem::name));
+    wtr.write_record(&headers)?;
+
+    for grade in grades {
+        let mut row = vec![
+            grade.student.clone(),
+            grade.score.map(|s| s.to_string()).unwrap_or_default(),
+            grade.possible.to_string(),
+            grade.compile_error.to_string(),
+        ];
+        row.extend(grade.items.iter().map(f64::to_string));
+        wtr.write_record(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{CompileErrorPolicy, Rubric, RubricItem, TestMethod};
+
+    fn method(class: &str, name: &str) -> TestMethod {
+        (class.to_string(), name.to_string())
+    }
+
+    fn rubric() -> Rubric {
+        Rubric {
+            items: vec![
+                RubricItem { class: String::from("MathTests"), method: None, points: 2.0, max: Some(3.0) },
+                RubricItem { class: String::from("MathTests"), method: Some(String::from("testDivide")), points: 5.0, max: None },
+                RubricItem { class: String::from("AlgoTests"), method: None, points: 1.0, max: None },
+            ],
+            max: None,
+            compile_error: CompileErrorPolicy::Points(1.0),
+        }
+    }
+
+    #[test]
+    fn test_grade() {
+        let rubric = rubric();
+        let passed = HashMap::from([
+            (method("MathTests", "testAdd"), true),
+            (method("MathTests", "testSub"), true),
+            (method("MathTests", "testDivide"), true),
+            (method("AlgoTests", "testSort"), true),
+            (method("AlgoTests", "testSearch"), false),
+        ]);
+        let all_methods: HashSet<TestMethod> = passed.keys().cloned().collect();
+        let possible = rubric.possible(&all_methods);
+        // MathTests capped at 3, testDivide, both AlgoTests
+        assert_eq!(possible, 10.0);
+
+        let grade = rubric.grade("alice", &passed, false, possible);
+        assert_eq!(grade.items, vec![3.0, 5.0, 1.0]);
+        assert_eq!(grade.score, Some(9.0));
+
+        let grade = rubric.grade("bob", &HashMap::new(), true, possible);
+        assert_eq!(grade.score, Some(1.0));
+        assert_eq!(grade.to_string(), "1/10 (Compile Error)");
+
+        let rubric = Rubric { max: Some(8.0), compile_error: CompileErrorPolicy::Manual, ..rubric };
+        assert_eq!(rubric.grade("alice", &passed, false, possible).score, Some(8.0));
+        assert_eq!(rubric.grade("bob", &HashMap::new(), true, possible).score, None);
+    }
+
+    #[test]
+    fn test_parse_rubric() {
+        let rubric: Rubric = serde_json::from_str(r#"{
+            "items": [
+                { "class": "test.test1.MathTests", "points": 2, "max": 10 },
+                { "class": "test.test1.MathTests", "method": "testAdd", "points": 5 }
+            ],
+            "compile_error": { "Points": 5 }
+        }"#).unwrap();
+        assert_eq!(rubric.items.len(), 2);
+        assert_eq!(rubric.items[1].name(), "test.test1.MathTests#testAdd");
+        assert_eq!(rubric.compile_error, CompileErrorPolicy::Points(5.0));
+        assert!(rubric.validate().is_ok());
+    }
+}
@@ -1,9 +1,12 @@
 use camino::Utf8PathBuf;
 use clap::{Parser, Subcommand};
 use config::darwin_root;
-use darwin_config::{read_config, ProjectType};
+use darwin_config::{read_config, ProjectType, SandboxLevel, SubmissionSource, DEFAULT_COMPILE_TIMEOUT_SECS, DEFAULT_TEST_TIMEOUT_SECS};
+use output::OutputFormat;
+use plagiarism_checker::{PlagiarismAlgorithm, PlagiarismCheckOptions};
 use project_runner::{no_project, project_type_to_project};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{collections::HashSet, fs};
 
 mod anonomize;
@@ -22,6 +25,15 @@ mod view_student_results;
 mod view_student_submission;
 mod project_runner;
 mod darwin_config;
+mod submission_source;
+mod grade;
+mod moodle_export;
+mod diff;
+mod submission_blobs;
+mod output;
+mod result_store;
+mod progress;
+mod serve;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -34,6 +46,9 @@ struct Cli {
     /// Name of the person to greet
     #[command(subcommand)]
     command: SubCommand,
+    /// How the list and view commands print, json is for scripts
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
 }
 
 #[derive(Debug, Subcommand)]
@@ -42,15 +57,53 @@ enum SubCommand {
     CreateProject {
         project_type: ProjectType,
         project_skeleton: Utf8PathBuf,
-        moodle_submissions_zipfile: Utf8PathBuf,
+        /// Moodle submissions zip, or whatever --source expects
+        submissions: Utf8PathBuf,
+        /// Where the submissions were downloaded from
+        #[arg(long, value_enum, default_value_t = SubmissionSource::Moodle)]
+        source: SubmissionSource,
+        /// How student code is isolated while compiling and running tests. `none` turns isolation off
+        #[arg(long, value_enum, default_value_t = SandboxLevel::Full)]
+        sandbox: SandboxLevel,
+        /// Seconds a student's project may spend compiling before it is killed
+        #[arg(long, default_value_t = DEFAULT_COMPILE_TIMEOUT_SECS)]
+        compile_timeout: u64,
+        /// Seconds a single test may run before it is killed
+        #[arg(long, default_value_t = DEFAULT_TEST_TIMEOUT_SECS)]
+        test_timeout: u64,
     },
     DeleteProject,
+    /// Add or replace the submissions in a Moodle submissions zip, or another source. Only the updated students have to be tested again
+    AddSubmissions {
+        submissions: Utf8PathBuf,
+        /// Where the submissions were downloaded from. Defaults to the project's source
+        #[arg(long, value_enum)]
+        source: Option<SubmissionSource>,
+    },
+    /// Add or replace a single student's submission zip, tarball or directory
+    AddStudentSubmission {
+        student: String,
+        submission: Utf8PathBuf,
+    },
     Auto {
         project_skeleton: Utf8PathBuf,
         moodle_submissions_zipfile: Utf8PathBuf,
     },
     ListStudents,
     ListTests,
+    SetSandbox {
+        sandbox: SandboxLevel,
+    },
+    /// Resolve the skeleton's dependencies into .darwin once, so student builds run offline against them. Maven only
+    CacheDependencies,
+    SetTimeouts {
+        /// Seconds a student's project may spend compiling before it is killed
+        #[arg(long)]
+        compile_timeout: Option<u64>,
+        /// Seconds a single test may run before it is killed
+        #[arg(long)]
+        test_timeout: Option<u64>,
+    },
     ViewStudentSubmission {
         student: String,
     },
@@ -58,10 +111,35 @@ enum SubCommand {
         student: String,
         tests: String, // Must be comma separated list of tests
     },
+    /// Run a comma separated list of tests on every student. Each student's project is built once for all of them
     TestAll {
         tests: String,
         num_threads: Option<usize>,
     },
+    /// Run the students whose test runs were interrupted, eg. by Ctrl-C, or had not started yet
+    Resume {
+        num_threads: Option<usize>,
+    },
+    /// Run the students whose test runs ended without a result again, eg. because the build tool crashed
+    RetryFailed {
+        /// Only retry this test's runs
+        test: Option<String>,
+        #[arg(long)]
+        num_threads: Option<usize>,
+    },
+    /// Serve the report pages and a json API to read results and queue test runs. There is no authentication, so
+    /// only bind to a trusted network
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+        /// A host name the server is reached by besides its address and localhost, eg. the lab machine's when bound to
+        /// 0.0.0.0. Requests naming any other host are refused. Can be repeated
+        #[arg(long = "allow-host")]
+        allowed_hosts: Vec<String>,
+        /// Threads each queued test run uses
+        #[arg(long)]
+        num_threads: Option<usize>,
+    },
     ViewStudentResultSummary {
         student: String,
         test: String,
@@ -93,12 +171,63 @@ enum SubCommand {
         parts: u8,
         tests: Vec<String>,
     },
+    /// Store the rubric used by grade and the report. See README for its format
+    SetRubric {
+        rubric: Utf8PathBuf,
+    },
+    /// Score every student using the rubric, across all tests run
+    Grade {
+        /// Also write the scores to a csv file
+        #[arg(long)]
+        outfile: Option<Utf8PathBuf>,
+    },
+    /// Write Moodle's offline grading worksheet and a feedback files zip of each student's report, for upload to Moodle.
+    /// Students are matched by the participant ids recorded by add-submissions, which older projects do not have
+    ExportMoodleGrades {
+        worksheet: Utf8PathBuf,
+        feedback_zip: Utf8PathBuf,
+        /// Tests summarized in the feedback and report. Defaults to all tests
+        tests: Vec<String>,
+    },
     PlagiarismCheck {
         dest_path: Utf8PathBuf,
+        /// Defaults to winnowing for Maven and Gradle projects and TLSH for the others
+        #[arg(long, value_enum)]
+        algorithm: Option<PlagiarismAlgorithm>,
+        /// An earlier term's .darwin folder, which is only read, or a directory with a subdirectory per solution.
+        /// Each student's closest submission in the archives is listed separately. Can be repeated
+        #[arg(long = "archive")]
+        archives: Vec<Utf8PathBuf>,
+        /// Pairs of students at most this far apart are listed as suspicious. Winnowing distances go from 0, identical,
+        /// to 1. Defaults to 0.5 for winnowing and 100 for TLSH
+        #[arg(long)]
+        threshold: Option<f64>,
+        /// Only list the closest N suspicious pairs
+        #[arg(long)]
+        top: Option<usize>,
+        /// Also write the suspicious pairs to a .csv file, or a .json file with the clusters
+        #[arg(long)]
+        pairs: Option<Utf8PathBuf>,
+        /// Link each student on the map to their page in this create-report output
+        #[arg(long)]
+        report: Option<Utf8PathBuf>,
+        /// Write a side by side comparison of each suspicious pair into this directory, linked from the map
+        #[arg(long)]
+        pair_reports: Option<Utf8PathBuf>,
     },
+    /// Compare two students. Winnowing lists the code that matches
     PlagiarismCheckStudents {
         student1: String,
         student2: String,
+        /// Defaults to winnowing for Maven and Gradle projects and TLSH for the others
+        #[arg(long, value_enum)]
+        algorithm: Option<PlagiarismAlgorithm>,
+    },
+    /// Write an html page comparing two students' code side by side, with the code that matches highlighted
+    PlagiarismReportStudents {
+        student1: String,
+        student2: String,
+        dest_path: Utf8PathBuf,
     },
     Anonomize,
     Clean,
@@ -115,6 +244,7 @@ fn main() {
     let cli = Cli::parse();
 
     let command = cli.command;
+    let format = cli.format;
     if matches!(command, SubCommand::CreateProject { .. })
         || matches!(command, SubCommand::Auto { .. })
         || matches!(command, SubCommand::ListProjectTypes)
@@ -125,9 +255,16 @@ fn main() {
     }
     
     let project = match command {
-        SubCommand::CreateProject { ref project_type, .. } => project_type_to_project(project_type),
+        SubCommand::CreateProject { ref project_type, sandbox, compile_timeout, test_timeout, .. } => project_type_to_project(project_type)
+            .map(|p| p.with_sandbox(sandbox).with_timeouts(Duration::from_secs(compile_timeout), Duration::from_secs(test_timeout))),
         SubCommand::ListProjectTypes => no_project(),
-        _ => project_type_to_project(&read_config().unwrap().project_type)
+        _ => {
+            let config = read_config().unwrap();
+            project_type_to_project(&config.project_type).map(|p| {
+                p.with_sandbox(config.sandbox)
+                    .with_timeouts(Duration::from_secs(config.compile_timeout), Duration::from_secs(config.test_timeout))
+            })
+        }
     };
 
     if let Err(e) = project {
@@ -138,23 +275,31 @@ fn main() {
 
     match command {
         SubCommand::ListProjectTypes => {
-            commands::list_project_types();
+            commands::list_project_types(format);
         }
         SubCommand::CreateProject {
             project_skeleton,
-            moodle_submissions_zipfile,
+            submissions,
+            source,
             ..
         } => {
             commands::create_darwin(
                 &project,
                 project_skeleton.as_std_path(),
-                moodle_submissions_zipfile.as_std_path(),
+                source,
+                submissions.as_std_path(),
                 &copy_ignore_set,
             );
         }
         SubCommand::DeleteProject => {
             fs::remove_dir_all(darwin_root()).unwrap();
         }
+        SubCommand::AddSubmissions { submissions, source } => {
+            commands::add_submissions(&project, source, submissions.as_std_path(), &copy_ignore_set);
+        }
+        SubCommand::AddStudentSubmission { student, submission } => {
+            commands::add_student_submission(&project, &student, submission.as_std_path(), &copy_ignore_set);
+        }
         SubCommand::Auto {
             project_skeleton,
             moodle_submissions_zipfile,
@@ -167,10 +312,19 @@ fn main() {
             );
         }
         SubCommand::ListTests => {
-            commands::list_tests(&project);
+            commands::list_tests(&project, format);
         }
         SubCommand::ListStudents => {
-            commands::list_students();
+            commands::list_students(format);
+        }
+        SubCommand::SetSandbox { sandbox } => {
+            commands::set_sandbox(sandbox);
+        }
+        SubCommand::CacheDependencies => {
+            commands::cache_dependencies(&project);
+        }
+        SubCommand::SetTimeouts { compile_timeout, test_timeout } => {
+            commands::set_timeouts(compile_timeout, test_timeout);
         }
         SubCommand::TestStudent { student, tests } => {
             commands::run_test_for_student(&project, student.as_str(), tests.as_str());
@@ -178,20 +332,29 @@ fn main() {
         SubCommand::TestAll { tests, num_threads } => {
             commands::run_tests(&project, tests.as_str(), num_threads.unwrap_or(1))
         }
+        SubCommand::Resume { num_threads } => {
+            commands::resume(&project, num_threads.unwrap_or(1));
+        }
+        SubCommand::RetryFailed { test, num_threads } => {
+            commands::retry_failed(&project, test.as_deref(), num_threads.unwrap_or(1));
+        }
+        SubCommand::Serve { address, allowed_hosts, num_threads } => {
+            commands::serve(&project, address.as_str(), &allowed_hosts, num_threads.unwrap_or(1));
+        }
         SubCommand::ViewStudentResultSummary { student, test } => {
-            commands::view_student_result(&project, &student, &test, &commands::ViewMode::Summarize);
+            commands::view_student_result(&project, &student, &test, &commands::ViewMode::Summarize, format);
         }
         SubCommand::ViewStudentResultByClassName { student, test } => {
-            commands::view_student_result(&project, &student, &test, &commands::ViewMode::ClassName);
+            commands::view_student_result(&project, &student, &test, &commands::ViewMode::ClassName, format);
         }
         SubCommand::ViewStudentResultsVerbose { student, test } => {
-            commands::view_student_result(&project, &student, &test, &commands::ViewMode::Everything);
+            commands::view_student_result(&project, &student, &test, &commands::ViewMode::Everything, format);
         }
         SubCommand::ViewAllStudentsResultsSummary { test } => {
-            commands::view_all_results(&project, test.as_str(), &commands::ViewMode::Summarize);
+            commands::view_all_results(&project, test.as_str(), &commands::ViewMode::Summarize, format);
         }
         SubCommand::ViewAllStudentsResultsByClassName { test } => {
-            commands::view_all_results(&project, test.as_str(), &commands::ViewMode::ClassName);
+            commands::view_all_results(&project, test.as_str(), &commands::ViewMode::ClassName, format);
         }
         SubCommand::DownloadResultsSummary { test, outfile } => {
             commands::download_results_summary(&project, test.as_str(), outfile.as_str());
@@ -209,11 +372,33 @@ fn main() {
         } => {
             commands::create_report(&project, dest_path.as_std_path(), parts, &tests);
         }
-        SubCommand::PlagiarismCheck { dest_path } => {
-            commands::plagiarism_check(dest_path.as_std_path());
+        SubCommand::SetRubric { rubric } => {
+            commands::set_rubric(rubric.as_std_path());
+        }
+        SubCommand::Grade { outfile } => {
+            commands::grade(&project, outfile.as_ref().map(|p| p.as_std_path()), format);
+        }
+        SubCommand::ExportMoodleGrades { worksheet, feedback_zip, tests } => {
+            commands::export_moodle_grades(&project, worksheet.as_std_path(), feedback_zip.as_std_path(), &tests);
+        }
+        SubCommand::PlagiarismCheck { dest_path, algorithm, archives, threshold, top, pairs, report, pair_reports } => {
+            let archives: Vec<PathBuf> = archives.into_iter().map(Utf8PathBuf::into_std_path_buf).collect();
+            let options = PlagiarismCheckOptions {
+                algorithm: algorithm.unwrap_or(PlagiarismAlgorithm::for_project_type(&project.project_type)),
+                archives: &archives,
+                threshold,
+                top,
+                report: report.as_ref().map(|p| p.as_std_path()),
+                pair_reports: pair_reports.as_ref().map(|p| p.as_std_path()),
+            };
+            commands::plagiarism_check(&project, dest_path.as_std_path(), &options, pairs.as_ref().map(|p| p.as_std_path()));
+        }
+        SubCommand::PlagiarismCheckStudents { student1, student2, algorithm } => {
+            let algorithm = algorithm.unwrap_or(PlagiarismAlgorithm::for_project_type(&project.project_type));
+            commands::plagiarism_check_students(&project, student1, student2, algorithm, format);
         }
-        SubCommand::PlagiarismCheckStudents { student1, student2 } => {
-            commands::plagiarism_check_students(student1, student2);
+        SubCommand::PlagiarismReportStudents { student1, student2, dest_path } => {
+            commands::plagiarism_report_students(&project, student1, student2, dest_path.as_std_path());
         }
         SubCommand::Anonomize => {
             commands::anonomize(&project);
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{copy, BufWriter, Error, ErrorKind, Result, Write},
+    path::{Path, PathBuf},
+};
+
+use handlebars::html_escape;
+use tempfile::tempdir;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    create_report::create_report,
+    darwin_config::read_config,
+    grade::{grade_students, read_rubric, Grade},
+    list_students::list_students,
+    project_runner::Project,
+    types::{StatusMsg, TestState},
+    util::zip_add_dir,
+    view_student_results::{parse_compile_output, parse_test_results},
+};
+
+/// Compile errors listed in the feedback, the rest are in the report
+const MAX_FEEDBACK_DIAGNOSTICS: usize = 5;
+
+/// Writes Moodle's offline grading worksheet with every student's rubric score and a summary of their results as
+/// feedback, and a zip of each student's report in the layout of Moodle's "upload multiple feedback files in a zip".
+/// Students are identified by the participant id recorded when their Moodle submission was imported.
+/// Returns the students left out for lacking one, which are all of them in projects created before ids were recorded
+pub fn export_moodle_grades(project: &Project, worksheet: &Path, feedback_zip: &Path, tests: &[String]) -> Result<Vec<String>> {
+    let rubric = read_rubric()?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "No rubric. Set one with set-rubric first"))?;
+    let participant_ids = read_config()?.student_ids;
+    let students = list_students();
+    let grades = grade_students(project, &rubric, &students)?;
+
+    let (graded, missing): (Vec<&Grade>, Vec<&Grade>) =
+        grades.iter().partition(|grade| participant_ids.contains_key(&grade.student));
+
+    let feedback = graded.iter().map(|grade| feedback_comment(project, grade, tests)).collect::<Result<Vec<String>>>()?;
+    write_worksheet(worksheet, &graded, &participant_ids, &feedback)?;
+
+    let report = tempdir()?;
+    let report_root = report.path().join("report");
+    create_report(project, &report_root, &tests.to_vec(), 1)?;
+    write_feedback_zip(&report_root, feedback_zip, &graded, &participant_ids)?;
+
+    Ok(missing.into_iter().map(|grade| grade.student.clone()).collect())
+}
+
+/// `feedback` holds each grade's feedback comment
+fn write_worksheet(
+    worksheet: &Path,
+    grades: &[&Grade],
+    participant_ids: &HashMap<String, String>,
+    feedback: &[String],
+) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(BufWriter::new(File::create(worksheet)?));
+    // Moodle matches rows by Identifier and only reads the grade and feedback columns
+    wtr.write_record(["Identifier", "Full name", "Grade", "Maximum Grade", "Feedback comments"])?;
+    for (grade, feedback) in grades.iter().zip(feedback) {
+        wtr.write_record([
+            format!("Participant {}", participant_ids[&grade.student]),
+            grade.student.clone(),
+            // Left empty for students graded by hand, so moodle keeps their grade
+            grade.score.map(|score| format!("{:.2}", score)).unwrap_or_default(),
+            format!("{:.2}", grade.possible),
+            feedback.clone(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Score and a line per test, as html since moodle shows feedback comments as such
+fn feedback_comment(project: &Project, grade: &Grade, tests: &[String]) -> Result<String> {
+    let mut lines = vec![format!("Score: {}", grade)];
+    if grade.compile_error {
+        lines.push(String::from("Your project did not compile"));
+        if let Some(compile_error) = parse_compile_output(&grade.student)? {
+            lines.extend(compile_error.diagnostics.iter().take(MAX_FEEDBACK_DIAGNOSTICS).map(|d| d.to_string()));
+        }
+    } else {
+        for test in tests {
+            // Tests that were not run are left out
+            let Ok(results) = parse_test_results(project, &grade.student, test) else {
+                continue;
+            };
+            match results.state {
+                TestState::CompilationError => {}
+                TestState::Timeout => lines.push(format!("{}: Timed out", test)),
+                TestState::Ok { results } => {
+                    let failed: Vec<&str> = results
+                        .iter()
+                        .filter(|r| r.msg != StatusMsg::None)
+                        .map(|r| r.name.as_str())
+                        .collect();
+                    let mut line = format!("{}: {}/{} passed", test, results.len() - failed.len(), results.len());
+                    if !failed.is_empty() {
+                        line.push_str(&format!(", failed {}", failed.join(", ")));
+                    }
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    Ok(lines.iter().map(|line| format!("<p>{}</p>", html_escape(line))).collect())
+}
+
+/// Moodle assigns each top level `{full name}_{participant id}_assignsubmission_file_` folder to that participant
+fn write_feedback_zip(
+    report_root: &Path,
+    feedback_zip: &Path,
+    grades: &[&Grade],
+    participant_ids: &HashMap<String, String>,
+) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(feedback_zip)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let styles = report_root.join("styles");
+    for grade in grades {
+        let student = &grade.student;
+        let prefix = PathBuf::from(format!("{}_{}_assignsubmission_file_", student, participant_ids[student]));
+        // Same layout as the report, so the pages find their styles
+        zip_add_dir(&mut zip, &report_root.join("students").join(student), &prefix.join("students").join(student), options)?;
+        for entry in fs::read_dir(&styles)? {
+            let path = entry?.path();
+            // Fonts would make up most of the upload, for every student
+            if path.extension().is_some_and(|ext| ext == "ttf") {
+                continue;
+            }
+            let name = prefix.join("styles").join(path.file_name().unwrap());
+            zip.start_file(name.to_string_lossy(), options)?;
+            copy(&mut File::open(&path)?, &mut zip)?;
+        }
+    }
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+
+    use tempfile::tempdir;
+    use zip::ZipArchive;
+
+    use crate::grade::Grade;
+
+    use super::{write_feedback_zip, write_worksheet};
+
+    fn grade(student: &str, score: Option<f64>) -> Grade {
+        Grade { student: student.to_string(), score, possible: 10.0, compile_error: score.is_none(), items: Vec::new() }
+    }
+
+    #[test]
+    fn test_write_worksheet() {
+        let dir = tempdir().unwrap();
+        let worksheet = dir.path().join("worksheet.csv");
+        let (alice, bob) = (grade("Alice", Some(7.5)), grade("Bob", None));
+        let participant_ids = HashMap::from([(String::from("Alice"), String::from("42")), (String::from("Bob"), String::from("7"))]);
+        let feedback = [String::from("<p>Score: 7.5/10</p>"), String::from("<p>Your project did not compile</p>")];
+
+        write_worksheet(&worksheet, &[&alice, &bob], &participant_ids, &feedback).unwrap();
+        assert_eq!(
+            fs::read_to_string(&worksheet).unwrap(),
+            "Identifier,Full name,Grade,Maximum Grade,Feedback comments
+Participant 42,Alice,7.50,10.00,<p>Score: 7.5/10</p>
+Participant 7,Bob,,10.00,<p>Your project did not compile</p>
+"
+        );
+    }
+
+    #[test]
+    fn test_write_feedback_zip() {
+        let dir = tempdir().unwrap();
+        let report_root = dir.path().join("report");
+        fs::create_dir_all(report_root.join("students").join("Alice")).unwrap();
+        fs::create_dir_all(report_root.join("styles")).unwrap();
+        fs::write(report_root.join("students").join("Alice").join("index.html"), "<html></html>").unwrap();
+        fs::write(report_root.join("styles").join("style.css"), "body {}").unwrap();
+        fs::write(report_root.join("styles").join("font.ttf"), "").unwrap();
+        let feedback_zip = dir.path().join("feedback.zip");
+        let participant_ids = HashMap::from([(String::from("Alice"), String::from("42"))]);
+
+        write_feedback_zip(&report_root, &feedback_zip, &[&grade("Alice", Some(7.5))], &participant_ids).unwrap();
+        let zip = ZipArchive::new(File::open(&feedback_zip).unwrap()).unwrap();
+        let mut names: Vec<&str> = zip.file_names().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "Alice_42_assignsubmission_file_/students/Alice/index.html",
+                "Alice_42_assignsubmission_file_/styles/style.css",
+            ]
+        );
+    }
+}
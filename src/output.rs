@@ -0,0 +1,80 @@
+//! How the read-only commands print what they found: readable text, an aligned table, or json for scripts
+
+use std::io::{stdout, Write};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable output
+    #[default]
+    Text,
+    /// Columns aligned under a header
+    Table,
+    /// Pretty printed json. See README for each command's schema
+    Json,
+}
+
+pub fn print_json<T: Serialize + ?Sized>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(s) => write_stdout(&format!("{}\n", s)),
+        Err(e) => eprintln!("Failed to write json: {}", e),
+    }
+}
+
+/// On stderr, or as `{"error": message}` on stdout in json, so a script always has an object to parse
+pub fn print_error(message: &str, format: OutputFormat) {
+    match format {
+        OutputFormat::Json => print_json(&serde_json::json!({ "error": message })),
+        _ => eprintln!("{}", message),
+    }
+}
+
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    write_stdout(&format_table(header, rows));
+}
+
+/// Unlike print!, does not panic when a script stops reading, eg. `| head`
+fn write_stdout(s: &str) {
+    let _ = stdout().lock().write_all(s.as_bytes());
+}
+
+/// Left aligned columns separated by two spaces, with the header underlined
+fn format_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: &[String]| -> String {
+        let line: Vec<String> = cells.iter().zip(&widths).map(|(cell, &width)| format!("{:<width$}", cell)).collect();
+        format!("{}\n", line.join("  ").trim_end())
+    };
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    let underline: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    let mut out = format_row(&header);
+    out.push_str(&format_row(&underline));
+    for row in rows {
+        out.push_str(&format_row(row));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_table;
+
+    #[test]
+    fn test_format_table() {
+        let rows = vec![
+            vec!["Alice".to_string(), "10".to_string()],
+            vec!["Christopher".to_string(), "7".to_string()],
+        ];
+        assert_eq!(
+            format_table(&["Student", "Correct"], &rows),
+            "Student      Correct\n-----------  -------\nAlice        10\nChristopher  7\n"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Submissions from earlier terms, which the current students are compared against. An archive is either the
+//! .darwin folder of an earlier term's project, which is only read, or a directory of solutions with one
+//! subdirectory per solution
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+use tempfile::tempdir;
+
+use super::{java_document, skeleton_hashes, winnowing::Document};
+use crate::{
+    config::{darwin_config, darwin_root, diff_dir, in_darwin_root, skel_dir, student_diff_file},
+    submission_blobs::restore_blobs,
+    util::patch,
+};
+
+/// A submission of an earlier term
+pub struct ArchivedSubmission {
+    /// `archive/student`, where archive is the name of the archive's directory
+    pub name: String,
+    pub hashes: HashSet<u64>,
+}
+
+/// Every submission in the archive, with `skeleton` and the archive's own skeleton left out of their fingerprints.
+/// Submissions that can not be read are skipped
+pub fn archived_submissions(archive: &Path, skeleton: &HashSet<u64>) -> Result<Vec<ArchivedSubmission>> {
+    if !archive.is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Archive {} is not a directory", archive.display()),
+        ));
+    }
+    // The project directory of an earlier term works as well as its .darwin folder
+    let archive = &if archive.join(darwin_root()).is_dir() { archive.join(darwin_root()) } else { archive.to_path_buf() };
+    let label = archive_label(archive)?;
+
+    let is_darwin_project = in_darwin_root(archive, &darwin_config()).is_file();
+    let mut excluded = skeleton.clone();
+    if is_darwin_project {
+        excluded.extend(skeleton_hashes(archive)?);
+    }
+
+    let mut submissions = Vec::new();
+    for (student, path) in archive_entries(archive, is_darwin_project)? {
+        let document = if is_darwin_project {
+            archived_student_document(archive, &student)
+        } else {
+            java_document(&path)
+        };
+        match document {
+            Ok(mut document) => {
+                document.exclude(&excluded);
+                submissions.push(ArchivedSubmission { name: format!("{}/{}", label, student), hashes: document.hashes() });
+            }
+            Err(e) => eprintln!("Failed to fingerprint {}/{}: {}", label, student, e),
+        }
+    }
+    Ok(submissions)
+}
+
+/// The directory's name, or for a .darwin folder the name of the directory it is in
+fn archive_label(archive: &Path) -> Result<String> {
+    let archive = archive.canonicalize()?;
+    let name = match archive.file_name() {
+        Some(name) if name == ".darwin" => archive.parent().and_then(|parent| parent.file_name()),
+        name => name,
+    };
+    Ok(name.map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| archive.display().to_string()))
+}
+
+/// Name and path of each submission, sorted. The students of an archived project, or the solutions of a directory
+fn archive_entries(archive: &Path, is_darwin_project: bool) -> Result<Vec<(String, PathBuf)>> {
+    let dir = if is_darwin_project { in_darwin_root(archive, &diff_dir()) } else { archive.to_path_buf() };
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let is_entry = if is_darwin_project { entry.file_type()?.is_file() } else { entry.file_type()?.is_dir() };
+        if is_entry {
+            entries.push((entry.file_name().to_string_lossy().to_string(), entry.path()));
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Rebuilds the student's project from the archive's skeleton and their diff, like recreate_normalized_project
+fn archived_student_document(archive: &Path, student: &str) -> Result<Document> {
+    let tmpdir = tempdir()?;
+    let project_path = tmpdir.path().join(student);
+    patch(&in_darwin_root(archive, &skel_dir()), &in_darwin_root(archive, &student_diff_file(student)), &project_path)?;
+    restore_blobs(archive, student, &project_path)?;
+    java_document(&project_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::archived_submissions;
+    use crate::plagiarism_checker::java_document;
+
+    const SKELETON: &str = "class Stack {\n    int[] items = new int[16];\n    int size;\n    void push(int item) { items[size++] = item; }\n}\n";
+    const SOLUTION: &str = "class Queue {\n    int pop() { int first = items[0]; for (int i = 1; i < size; i++) items[i - 1] = items[i]; size--; return first; }\n}\n";
+
+    #[test]
+    fn test_archived_solutions() {
+        let corpus = tempdir().unwrap();
+        let solutions = corpus.path().join("fall");
+        for (solution, source) in [("given", SKELETON.to_string()), ("finished", format!("{}{}", SKELETON, SOLUTION))] {
+            fs::create_dir_all(solutions.join(solution).join("src")).unwrap();
+            fs::write(solutions.join(solution).join("src").join("Stack.java"), source).unwrap();
+        }
+        // Not a solution
+        fs::write(solutions.join("README.md"), "Solutions").unwrap();
+
+        let skeleton = java_document(&solutions.join("given")).unwrap().hashes();
+        let submissions = archived_submissions(&solutions, &skeleton).unwrap();
+        let names: Vec<&str> = submissions.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["fall/finished", "fall/given"]);
+        // Only the code the skeleton does not have is left
+        assert!(!submissions[0].hashes.is_empty());
+        assert!(submissions[0].hashes.is_disjoint(&skeleton));
+        assert!(submissions[1].hashes.is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+/// A token of Java source. Identifiers and literals are normalized, so renaming variables or changing constants does
+/// not change the tokens, and neither does formatting or comments
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    /// Keywords and operators as written, `$id` for identifiers and `$lit` for literals
+    pub text: String,
+    /// 1 based
+    pub line: usize,
+}
+
+const IDENTIFIER: &str = "$id";
+const LITERAL: &str = "$lit";
+
+const KEYWORDS: [&str; 53] = [
+    "abstract", "assert", "boolean", "break", "byte", "case", "catch", "char", "class", "const", "continue", "default",
+    "do", "double", "else", "enum", "extends", "final", "finally", "float", "for", "goto", "if", "implements",
+    "instanceof", "int", "interface", "long", "native", "new", "private", "protected", "public",
+    "return", "short", "static", "strictfp", "super", "switch", "synchronized", "this", "throw", "throws",
+    "transient", "try", "void", "volatile", "while", "var", "record", "yield", "sealed", "permits",
+];
+
+const LITERAL_KEYWORDS: [&str; 3] = ["true", "false", "null"];
+
+/// Longest first, so the longest operator matches
+const OPERATORS: [&str; 25] = [
+    ">>>=", "<<=", ">>=", ">>>", "...", "->", "::", "++", "--", "&&", "||", "==", "!=", "<=", ">=", "+=", "-=", "*=",
+    "/=", "%=", "&=", "|=", "^=", "<<", ">>",
+];
+
+/// Tokens of a Java source file. Package and import statements are left out, being the same in every submission
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut rest = source;
+    // Set after package or import, until the statement's semicolon
+    let mut skipping = false;
+
+    while let Some(c) = rest.chars().next() {
+        let start_line = line;
+        let len = if c.is_whitespace() {
+            c.len_utf8()
+        } else if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            comment.find("*/").map(|end| end + 4).unwrap_or(rest.len())
+        } else if rest.starts_with("\"\"\"") {
+            push(&mut tokens, skipping, LITERAL, start_line);
+            quoted_len(rest, "\"\"\"")
+        } else if c == '"' || c == '\'' {
+            push(&mut tokens, skipping, LITERAL, start_line);
+            quoted_len(rest, &rest[..1])
+        } else if c.is_ascii_digit() || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            push(&mut tokens, skipping, LITERAL, start_line);
+            number_len(rest)
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            if word == "package" || word == "import" {
+                skipping = true;
+            } else if KEYWORDS.contains(&word) {
+                push(&mut tokens, skipping, word, start_line);
+            } else if LITERAL_KEYWORDS.contains(&word) {
+                push(&mut tokens, skipping, LITERAL, start_line);
+            } else {
+                push(&mut tokens, skipping, IDENTIFIER, start_line);
+            }
+            len
+        } else {
+            let operator = OPERATORS.iter().find(|op| rest.starts_with(**op)).copied().unwrap_or(&rest[..c.len_utf8()]);
+            if skipping && operator == ";" {
+                skipping = false;
+            } else {
+                push(&mut tokens, skipping, operator, start_line);
+            }
+            operator.len()
+        };
+        line += rest[..len].matches('\n').count();
+        rest = &rest[len..];
+    }
+    tokens
+}
+
+fn push(tokens: &mut Vec<Token>, skipping: bool, text: &str, line: usize) {
+    if !skipping {
+        tokens.push(Token { text: text.to_string(), line });
+    }
+}
+
+/// Length of a string, char or text block literal, up to and including its closing quote.
+/// Unterminated strings and chars end at the end of the line
+fn quoted_len(rest: &str, quote: &str) -> usize {
+    let mut chars = rest.char_indices().skip(quote.chars().count());
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if rest[i..].starts_with(quote) {
+            return i + quote.len();
+        } else if c == '\n' && quote.len() == 1 {
+            return i;
+        }
+    }
+    rest.len()
+}
+
+/// Decimal, hex, octal and binary literals, with underscores, exponents and type suffixes
+fn number_len(rest: &str) -> usize {
+    let bytes = rest.as_bytes();
+    let hex = rest.starts_with("0x") || rest.starts_with("0X");
+    let is_exponent = |b: u8| if hex { b == b'p' || b == b'P' } else { b == b'e' || b == b'E' };
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let exponent_sign = (b == b'+' || b == b'-') && i > 0 && is_exponent(bytes[i - 1]);
+        if b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || exponent_sign {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokenize;
+
+    fn texts(source: &str) -> Vec<String> {
+        tokenize(source).into_iter().map(|t| t.text).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = texts("package a.b;\nimport java.util.*;\nclass A { int x = 0x1F + 1.5e-3; String s = \"a\\\"b\"; }");
+        assert_eq!(
+            tokens,
+            ["class", "$id", "{", "int", "$id", "=", "$lit", "+", "$lit", ";", "$id", "$id", "=", "$lit", ";", "}"]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_ignores_names_formatting_and_comments() {
+        let original = "int sum(int[] xs) {\n    int total = 0;\n    for (int x : xs) total += x;\n    return total;\n}\n";
+        let disguised = "/** Adds */\nint add(int[] values){int acc=0; // running\nfor(int v:values)\n  acc+=v;\nreturn acc;}";
+        assert_eq!(texts(original), texts(disguised));
+    }
+
+    #[test]
+    fn test_tokenize_lines() {
+        let tokens = tokenize("/* a\n b */ x\n\"\"\"\ntext\n\"\"\" y >>>= 1;");
+        let lines: Vec<usize> = tokens.iter().map(|t| t.line).collect();
+        assert_eq!(lines, [2, 3, 5, 5, 5, 5]);
+    }
+}
@@ -0,0 +1,141 @@
+//! The interactive map of how close students are to each other, with links to their reports
+
+use std::{
+    fs,
+    io::{Error, Result},
+    path::{Component, Path, PathBuf},
+};
+
+use handlebars::Handlebars;
+use serde::Serialize;
+
+use super::PlagiarismAlgorithm;
+use crate::create_report::student_report_page;
+
+/// Everything shown on the map
+pub struct PlagiarismMap<'a> {
+    pub algorithm: PlagiarismAlgorithm,
+    /// Initial position of the edge slider
+    pub threshold: f64,
+    pub students: &'a [String],
+    /// Between 0 and 100
+    pub xs: &'a [f64],
+    pub ys: &'a [f64],
+    pub distance_matrix: &'a [Vec<f64>],
+    /// Whether each student could be compared
+    pub compared: &'a [bool],
+    /// create_report output the students link to
+    pub report: Option<&'a Path>,
+    /// Pairwise comparison page of students i and j, with i < j
+    pub pair_pages: &'a [(usize, usize, PathBuf)],
+}
+
+#[derive(Serialize)]
+struct PlagiarismMapContext {
+    /// MapData as json, read by the page's script
+    data: String,
+}
+
+#[derive(Serialize)]
+struct MapData<'a> {
+    algorithm: PlagiarismAlgorithm,
+    threshold: f64,
+    students: Vec<MapStudent<'a>>,
+    /// None for pairs with a student who could not be compared
+    distances: Vec<Vec<Option<f64>>>,
+    pair_pages: Vec<MapPairPage>,
+}
+
+#[derive(Serialize)]
+struct MapStudent<'a> {
+    name: &'a str,
+    x: f64,
+    y: f64,
+    /// Link to the student's report page
+    report: Option<String>,
+}
+
+#[derive(Serialize)]
+struct MapPairPage {
+    student1: usize,
+    student2: usize,
+    href: String,
+}
+
+pub fn create_plagiarism_map(dest_path: &Path, map: &PlagiarismMap) -> Result<()> {
+    // Links are relative to the page, so the page, reports and comparisons can be moved together
+    let page_dir = dest_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")).canonicalize()?;
+    let href = |target: &Path| -> Result<String> { Ok(relative_href(&page_dir, &target.canonicalize()?)) };
+
+    let mut students = Vec::new();
+    for (i, name) in map.students.iter().enumerate() {
+        let report = match map.report.and_then(|report| student_report_page(report, name)) {
+            Some(page) => Some(href(&page)?),
+            None => None,
+        };
+        students.push(MapStudent { name, x: map.xs[i], y: map.ys[i], report });
+    }
+    let distances = map
+        .distance_matrix
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(j, &distance)| (map.compared[i] && map.compared[j]).then_some(distance))
+                .collect()
+        })
+        .collect();
+    let mut pair_pages = Vec::new();
+    for (student1, student2, page) in map.pair_pages {
+        pair_pages.push(MapPairPage { student1: *student1, student2: *student2, href: href(page)? });
+    }
+
+    let data = MapData { algorithm: map.algorithm, threshold: map.threshold, students, distances, pair_pages };
+    // Inside a script element, which a student named </script> must not end
+    let data = serde_json::to_string(&data)?.replace("</", "<\\/");
+    let s = Handlebars::new()
+        .render_template(include_str!("../../template/plagiarism.hbs"), &PlagiarismMapContext { data })
+        .map_err(|e| Error::other(e.to_string()))?;
+    fs::write(dest_path, s)?;
+
+    Ok(())
+}
+
+/// Link from a page in `from_dir` to `to`, both absolute
+fn relative_href(from_dir: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(to[common..].iter().map(|c| percent_encode(&c.as_os_str().to_string_lossy())));
+    parts.join("/")
+}
+
+/// Escapes what would end or change the meaning of a path in a link
+fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            ' ' | '"' | '#' | '%' | '?' | '<' | '>' | '\\' => out.push_str(&format!("%{:02X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::relative_href;
+
+    #[test]
+    fn test_relative_href() {
+        assert_eq!(relative_href(Path::new("/a/b"), Path::new("/a/b/pairs/x.html")), "pairs/x.html");
+        assert_eq!(
+            relative_href(Path::new("/a/b"), Path::new("/a/report/students/Jo Smith#2/index.html")),
+            "../report/students/Jo%20Smith%232/index.html"
+        );
+    }
+}
@@ -0,0 +1,556 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tempfile::tempdir;
+use tlsh_fixed::{
+    BucketKind::Bucket128, ChecksumKind::OneByte, Tlsh, TlshBuilder, Version::Version4,
+};
+
+use crate::config::{darwin_root, diff_exclude_dir, in_darwin_root, skel_dir, student_diff_file};
+use crate::darwin_config::ProjectType;
+use crate::list_students::list_students;
+use crate::project_runner::Project;
+use crate::util::{buffer_flatmap, is_student};
+use archive::archived_submissions;
+use java_tokens::tokenize;
+use map::{create_plagiarism_map, PlagiarismMap};
+use pairs::rank_pairs;
+use pairwise_report::create_pairwise_report;
+use pcoa::apply_pcoa;
+use pcoa::nalgebra::DMatrix;
+use winnowing::{containment, matches, similarity, Document, Match, SourceFile};
+
+mod archive;
+mod java_tokens;
+mod map;
+mod pairs;
+mod pairwise_report;
+mod winnowing;
+
+pub use pairs::{pairs_format, write_pairs, Cluster, SuspiciousPair};
+
+/// How students' submissions are compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PlagiarismAlgorithm {
+    /// Fingerprints of each student's Java source with identifiers and literals normalized, so renaming, reordering
+    /// and reformatting do not hide copied code. Finds the code that matches
+    #[default]
+    Winnowing,
+    /// Locality sensitive hash of each student's diff
+    Tlsh,
+}
+
+impl PlagiarismAlgorithm {
+    /// The algorithm used when none is given. Winnowing only reads Java source, so other projects are compared by TLSH
+    pub fn for_project_type(project_type: &ProjectType) -> Self {
+        match project_type {
+            ProjectType::None | ProjectType::MavenSurefire | ProjectType::Gradle => PlagiarismAlgorithm::Winnowing,
+            ProjectType::Go | ProjectType::Pytest => PlagiarismAlgorithm::Tlsh,
+        }
+    }
+
+    /// Distance at which a pair of students is suspicious, when none is given. Winnowing distances are 1 minus the
+    /// similarity, so this is half of a student's code being found in the other's. TLSH distances are unbounded, with
+    /// renamed copies well below 100
+    pub fn default_threshold(self) -> f64 {
+        match self {
+            PlagiarismAlgorithm::Winnowing => 0.5,
+            PlagiarismAlgorithm::Tlsh => 100.0,
+        }
+    }
+}
+
+/// What plagiarism_check finds besides the map
+pub struct PlagiarismCheckResult {
+    pub threshold: f64,
+    /// Closest first
+    pub pairs: Vec<SuspiciousPair>,
+    /// Largest first
+    pub clusters: Vec<Cluster>,
+    /// Most similar first
+    pub archive_matches: Vec<ArchiveMatch>,
+}
+
+/// The submission of an earlier term most similar to a current student's
+pub struct ArchiveMatch {
+    pub student: String,
+    /// `archive/student`
+    pub archived: String,
+    pub similarity: f64,
+}
+
+/// How plagiarism_check compares students and what it writes besides the map
+#[derive(Default)]
+pub struct PlagiarismCheckOptions<'a> {
+    pub algorithm: PlagiarismAlgorithm,
+    /// Each student is also compared against the submissions in these, always by winnowing
+    pub archives: &'a [PathBuf],
+    /// Pairs of students at most this far apart are suspicious. Defaults to the algorithm's default threshold
+    pub threshold: Option<f64>,
+    /// Only the closest pairs are kept
+    pub top: Option<usize>,
+    /// A create_report output, whose student pages the map links to
+    pub report: Option<&'a Path>,
+    /// Directory to write a pairwise comparison page of each suspicious pair into, which the map links to
+    pub pair_reports: Option<&'a Path>,
+}
+
+/// Writes the plagiarism map of the current students to dest_path, and ranks the pairs of students that are
+/// suspiciously close
+pub fn plagiarism_check(
+    project: &Project,
+    dest_path: &Path,
+    options: &PlagiarismCheckOptions,
+) -> Result<PlagiarismCheckResult> {
+    if dest_path.exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            "Dest path should not exist",
+        ));
+    }
+    if let Some(report) = options.report {
+        if !report.is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Report {} does not exist, create it with create-report first", report.display()),
+            ));
+        }
+    }
+
+    _plagiarism_check(project, dest_path, options)
+}
+
+fn _plagiarism_check(
+    project: &Project,
+    dest_path: &Path,
+    options: &PlagiarismCheckOptions,
+) -> Result<PlagiarismCheckResult> {
+    let algorithm = options.algorithm;
+    let threshold = options.threshold.unwrap_or(algorithm.default_threshold());
+    let students = list_students();
+    let skeleton = skeleton_hashes(&darwin_root())?;
+    let documents = if algorithm == PlagiarismAlgorithm::Winnowing
+        || !options.archives.is_empty()
+        || options.pair_reports.is_some()
+    {
+        create_student_documents(project, &students, &skeleton)
+    } else {
+        Vec::new()
+    };
+    if algorithm == PlagiarismAlgorithm::Winnowing && !documents.iter().flatten().any(|document| !document.files.is_empty()) {
+        return Err(no_java_source("no student has any"));
+    }
+    let fingerprints: Vec<Option<HashSet<u64>>> =
+        documents.iter().map(|document| document.as_ref().map(Document::hashes)).collect();
+
+    // Students who could not be compared are as far from everyone as can be on the map, but are no pair's evidence
+    let (distance_matrix, compared): (Vec<Vec<f64>>, Vec<bool>) = match algorithm {
+        PlagiarismAlgorithm::Winnowing => {
+            (create_winnowing_distance_matrix(&fingerprints), fingerprints.iter().map(Option::is_some).collect())
+        }
+        PlagiarismAlgorithm::Tlsh => {
+            let hashes = create_student_hashes(&students)?;
+            let compared = hashes.iter().map(Option::is_some).collect();
+            (create_distance_matrix(hashes), compared)
+        }
+    };
+    let (pairs, clusters) = rank_pairs(&students, &distance_matrix, &compared, threshold, options.top);
+
+    let mut pair_pages = Vec::new();
+    if let Some(pair_reports) = options.pair_reports {
+        fs::create_dir_all(pair_reports)?;
+        for pair in &pairs {
+            let i = students.iter().position(|s| *s == pair.student1).unwrap_or_default();
+            let j = students.iter().position(|s| *s == pair.student2).unwrap_or_default();
+            let (Some(document1), Some(document2)) = (&documents[i], &documents[j]) else {
+                continue;
+            };
+            let page = pair_reports.join(pair_page_name(&pair.student1, &pair.student2));
+            let comparison = compare_documents(document1.clone(), document2.clone());
+            create_pairwise_report(project, &comparison, &pair.student1, &pair.student2, &page)?;
+            pair_pages.push((i, j, page));
+        }
+    }
+
+    // Use Multidimensional scaling to visualize similarities between students
+    // https://en.wikipedia.org/wiki/Multidimensional_scaling
+    let (mut xs, mut ys) = multidimensional_scaling(&distance_matrix);
+    normalize_vector(&mut xs, 100.0);
+    normalize_vector(&mut ys, 100.0);
+    let map = PlagiarismMap {
+        algorithm,
+        threshold,
+        students: &students,
+        xs: &xs,
+        ys: &ys,
+        distance_matrix: &distance_matrix,
+        compared: &compared,
+        report: options.report,
+        pair_pages: &pair_pages,
+    };
+    create_plagiarism_map(dest_path, &map)?;
+
+    Ok(PlagiarismCheckResult {
+        threshold,
+        pairs,
+        clusters,
+        archive_matches: compare_with_archives(&students, &fingerprints, options.archives, &skeleton)?,
+    })
+}
+
+/// File name of the pairwise comparison page of two students
+fn pair_page_name(student1: &str, student2: &str) -> String {
+    format!("{}_{}.html", student1, student2)
+}
+
+fn compare_with_archives(
+    students: &[String],
+    fingerprints: &[Option<HashSet<u64>>],
+    archives: &[PathBuf],
+    skeleton: &HashSet<u64>,
+) -> Result<Vec<ArchiveMatch>> {
+    let mut archived = Vec::new();
+    for archive in archives {
+        archived.extend(archived_submissions(archive, skeleton)?);
+    }
+
+    let mut archive_matches = Vec::new();
+    for (student, hashes) in students.iter().zip(fingerprints) {
+        let Some(hashes) = hashes else {
+            continue;
+        };
+        let closest = archived
+            .iter()
+            .map(|submission| (submission, similarity(hashes, &submission.hashes)))
+            .filter(|(_, similarity)| *similarity > 0.0)
+            // The first of equally similar submissions
+            .reduce(|closest, next| if next.1 > closest.1 { next } else { closest });
+        if let Some((submission, similarity)) = closest {
+            archive_matches.push(ArchiveMatch {
+                student: student.clone(),
+                archived: submission.name.clone(),
+                similarity,
+            });
+        }
+    }
+    archive_matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    Ok(archive_matches)
+}
+
+fn create_student_hashes(students: &[String]) -> Result<Vec<Option<Tlsh>>> {
+    let mut student_hashes = Vec::new();
+    for student in students {
+        let diff_path = student_diff_file(student);
+        let student_hash = process_file(&diff_path)
+            .inspect_err(|_| eprintln!("Failed to compute hash for {}", student)).ok();
+        student_hashes.push(student_hash);
+    }
+    Ok(student_hashes)
+}
+
+fn create_distance_matrix(hashes: Vec<Option<Tlsh>>) -> Vec<Vec<f64>> {
+    let mut out: Vec<Vec<f64>> = vec![vec![0.0; hashes.len()]; hashes.len()];
+    for i in 0..hashes.len() - 1 {
+        for j in i + 1..hashes.len() {
+            if let Some(h1) = &hashes[i] {
+                if let Some(h2) = &hashes[j] {
+                    out[i][j] = h1.diff(h2, true) as f64;
+                    out[j][i] = out[i][j];
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn create_student_documents(project: &Project, students: &[String], skeleton: &HashSet<u64>) -> Vec<Option<Document>> {
+    students
+        .iter()
+        .map(|student| {
+            student_document(project, student, skeleton)
+                .inspect_err(|e| eprintln!("Failed to fingerprint {}: {}", student, e))
+                .ok()
+        })
+        .collect()
+}
+
+/// Students whose code could not be fingerprinted are as far from everyone as can be
+fn create_winnowing_distance_matrix(fingerprints: &[Option<HashSet<u64>>]) -> Vec<Vec<f64>> {
+    let mut out: Vec<Vec<f64>> = vec![vec![0.0; fingerprints.len()]; fingerprints.len()];
+    for i in 0..fingerprints.len() {
+        for j in i + 1..fingerprints.len() {
+            out[i][j] = match (&fingerprints[i], &fingerprints[j]) {
+                (Some(f1), Some(f2)) => 1.0 - similarity(f1, f2),
+                _ => 1.0,
+            };
+            out[j][i] = out[i][j];
+        }
+    }
+    out
+}
+
+/// The student's Java source, from their rebuilt project. Files every student shares through diff_exclude, eg. the
+/// tests, are left out, as are fingerprints of the skeleton's code
+fn student_document(project: &Project, student: &str, skeleton: &HashSet<u64>) -> Result<Document> {
+    let tmpdir = tempdir()?;
+    let project_path = tmpdir.path().join(student);
+    project.recreate_normalized_project(student, &project_path)?;
+
+    let mut document = java_document(&project_path)?;
+    document.exclude(skeleton);
+    Ok(document)
+}
+
+/// Fingerprints of the code every student of the .darwin folder at `root` is given, which copying does not explain
+fn skeleton_hashes(root: &Path) -> Result<HashSet<u64>> {
+    let mut hashes = HashSet::new();
+    for dir in [in_darwin_root(root, &skel_dir()), in_darwin_root(root, &diff_exclude_dir())] {
+        if dir.is_dir() {
+            hashes.extend(java_document(&dir)?.hashes());
+        }
+    }
+    Ok(hashes)
+}
+
+/// The Java source files in dir, with paths relative to it
+fn java_document(dir: &Path) -> Result<Document> {
+    let mut files = Vec::new();
+    for path in java_files(dir)? {
+        let source = String::from_utf8_lossy(&fs::read(&path)?).to_string();
+        let path = path.strip_prefix(dir).map_err(|_| Error::from(ErrorKind::Other))?.to_path_buf();
+        let tokens = tokenize(&source);
+        files.push(SourceFile { path, source, tokens });
+    }
+    Ok(Document::new(files))
+}
+
+/// Sorted. Does not follow symlinks, which are the diff_exclude entries
+fn java_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    let mut files = Vec::new();
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            files.extend(java_files(&path)?);
+        } else if file_type.is_file() && path.extension().is_some_and(|ext| ext == "java") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Coordinates of each student, whose distances are close to those in the matrix. Without enough distinct students
+/// to scale, eg. when everyone submitted the skeleton, they are placed around a circle instead
+fn multidimensional_scaling(distance_matrix: &[Vec<f64>]) -> (Vec<f64>, Vec<f64>) {
+    let number_of_dimensions = 2;
+    let flat_distance_matrix: Vec<f64> = distance_matrix.iter().flatten().cloned().collect();
+    let matrix = DMatrix::from_column_slice(
+        distance_matrix.len(),
+        distance_matrix.len(),
+        &flat_distance_matrix,
+    );
+    // apply pcoa
+    let Some(coords_matrix) = apply_pcoa(matrix, number_of_dimensions).filter(|c| c.iter().all(|x| x.is_finite())) else {
+        let n = distance_matrix.len() as f64;
+        return (0..distance_matrix.len())
+            .map(|i| {
+                let angle = i as f64 / n * std::f64::consts::TAU;
+                (angle.cos(), angle.sin())
+            })
+            .unzip();
+    };
+
+    // NOTE: transpose matrix to get first column for x coordinates and the second - for y coordinates.
+    let coords_matrix = coords_matrix.transpose();
+    let xs: Vec<f64> = coords_matrix.column(0).iter().copied().collect();
+    let ys: Vec<f64> = coords_matrix.column(1).iter().copied().collect();
+
+    (xs, ys)
+}
+
+fn normalize_vector(v: &mut [f64], max: f64) {
+    // Normalizes vector between [0; max]
+    let mn = v
+        .iter()
+        .min_by(|a, b| a.partial_cmp(b).unwrap())
+        .copied()
+        .unwrap_or(0.0);
+    let mx = v
+        .iter()
+        .max_by(|a, b| a.partial_cmp(b).unwrap())
+        .copied()
+        .unwrap_or(0.0);
+
+    if (mx - mn).abs() > f64::EPSILON {
+        v.iter_mut().for_each(|a| {
+            *a = (*a - mn) / (mx - mn) * max;
+        });
+    } else {
+        v.iter_mut().for_each(|a| {
+            *a = 0.0;
+        });
+    }
+}
+
+/// Two students' code and what matches between them
+pub struct StudentComparison {
+    pub document1: Document,
+    pub document2: Document,
+    /// Fraction of student1's code found in student2's
+    pub containment1: f64,
+    /// Fraction of student2's code found in student1's
+    pub containment2: f64,
+    /// Largest first
+    pub matches: Vec<Match>,
+}
+
+impl StudentComparison {
+    pub fn similarity(&self) -> f64 {
+        self.containment1.max(self.containment2)
+    }
+
+    /// Each match as `path:first-last` in both students' projects, and its length in tokens
+    pub fn match_locations(&self) -> Vec<(String, String, usize)> {
+        let location = |document: &Document, file: usize, tokens| {
+            let (first, last) = document.lines(file, tokens);
+            format!("{}:{}-{}", document.files[file].path.display(), first, last)
+        };
+        self.matches
+            .iter()
+            .map(|m| {
+                (location(&self.document1, m.file1, &m.tokens1), location(&self.document2, m.file2, &m.tokens2), m.tokens1.len())
+            })
+            .collect()
+    }
+}
+
+/// Compares two students' code by winnowing
+pub fn compare_students(project: &Project, student1: &str, student2: &str) -> Result<StudentComparison> {
+    validate_students(student1, student2)?;
+
+    let skeleton = skeleton_hashes(&darwin_root())?;
+    let document1 = student_document(project, student1, &skeleton)?;
+    let document2 = student_document(project, student2, &skeleton)?;
+    if document1.files.is_empty() && document2.files.is_empty() {
+        return Err(no_java_source("neither student has any"));
+    }
+    Ok(compare_documents(document1, document2))
+}
+
+/// Winnowing would find every pair of students as far apart as can be, which looks like no plagiarism at all
+fn no_java_source(who: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("Winnowing compares Java source and {}, use --algorithm tlsh", who),
+    )
+}
+
+fn compare_documents(document1: Document, document2: Document) -> StudentComparison {
+    let (hashes1, hashes2) = (document1.hashes(), document2.hashes());
+    let matches = matches(&document1, &document2);
+    StudentComparison {
+        containment1: containment(&hashes1, &hashes2),
+        containment2: containment(&hashes2, &hashes1),
+        document1,
+        document2,
+        matches,
+    }
+}
+
+/// Writes an html page comparing two students' code side by side, with the matching code highlighted
+pub fn plagiarism_report_students(project: &Project, student1: &str, student2: &str, dest_path: &Path) -> Result<()> {
+    if dest_path.exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            "Dest path should not exist",
+        ));
+    }
+
+    let comparison = compare_students(project, student1, student2)?;
+    create_pairwise_report(project, &comparison, student1, student2, dest_path)
+}
+
+/// Compares two students' diffs by TLSH. Lower is more similar
+pub fn plagiarism_check_students(student1: &str, student2: &str) -> Result<usize> {
+    validate_students(student1, student2)?;
+    _plagiarism_check_students(student1, student2)
+}
+
+fn validate_students(student1: &str, student2: &str) -> Result<()> {
+    if !is_student(student1) {
+        return Err(Error::other(
+            format!("{} is not a student", student1),
+        ));
+    }
+
+    if !is_student(student2) {
+        return Err(Error::other(
+            format!("{} is not a student", student2),
+        ));
+    }
+
+    Ok(())
+}
+
+fn _plagiarism_check_students(student1: &str, student2: &str) -> Result<usize> {
+    let student1_diff_path = student_diff_file(student1);
+    let student2_diff_path = student_diff_file(student2);
+
+    let student1_hash = process_file(&student1_diff_path)?;
+    let student2_hash = process_file(&student2_diff_path)?;
+
+    Ok(student1_hash.diff(&student2_hash, true))
+}
+
+fn process_file(path: &Path) -> Result<Tlsh> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    _process_file(file)
+}
+
+fn _process_file(file: File) -> Result<Tlsh> {
+    // Luckily, diff processes files in the same order so we don't need to do reordering. However, we should remove all lines for diffing
+    let mut builder = TlshBuilder::new(Bucket128, OneByte, Version4);
+
+    let buf = Vec::new();
+    let mut reader = BufReader::new(file);
+    let mut writer = BufWriter::new(buf);
+
+    buffer_flatmap(&mut reader, &mut writer, |line| {
+        let line_copy = line.trim();
+        if line_copy.starts_with("diff -ruN")
+            || line_copy.starts_with("---")
+            || line_copy.starts_with("+++")
+            || line_copy.starts_with("@@")
+        {
+            return None;
+        }
+        Some(line.to_string())
+    })?;
+
+    writer.flush()?;
+    builder.update(&writer.into_inner()?);
+    builder
+        .build()
+        .map_err(|e| Error::other(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlagiarismAlgorithm;
+    use crate::darwin_config::ProjectType;
+
+    #[test]
+    fn test_for_project_type() {
+        assert_eq!(PlagiarismAlgorithm::for_project_type(&ProjectType::MavenSurefire), PlagiarismAlgorithm::Winnowing);
+        assert_eq!(PlagiarismAlgorithm::for_project_type(&ProjectType::Gradle), PlagiarismAlgorithm::Winnowing);
+        assert_eq!(PlagiarismAlgorithm::for_project_type(&ProjectType::Go), PlagiarismAlgorithm::Tlsh);
+        assert_eq!(PlagiarismAlgorithm::for_project_type(&ProjectType::Pytest), PlagiarismAlgorithm::Tlsh);
+    }
+}
@@ -0,0 +1,201 @@
+//! Ranking the pairs of students by distance, so the most suspicious can be reviewed first
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Error, ErrorKind, Result, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use super::PlagiarismAlgorithm;
+
+/// Two students at most the threshold apart
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SuspiciousPair {
+    /// 1 is the closest pair
+    pub rank: usize,
+    pub student1: String,
+    pub student2: String,
+    pub distance: f64,
+    /// Number of the cluster both students are in
+    pub cluster: usize,
+}
+
+/// Students connected by suspicious pairs, eg. a group who shared code around
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// 1 is the largest cluster
+    pub cluster: usize,
+    pub students: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PairsFile<'a> {
+    algorithm: PlagiarismAlgorithm,
+    threshold: f64,
+    pairs: &'a [SuspiciousPair],
+    clusters: &'a [Cluster],
+}
+
+/// The pairs of students at most threshold apart, closest first, and the clusters they form. Students in `compared`
+/// that could not be compared are left out. Clusters are found from every suspicious pair, before keeping the `top`
+pub fn rank_pairs(
+    students: &[String],
+    distance_matrix: &[Vec<f64>],
+    compared: &[bool],
+    threshold: f64,
+    top: Option<usize>,
+) -> (Vec<SuspiciousPair>, Vec<Cluster>) {
+    let mut edges = Vec::new();
+    for i in 0..students.len() {
+        for j in i + 1..students.len() {
+            if compared[i] && compared[j] && distance_matrix[i][j] <= threshold {
+                edges.push((i, j, distance_matrix[i][j]));
+            }
+        }
+    }
+    // Stable, so equally distant pairs stay in student order
+    edges.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let components = connected_components(students.len(), &edges);
+    let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for &(i, j, _) in &edges {
+        for student in [i, j] {
+            members.entry(components[student]).or_default().push(student);
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = members
+        .into_values()
+        .map(|mut group| {
+            group.sort_unstable();
+            group.dedup();
+            group
+        })
+        .collect();
+    // Largest first, then by their first student
+    groups.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+
+    let mut cluster_of = vec![0; students.len()];
+    let clusters = groups
+        .iter()
+        .enumerate()
+        .map(|(n, group)| {
+            for &student in group {
+                cluster_of[student] = n + 1;
+            }
+            Cluster { cluster: n + 1, students: group.iter().map(|&s| students[s].clone()).collect() }
+        })
+        .collect();
+
+    let pairs = edges
+        .into_iter()
+        .take(top.unwrap_or(usize::MAX))
+        .enumerate()
+        .map(|(rank, (i, j, distance))| SuspiciousPair {
+            rank: rank + 1,
+            student1: students[i].clone(),
+            student2: students[j].clone(),
+            distance,
+            cluster: cluster_of[i],
+        })
+        .collect();
+    (pairs, clusters)
+}
+
+/// The component of each node, as the index of one of its nodes
+fn connected_components(nodes: usize, edges: &[(usize, usize, f64)]) -> Vec<usize> {
+    fn find(parent: &mut [usize], node: usize) -> usize {
+        let mut root = node;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        // Path compression
+        let mut node = node;
+        while parent[node] != root {
+            let next = parent[node];
+            parent[node] = root;
+            node = next;
+        }
+        root
+    }
+
+    let mut parent: Vec<usize> = (0..nodes).collect();
+    for &(i, j, _) in edges {
+        let (root_i, root_j) = (find(&mut parent, i), find(&mut parent, j));
+        parent[root_i.max(root_j)] = root_i.min(root_j);
+    }
+    (0..nodes).map(|node| find(&mut parent, node)).collect()
+}
+
+/// Writes the pairs as csv, or as json with the clusters and the settings they were found with, by the file's
+/// extension
+pub fn write_pairs(
+    path: &Path,
+    algorithm: PlagiarismAlgorithm,
+    threshold: f64,
+    pairs: &[SuspiciousPair],
+    clusters: &[Cluster],
+) -> Result<()> {
+    match pairs_format(path)? {
+        "csv" => {
+            let mut wtr = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+            for pair in pairs {
+                wtr.serialize(pair)?;
+            }
+            wtr.flush()?;
+            Ok(())
+        }
+        _ => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            serde_json::to_writer_pretty(&mut writer, &PairsFile { algorithm, threshold, pairs, clusters })?;
+            writer.flush()
+        }
+    }
+}
+
+/// `csv` or `json`, by the file's extension
+pub fn pairs_format(path: &Path) -> Result<&str> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(format @ ("csv" | "json")) => Ok(format),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} should end in .csv or .json", path.display()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rank_pairs, Cluster};
+
+    #[test]
+    fn test_rank_pairs() {
+        let students: Vec<String> = ["A", "B", "C", "D", "E"].iter().map(|s| s.to_string()).collect();
+        let mut matrix = vec![vec![1.0; 5]; 5];
+        let mut set = |i: usize, j: usize, d: f64| {
+            matrix[i][j] = d;
+            matrix[j][i] = d;
+        };
+        set(0, 1, 0.3);
+        set(1, 2, 0.1);
+        set(3, 4, 0.2);
+        // E could not be compared, so its pairs are meaningless
+        let compared = [true, true, true, true, false];
+
+        let (pairs, clusters) = rank_pairs(&students, &matrix, &compared, 0.5, None);
+        let ranked: Vec<(usize, &str, &str, usize)> =
+            pairs.iter().map(|p| (p.rank, p.student1.as_str(), p.student2.as_str(), p.cluster)).collect();
+        assert_eq!(ranked, [(1, "B", "C", 1), (2, "A", "B", 1)]);
+        assert_eq!(clusters, [Cluster { cluster: 1, students: vec!["A".into(), "B".into(), "C".into()] }]);
+
+        // The clusters still include pairs past the top
+        let (pairs, clusters) = rank_pairs(&students, &matrix, &compared, 0.5, Some(1));
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(clusters[0].students, ["A", "B", "C"]);
+
+        let (pairs, clusters) = rank_pairs(&students, &matrix, &compared, 0.05, None);
+        assert!(pairs.is_empty() && clusters.is_empty());
+    }
+}
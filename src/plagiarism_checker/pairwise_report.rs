@@ -0,0 +1,277 @@
+//! The side by side comparison of two students' code, with the code that matches highlighted
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{Error, Result},
+    ops::Range,
+    path::Path,
+};
+
+use handlebars::Handlebars;
+use serde::Serialize;
+
+use super::{
+    winnowing::{Document, Match, K, WINDOW},
+    StudentComparison,
+};
+use crate::project_runner::Project;
+
+/// Distinct colours the matches cycle through
+const COLOURS: usize = 8;
+
+#[derive(Serialize)]
+struct PairwiseReportContext<'a> {
+    student1: &'a str,
+    student2: &'a str,
+    similarity: String,
+    containment1: String,
+    containment2: String,
+    k: usize,
+    window: usize,
+    matches: Vec<MatchContext>,
+    side1: SideContext<'a>,
+    side2: SideContext<'a>,
+}
+
+#[derive(Serialize)]
+struct MatchContext {
+    number: usize,
+    anchor1: String,
+    anchor2: String,
+    location1: String,
+    location2: String,
+    tokens: usize,
+    colour: usize,
+}
+
+#[derive(Serialize)]
+struct SideContext<'a> {
+    student: &'a str,
+    files: Vec<FileContext>,
+}
+
+#[derive(Serialize)]
+struct FileContext {
+    anchor: String,
+    /// In the student's submission
+    path: String,
+    tokens: usize,
+    /// Share of the file's tokens in a match
+    matched: String,
+    /// Files of the other student this one has matches with
+    matched_with: Vec<FileLinkContext>,
+    lines: Vec<LineContext>,
+}
+
+#[derive(Serialize)]
+struct FileLinkContext {
+    anchor: String,
+    path: String,
+}
+
+#[derive(Serialize)]
+struct LineContext {
+    number: usize,
+    text: String,
+    /// Ids of the matches starting at this line
+    anchors: Vec<String>,
+    /// The match on the other side, for lines in a match
+    link: Option<String>,
+    colour: usize,
+}
+
+/// One side of the comparison, file1/tokens1 or file2/tokens2 of each match
+struct Side<'a> {
+    /// Prefix of the side's anchors
+    name: &'static str,
+    student: &'a str,
+    document: &'a Document,
+    /// The file and tokens of a match on this side
+    part: fn(&Match) -> (usize, &Range<usize>),
+}
+
+/// Writes the comparison of two students as a single html file, which needs nothing else to be viewed
+pub fn create_pairwise_report(
+    project: &Project,
+    comparison: &StudentComparison,
+    student1: &str,
+    student2: &str,
+    dest_path: &Path,
+) -> Result<()> {
+    let side1 = Side { name: "a", student: student1, document: &comparison.document1, part: |m| (m.file1, &m.tokens1) };
+    let side2 = Side { name: "b", student: student2, document: &comparison.document2, part: |m| (m.file2, &m.tokens2) };
+
+    let matches = comparison
+        .matches
+        .iter()
+        .enumerate()
+        .map(|(i, m)| MatchContext {
+            number: i + 1,
+            anchor1: match_anchor(&side1, i),
+            anchor2: match_anchor(&side2, i),
+            location1: location(project, &side1, m),
+            location2: location(project, &side2, m),
+            tokens: m.tokens1.len(),
+            colour: i % COLOURS,
+        })
+        .collect();
+
+    let context = PairwiseReportContext {
+        student1,
+        student2,
+        similarity: percent(comparison.similarity()),
+        containment1: percent(comparison.containment1),
+        containment2: percent(comparison.containment2),
+        k: K,
+        window: WINDOW,
+        matches,
+        side1: side_context(project, &comparison.matches, &side1, &side2),
+        side2: side_context(project, &comparison.matches, &side2, &side1),
+    };
+
+    let mut handlebars = Handlebars::new();
+    handlebars
+        .register_template_string("pairwise_template", include_str!("../../template/plagiarism_pair.hbs"))
+        .map_err(|e| Error::other(e.to_string()))?;
+    let rendered = handlebars
+        .render("pairwise_template", &context)
+        .map_err(|e| Error::other(e.to_string()))?;
+    fs::write(dest_path, rendered)
+}
+
+fn side_context<'a>(project: &Project, matches: &[Match], side: &Side<'a>, other: &Side) -> SideContext<'a> {
+    let files = side
+        .document
+        .files
+        .iter()
+        .enumerate()
+        .map(|(file, source_file)| {
+            let mut lines: Vec<LineContext> = source_file
+                .source
+                .lines()
+                .enumerate()
+                .map(|(i, text)| LineContext { number: i + 1, text: text.to_string(), anchors: Vec::new(), link: None, colour: 0 })
+                .collect();
+            let mut matched_tokens = 0;
+            let mut matched_with = BTreeSet::new();
+            // Largest first, so a line shared by two matches belongs to the larger one
+            for (i, m) in matches.iter().enumerate() {
+                let (match_file, tokens) = (side.part)(m);
+                if match_file != file {
+                    continue;
+                }
+                matched_tokens += tokens.len();
+                matched_with.insert((other.part)(m).0);
+
+                let (first, last) = side.document.lines(file, tokens);
+                if let Some(line) = lines.get_mut(first - 1) {
+                    line.anchors.push(match_anchor(side, i));
+                }
+                for line in lines.iter_mut().take(last).skip(first - 1) {
+                    if line.link.is_none() {
+                        line.link = Some(match_anchor(other, i));
+                        line.colour = i % COLOURS;
+                    }
+                }
+            }
+
+            let tokens = source_file.tokens.len();
+            FileContext {
+                anchor: file_anchor(side, file),
+                path: original_path(project, side.student, side.document, file),
+                tokens,
+                matched: if tokens == 0 { percent(0.0) } else { percent(matched_tokens as f64 / tokens as f64) },
+                matched_with: matched_with
+                    .into_iter()
+                    .map(|other_file| FileLinkContext {
+                        anchor: file_anchor(other, other_file),
+                        path: original_path(project, other.student, other.document, other_file),
+                    })
+                    .collect(),
+                lines,
+            }
+        })
+        .collect();
+    SideContext { student: side.student, files }
+}
+
+fn match_anchor(side: &Side, i: usize) -> String {
+    format!("{}-match-{}", side.name, i + 1)
+}
+
+fn file_anchor(side: &Side, file: usize) -> String {
+    format!("{}-file-{}", side.name, file)
+}
+
+fn percent(fraction: f64) -> String {
+    format!("{:.0}%", fraction * 100.0)
+}
+
+/// Where the file was in the student's submission, which is what they will recognize
+fn original_path(project: &Project, student: &str, document: &Document, file: usize) -> String {
+    let path = &document.files[file].path;
+    project
+        .original_path(student, path)
+        .unwrap_or_else(|_| path.clone())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The match's `path:first-last` on the side, with the path of the student's submission
+fn location(project: &Project, side: &Side, m: &Match) -> String {
+    let (file, tokens) = (side.part)(m);
+    let (first, last) = side.document.lines(file, tokens);
+    format!("{}:{}-{}", original_path(project, side.student, side.document, file), first, last)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use tempfile::tempdir;
+
+    use super::create_pairwise_report;
+    use crate::{
+        plagiarism_checker::{
+            java_tokens::tokenize,
+            winnowing::{matches, Document, SourceFile},
+            StudentComparison,
+        },
+        project_runner::no_project,
+    };
+
+    fn document(source: &str) -> Document {
+        Document::new(vec![SourceFile {
+            path: PathBuf::from("Loop.java"),
+            source: source.to_string(),
+            tokens: tokenize(source),
+        }])
+    }
+
+    #[test]
+    fn test_create_pairwise_report() {
+        let source = "class Loop {\n    int f(int n) {\n        int s = 0;\n        for (int i = 0; i < n; i++) s += i;\n        return s;\n    }\n}\n";
+        let document1 = document(source);
+        let document2 = document(&format!("// Mine\n{}", source.replace(" s ", " total ")));
+        let comparison = StudentComparison {
+            matches: matches(&document1, &document2),
+            containment1: 1.0,
+            containment2: 1.0,
+            document1,
+            document2,
+        };
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("pair.html");
+        create_pairwise_report(&no_project().unwrap(), &comparison, "Alice", "Bob", &dest).unwrap();
+
+        let html = fs::read_to_string(dest).unwrap();
+        assert!(html.contains("<td><a href=\"#a-match-1\">Loop.java:1-7</a></td>"));
+        assert!(html.contains("<td><a href=\"#b-match-1\">Loop.java:2-8</a></td>"));
+        // Each line of the match links to the other student's copy, and the code is escaped
+        assert!(html.contains(
+            "<a class=\"line c0\" href=\"#b-match-1\"><span class=\"number\">4</span>        for (int i &#x3D; 0; i &lt; n; i++) s +&#x3D; i;</a>"
+        ));
+        assert!(html.contains("<span class=\"line\"><span class=\"number\">1</span>// Mine</span>"));
+    }
+}
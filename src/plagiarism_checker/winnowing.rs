@@ -0,0 +1,274 @@
+//! Document fingerprinting by winnowing, as used by MOSS. See "Winnowing: Local Algorithms for Document
+//! Fingerprinting" (Schleimer, Wilkerson, Aiken 2003)
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::PathBuf,
+};
+
+use super::java_tokens::Token;
+
+/// Tokens per k-gram. Matches shorter than this are noise
+pub const K: usize = 10;
+/// k-grams per window. Any match of at least K + WINDOW - 1 tokens is found
+pub const WINDOW: usize = 8;
+
+#[derive(Clone)]
+pub struct SourceFile {
+    /// Relative to the project
+    pub path: PathBuf,
+    /// Shown in the pairwise report
+    pub source: String,
+    pub tokens: Vec<Token>,
+}
+
+/// The hash of the k-gram starting at a file's token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fingerprint {
+    pub hash: u64,
+    pub file: usize,
+    pub token: usize,
+}
+
+/// A student's source files and their fingerprints
+#[derive(Clone)]
+pub struct Document {
+    pub files: Vec<SourceFile>,
+    pub fingerprints: Vec<Fingerprint>,
+}
+
+impl Document {
+    pub fn new(files: Vec<SourceFile>) -> Document {
+        let mut fingerprints = Vec::new();
+        for (file, source) in files.iter().enumerate() {
+            let hashes = kgram_hashes(&source.tokens);
+            fingerprints.extend(winnow(&hashes, WINDOW).into_iter().map(|token| Fingerprint { hash: hashes[token], file, token }));
+        }
+        Document { files, fingerprints }
+    }
+
+    /// Drops fingerprints of code that is not the student's own, eg. the skeleton's
+    pub fn exclude(&mut self, hashes: &HashSet<u64>) {
+        self.fingerprints.retain(|f| !hashes.contains(&f.hash));
+    }
+
+    pub fn hashes(&self) -> HashSet<u64> {
+        self.fingerprints.iter().map(|f| f.hash).collect()
+    }
+
+    /// The first and last line of a range of tokens in a file
+    pub fn lines(&self, file: usize, tokens: &Range<usize>) -> (usize, usize) {
+        let tokens_of_file = &self.files[file].tokens;
+        (tokens_of_file[tokens.start].line, tokens_of_file[tokens.end - 1].line)
+    }
+}
+
+/// FNV-1a, which unlike std's hasher is stable across releases
+fn hash_str(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// The hash of each k-gram, by the index of its first token
+fn kgram_hashes(tokens: &[Token]) -> Vec<u64> {
+    let token_hashes: Vec<u64> = tokens.iter().map(|t| hash_str(&t.text)).collect();
+    token_hashes
+        .windows(K)
+        .map(|kgram| kgram.iter().fold(0u64, |h, &t| h.wrapping_mul(0x100000001b3) ^ t))
+        .collect()
+}
+
+/// Indices of the hashes selected as fingerprints: the minimum of every window, the rightmost one on ties.
+/// A hash is only selected again once the window has moved past it
+pub fn winnow(hashes: &[u64], window: usize) -> Vec<usize> {
+    // Short files still get a fingerprint
+    let window = window.min(hashes.len());
+    let mut selected: Vec<usize> = Vec::new();
+    if window == 0 {
+        return selected;
+    }
+    for start in 0..=hashes.len() - window {
+        let mut min = start;
+        for i in start..start + window {
+            if hashes[i] <= hashes[min] {
+                min = i;
+            }
+        }
+        if selected.last() != Some(&min) {
+            selected.push(min);
+        }
+    }
+    selected
+}
+
+/// Code in a file of each document with the same tokens, by token ranges
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub file1: usize,
+    pub tokens1: Range<usize>,
+    pub file2: usize,
+    pub tokens2: Range<usize>,
+}
+
+/// Fraction of the distinct fingerprints of one document found in the other
+pub fn containment(document: &HashSet<u64>, other: &HashSet<u64>) -> f64 {
+    if document.is_empty() {
+        return 0.0;
+    }
+    document.intersection(other).count() as f64 / document.len() as f64
+}
+
+/// Similarity of two documents, from 0 to 1. Copying a small part of a larger project still counts, so this is the
+/// larger of the two containments
+pub fn similarity(hashes1: &HashSet<u64>, hashes2: &HashSet<u64>) -> f64 {
+    containment(hashes1, hashes2).max(containment(hashes2, hashes1))
+}
+
+/// The matching code of two documents, largest first, with no code in more than one match. Shared fingerprints at
+/// the same offset from each other are merged into one match where their k-grams overlap
+pub fn matches(document1: &Document, document2: &Document) -> Vec<Match> {
+    let mut positions: HashMap<u64, Vec<&Fingerprint>> = HashMap::new();
+    for fingerprint in &document2.fingerprints {
+        positions.entry(fingerprint.hash).or_default().push(fingerprint);
+    }
+
+    let mut pairs: Vec<(usize, usize, isize, usize, usize)> = Vec::new();
+    for f1 in &document1.fingerprints {
+        for f2 in positions.get(&f1.hash).into_iter().flatten() {
+            pairs.push((f1.file, f2.file, f1.token as isize - f2.token as isize, f1.token, f2.token));
+        }
+    }
+    pairs.sort_unstable();
+
+    let mut matches: Vec<Match> = Vec::new();
+    let mut previous: Option<(usize, usize, isize)> = None;
+    for (file1, file2, offset, token1, token2) in pairs {
+        if let Some(last) = matches.last_mut() {
+            if previous == Some((file1, file2, offset)) && token1 <= last.tokens1.end {
+                last.tokens1.end = last.tokens1.end.max(token1 + K);
+                last.tokens2.end = last.tokens2.end.max(token2 + K);
+                continue;
+            }
+        }
+        previous = Some((file1, file2, offset));
+        matches.push(Match { file1, tokens1: token1..token1 + K, file2, tokens2: token2..token2 + K });
+    }
+    for m in &mut matches {
+        extend(document1, document2, m);
+    }
+    matches.sort_by(|a, b| b.tokens1.len().cmp(&a.tokens1.len()).then((a.file1, a.tokens1.start).cmp(&(b.file1, b.tokens1.start))));
+
+    // Code repeated within a document matches every repetition in the other, and extending can make matches of the
+    // same code equal. Like greedy string tiling, only keep matches of code that no larger match covers
+    let mut kept: Vec<Match> = Vec::new();
+    for m in matches {
+        let overlaps = |k: &Match| {
+            (k.file1 == m.file1 && ranges_overlap(&k.tokens1, &m.tokens1))
+                || (k.file2 == m.file2 && ranges_overlap(&k.tokens2, &m.tokens2))
+        };
+        if !kept.iter().any(overlaps) {
+            kept.push(m);
+        }
+    }
+    kept
+}
+
+fn ranges_overlap(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Grows a match in both directions while the tokens stay the same. Fingerprints are only a sample of the k-grams,
+/// so the matching code usually starts before the first and ends after the last
+fn extend(document1: &Document, document2: &Document, m: &mut Match) {
+    let tokens1 = &document1.files[m.file1].tokens;
+    let tokens2 = &document2.files[m.file2].tokens;
+    while m.tokens1.start > 0 && m.tokens2.start > 0 && tokens1[m.tokens1.start - 1].text == tokens2[m.tokens2.start - 1].text {
+        m.tokens1.start -= 1;
+        m.tokens2.start -= 1;
+    }
+    while m.tokens1.end < tokens1.len() && m.tokens2.end < tokens2.len() && tokens1[m.tokens1.end].text == tokens2[m.tokens2.end].text {
+        m.tokens1.end += 1;
+        m.tokens2.end += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{matches, similarity, winnow, Document, SourceFile};
+    use crate::plagiarism_checker::java_tokens::tokenize;
+
+    fn document(sources: &[&str]) -> Document {
+        Document::new(
+            sources
+                .iter()
+                .enumerate()
+                .map(|(i, source)| {
+                    SourceFile { path: PathBuf::from(format!("F{}.java", i)), source: source.to_string(), tokens: tokenize(source) }
+                })
+                .collect(),
+        )
+    }
+
+    const SORT: &str = "class Sort {
+    static void sort(int[] a) {
+        for (int i = 1; i < a.length; i++) {
+            int key = a[i];
+            int j = i - 1;
+            while (j >= 0 && a[j] > key) {
+                a[j + 1] = a[j];
+                j--;
+            }
+            a[j + 1] = key;
+        }
+    }
+}
+";
+
+    #[test]
+    fn test_winnow() {
+        let hashes = [77, 74, 42, 17, 98, 50, 17, 98, 8, 88, 67, 39, 77, 74, 42, 17, 98];
+        // The example from the paper
+        assert_eq!(winnow(&hashes, 4), [3, 6, 8, 11, 15]);
+        assert_eq!(winnow(&[5, 1], 4), [1]);
+        assert!(winnow(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn test_renamed_copy_matches() {
+        let copy = SORT.replace("key", "value").replace("sort", "order").replace("    ", "\t");
+        let original = document(&[SORT]);
+        let disguised = document(&["// Mine\n", &copy]);
+        assert_eq!(similarity(&original.hashes(), &disguised.hashes()), 1.0);
+
+        let found = matches(&original, &disguised);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].file2, 1);
+        assert_eq!(original.lines(found[0].file1, &found[0].tokens1), (1, 13));
+    }
+
+    #[test]
+    fn test_repeated_code_matches_once() {
+        let twice = format!("{}{}", SORT, SORT);
+        let found = matches(&document(&[&twice]), &document(&[&twice]));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].tokens1, 0..found[0].tokens1.end);
+        assert_eq!(found[0].tokens1, found[0].tokens2);
+    }
+
+    #[test]
+    fn test_unrelated_code_does_not_match() {
+        let other = "class Stack {
+    private int[] items = new int[16];
+    private int size;
+    void push(int item) { if (size == items.length) grow(); items[size++] = item; }
+    int pop() { return items[--size]; }
+    private void grow() { items = java.util.Arrays.copyOf(items, size * 2); }
+}
+";
+        let hashes1 = document(&[SORT]).hashes();
+        let hashes2 = document(&[other]).hashes();
+        assert!(similarity(&hashes1, &hashes2) < 0.2);
+    }
+}
@@ -0,0 +1,275 @@
+//! How far a test run has got: a live status line when stdout is a terminal, otherwise a line per student.
+//! Ends with a summary of how the students did
+
+use std::{
+    io::{stdout, IsTerminal, Result, Write},
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::output::print_table;
+
+/// How often the live status line is redrawn, so the elapsed time keeps moving while students take long
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Running students listed on the status line, the rest are counted
+const MAX_ACTIVE_SHOWN: usize = 3;
+
+/// Shared by the threads running students. Call `finish` once the run is over
+pub struct ProgressReporter {
+    state: Mutex<ProgressState>,
+    /// Whether stdout is a terminal the status line can be redrawn on
+    live: bool,
+    started: Instant,
+    stop_redraw: Mutex<Option<Sender<()>>>,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    total: usize,
+    completed: usize,
+    active: Vec<String>,
+}
+
+/// How the students of a run did, see `run_tests::summarize`
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct RunSummary {
+    /// Student and test pairs that were run
+    pub runs: usize,
+    pub compile_errors: Vec<String>,
+    /// `student (test)`
+    pub timeouts: Vec<String>,
+    /// Runs that ended without a result, eg. because the build tool crashed. `student (test)`
+    pub infrastructure_errors: Vec<String>,
+    /// Fraction of test cases passed by each student that got results
+    pub pass_rates: Vec<f64>,
+}
+
+impl ProgressReporter {
+    pub fn new() -> Arc<ProgressReporter> {
+        let live = stdout().is_terminal();
+        let progress = Arc::new(ProgressReporter {
+            state: Mutex::new(ProgressState::default()),
+            live,
+            started: Instant::now(),
+            stop_redraw: Mutex::new(None),
+        });
+        if live {
+            let (sender, receiver) = channel::<()>();
+            *progress.stop_redraw.lock().unwrap() = Some(sender);
+            let weak: Weak<ProgressReporter> = Arc::downgrade(&progress);
+            thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(REDRAW_INTERVAL) {
+                    match weak.upgrade() {
+                        Some(progress) => progress.redraw(&progress.state.lock().unwrap()),
+                        None => break,
+                    }
+                }
+            });
+        }
+        progress
+    }
+
+    /// Students about to be run
+    pub fn add_total(&self, students: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.total += students;
+        if self.live {
+            self.redraw(&state);
+        }
+    }
+
+    pub fn start(&self, student: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.active.push(student.to_string());
+        if self.live {
+            self.redraw(&state);
+        } else {
+            println!("Processing: {}", student);
+        }
+    }
+
+    pub fn end(&self, student: &str, result: &Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        state.completed += 1;
+        state.active.retain(|s| s != student);
+        if self.live {
+            if let Err(e) = result {
+                clear_line();
+                eprintln!("Error processing {}: {}", student, e);
+            }
+            self.redraw(&state);
+            return;
+        }
+        match result {
+            Ok(()) => println!("[{}/{}] Done: {}", state.completed, state.total, student),
+            Err(e) => eprintln!("[{}/{}] Error processing {}: {}", state.completed, state.total, student, e),
+        }
+    }
+
+    /// Students finished, and students to be run
+    pub fn counts(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.completed, state.total)
+    }
+
+    /// Stops redrawing and replaces the status line with how long the run took
+    pub fn finish(&self) {
+        if let Some(stop) = self.stop_redraw.lock().unwrap().take() {
+            let _ = stop.send(());
+        }
+        let state = self.state.lock().unwrap();
+        if self.live {
+            clear_line();
+        }
+        if state.total > 0 {
+            println!("Ran {} students in {}", state.completed, format_duration(self.started.elapsed()));
+        }
+    }
+
+    fn redraw(&self, state: &ProgressState) {
+        if state.total == 0 {
+            return;
+        }
+        let line = status_line(state, self.started.elapsed());
+        let line: String = line.chars().take(terminal_width().saturating_sub(1)).collect();
+        let mut stdout = stdout().lock();
+        let _ = write!(stdout, "\r\x1b[2K{}", line);
+        let _ = stdout.flush();
+    }
+}
+
+/// `[12/40] 30% | elapsed 1m02s | ETA 2m25s | running: Alice, Bob, Carol +1`
+fn status_line(state: &ProgressState, elapsed: Duration) -> String {
+    let eta = match state.completed {
+        0 => String::from("--"),
+        completed => format_duration(elapsed.mul_f64((state.total - completed) as f64 / completed as f64)),
+    };
+    let mut line = format!(
+        "[{}/{}] {}% | elapsed {} | ETA {}",
+        state.completed,
+        state.total,
+        state.completed * 100 / state.total,
+        format_duration(elapsed),
+        eta
+    );
+    if !state.active.is_empty() {
+        line.push_str(" | running: ");
+        line.push_str(&state.active.iter().take(MAX_ACTIVE_SHOWN).cloned().collect::<Vec<String>>().join(", "));
+    }
+    if state.active.len() > MAX_ACTIVE_SHOWN {
+        line.push_str(&format!(" +{}", state.active.len() - MAX_ACTIVE_SHOWN));
+    }
+    line
+}
+
+fn clear_line() {
+    let mut stdout = stdout().lock();
+    let _ = write!(stdout, "\r\x1b[2K");
+    let _ = stdout.flush();
+}
+
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 => size.ws_col as usize,
+        _ => 80,
+    }
+}
+
+/// `45s`, `1m02s` or `1h03m`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Highest first, so the table reads from the best students down
+const PASS_RATE_BUCKETS: [(&str, f64); 6] = [
+    ("100% passed", 1.0),
+    ("75-99% passed", 0.75),
+    ("50-74% passed", 0.5),
+    ("25-49% passed", 0.25),
+    ("1-24% passed", f64::MIN_POSITIVE),
+    ("0% passed", 0.0),
+];
+
+fn pass_rate_bucket(rate: f64) -> usize {
+    PASS_RATE_BUCKETS.iter().position(|(_, min)| rate >= *min).unwrap_or(PASS_RATE_BUCKETS.len() - 1)
+}
+
+pub fn print_summary(summary: &RunSummary) {
+    print_table(&["Outcome", "Count", "Students"], &summary_rows(summary));
+}
+
+fn summary_rows(summary: &RunSummary) -> Vec<Vec<String>> {
+    let mut rows = vec![
+        vec![String::from("Compile errors"), summary.compile_errors.len().to_string(), summary.compile_errors.join(", ")],
+        vec![String::from("Timeouts"), summary.timeouts.len().to_string(), summary.timeouts.join(", ")],
+        vec![
+            String::from("Infrastructure errors"),
+            summary.infrastructure_errors.len().to_string(),
+            summary.infrastructure_errors.join(", "),
+        ],
+    ];
+    let mut counts = [0; PASS_RATE_BUCKETS.len()];
+    for rate in &summary.pass_rates {
+        counts[pass_rate_bucket(*rate)] += 1;
+    }
+    for ((name, _), count) in PASS_RATE_BUCKETS.iter().zip(counts) {
+        rows.push(vec![name.to_string(), count.to_string(), String::new()]);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_duration, pass_rate_bucket, status_line, summary_rows, ProgressState, RunSummary};
+
+    #[test]
+    fn test_status_line() {
+        let state = ProgressState {
+            total: 40,
+            completed: 10,
+            active: vec!["Alice".into(), "Bob".into(), "Carol".into(), "Dave".into()],
+        };
+        assert_eq!(
+            status_line(&state, Duration::from_secs(62)),
+            "[10/40] 25% | elapsed 1m02s | ETA 3m06s | running: Alice, Bob, Carol +1"
+        );
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(3780)), "1h03m");
+    }
+
+    #[test]
+    fn test_summary_rows() {
+        assert_eq!(pass_rate_bucket(1.0), 0);
+        assert_eq!(pass_rate_bucket(0.75), 1);
+        assert_eq!(pass_rate_bucket(0.01), 4);
+        assert_eq!(pass_rate_bucket(0.0), 5);
+
+        let summary = RunSummary {
+            runs: 4,
+            compile_errors: vec!["Eve".into()],
+            timeouts: vec![],
+            infrastructure_errors: vec!["Bob (MathTests)".into()],
+            pass_rates: vec![1.0, 0.5, 0.6],
+        };
+        let rows = summary_rows(&summary);
+        assert_eq!(rows[0], vec!["Compile errors", "1", "Eve"]);
+        assert_eq!(rows[2], vec!["Infrastructure errors", "1", "Bob (MathTests)"]);
+        assert_eq!(rows[3], vec!["100% passed", "1", ""]);
+        assert_eq!(rows[5], vec!["50-74% passed", "2", ""]);
+    }
+}
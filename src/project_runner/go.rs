@@ -0,0 +1,462 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, create_dir_all, read_dir, remove_file, rename, OpenOptions};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tempfile::tempfile;
+
+use crate::config::skel_dir;
+use crate::types::{CompileDiagnostic, CompileError, StatusMsg, TestResult, TestResultError};
+
+use super::{project_relative_path, sandbox, Project};
+
+/// Directory (relative to the project root) `go test -json` output is written to
+const REPORT_DIR: &str = "test-reports";
+
+pub fn compile(project: &Project, project_path: &Path) -> Result<()> {
+    restore_skeleton_tests(project_path)?;
+
+    // Both commands share the compile time limit
+    let deadline = Instant::now() + project.compile_timeout;
+    // go vet type checks test packages as well, go build does not
+    for args in [["vet", "./..."], ["build", "./..."]] {
+        let mut command = sandbox::command(project, project_path, "go")?;
+        command.args(args).stdin(Stdio::null());
+        let (status, output) = command.output_timeout(deadline.saturating_duration_since(Instant::now()))?;
+
+        if !status.success() {
+            return Err(CompileError {
+                summary: format!("'go {}' failed", args.join(" ")),
+                diagnostics: parse_go_build_output(&output, project_path),
+                output,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists every `*_test.go` file in the skeleton as `package.path.file_test`, eg. `src/calc/calc_test.go` is
+/// `src.calc.calc_test`
+pub fn list_tests(_: &Project) -> HashSet<String> {
+    let skel = skel_dir();
+    let mut out = HashSet::new();
+    for file in test_files(&skel) {
+        let file = file.strip_prefix(&skel).unwrap().with_extension("");
+        out.insert(file.to_string_lossy().replace('/', "."));
+    }
+    out
+}
+
+/// The tests graded are the skeleton's, so the students' own `*_test.go` files are removed and any they changed are
+/// put back. Test files next to the packages would otherwise come from the submission
+fn restore_skeleton_tests(project_path: &Path) -> Result<()> {
+    for file in test_files(project_path) {
+        remove_file(&file)?;
+    }
+    let skel = skel_dir();
+    for file in test_files(&skel) {
+        let dest = project_path.join(file.strip_prefix(&skel).unwrap());
+        if let Some(parent) = dest.parent() {
+            create_dir_all(parent)?;
+        }
+        fs::copy(&file, &dest).map_err(|e| Error::other(format!("Failed to copy {:?} to {:?}: {}", file, dest, e)))?;
+    }
+    Ok(())
+}
+
+/// `*_test.go` files under `dir`, skipping the directories the go tool ignores. Symlinks are not followed
+fn test_files(dir: &Path) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    let Ok(entries) = read_dir(dir) else {
+        return out;
+    };
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if file_type.is_dir() && !(name.starts_with('.') || name.starts_with('_') || name == "testdata" || name == "vendor") {
+            out.append(&mut test_files(&entry.path()));
+        } else if file_type.is_file() && name.ends_with("_test.go") {
+            out.push(entry.path());
+        }
+    }
+    out
+}
+
+/// Runs `go test -json` once per package, restricted to the test functions declared in the selected test files, and
+/// splits its output into a report per test file
+pub fn run_test(project: &Project, project_path: &Path, tests: &[String]) -> Result<()> {
+    // Package directory -> tests in it and their test functions
+    let mut packages: BTreeMap<PathBuf, Vec<(&str, Vec<String>)>> = BTreeMap::new();
+    for test in tests {
+        let test_file = test_file_path(test);
+        let test_functions = list_test_functions(&project_path.join(&test_file))?;
+        if test_functions.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("No test functions found in {:?}", test_file),
+            ));
+        }
+        let package = test_file.parent().map(Path::to_path_buf).unwrap_or_default();
+        packages.entry(package).or_default().push((test, test_functions));
+    }
+
+    let report_dir = project_path.join(REPORT_DIR);
+    create_dir_all(&report_dir)?;
+    for (package, tests) in packages {
+        let report = run_package(project, project_path, &package, &tests)?;
+        for ((test, _), report) in tests.iter().zip(split_package_report(&report, &tests)) {
+            fs::write(report_dir.join(report_file_name(test)), report)?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs `go test -json -run '^(TestA|TestB)$' ./{package}` and returns its output. The package's tests share the test
+/// timeout once per test file
+fn run_package(project: &Project, project_path: &Path, package: &Path, tests: &[(&str, Vec<String>)]) -> Result<String> {
+    let test_functions: Vec<&str> = tests.iter().flat_map(|(_, functions)| functions.iter().map(String::as_str)).collect();
+    let mut report = tempfile()?;
+
+    let mut command = sandbox::command(project, project_path, "go")?;
+    command
+        .arg("test")
+        .arg("-json")
+        .arg("-run")
+        .arg(format!("^({})$", test_functions.join("|")))
+        .arg(format!("./{}", package.to_string_lossy()))
+        .stdin(Stdio::null())
+        .stdout(report.try_clone()?)
+        .stderr(Stdio::null());
+    command.status_timeout(project.test_timeout * tests.len() as u32)?;
+
+    let mut out = String::new();
+    report.seek(SeekFrom::Start(0))?;
+    report.read_to_string(&mut out)?;
+    Ok(out)
+}
+
+/// Gives each test file the events of its own test functions, and subtests of them. Events of the package as a whole,
+/// eg. build failures, go to every test file
+fn split_package_report(report: &str, tests: &[(&str, Vec<String>)]) -> Vec<String> {
+    let mut out = vec![String::new(); tests.len()];
+    for line in report.lines() {
+        let test_function = serde_json::from_str::<TestEvent>(line)
+            .ok()
+            .and_then(|event| event.test)
+            .map(|name| name.split('/').next().unwrap_or_default().to_string());
+        let owner = test_function.and_then(|function| tests.iter().position(|(_, functions)| functions.contains(&function)));
+        for (i, report) in out.iter_mut().enumerate() {
+            if owner.is_none_or(|owner| owner == i) {
+                report.push_str(line);
+                report.push('\n');
+            }
+        }
+    }
+    out
+}
+
+pub fn relocate_test_results(_: &Project, project_path: &Path, test: &str, dest_file: &Path) -> Result<()> {
+    let results_file_from = project_path.join(REPORT_DIR).join(report_file_name(test));
+
+    if !results_file_from.exists() {
+        return Err(Error::new(ErrorKind::NotFound, "Results file was not found"));
+    }
+    rename(&results_file_from, dest_file).map_err(|e|Error::other(format!("Failed to rename {:?} to {:?}: {}", results_file_from, dest_file, e)))?;
+    Ok(())
+}
+
+pub fn parse_result_report(_: &Project, report_path: &Path, student: &str, test: &str) -> std::result::Result<Vec<TestResult>, TestResultError> {
+    parse_go_test_report(report_path, student, test)
+}
+
+/// Parses `path/file.go:12:5: message` lines, as printed by both go build and go vet.
+/// Tab indented detail lines following an error are appended to its message
+fn parse_go_build_output(output: &str, project_path: &Path) -> Vec<CompileDiagnostic> {
+    let mut out: Vec<CompileDiagnostic> = Vec::new();
+    for line in output.lines() {
+        if let Some(detail) = line.strip_prefix('\t') {
+            if let Some(diagnostic) = out.last_mut() {
+                diagnostic.message.push('\n');
+                diagnostic.message.push_str(detail.trim());
+            }
+            continue;
+        }
+        let line = line.strip_prefix("vet: ").unwrap_or(line);
+        let Some((file, rest)) = line.split_once(".go:") else {
+            continue;
+        };
+        let mut parts = rest.splitn(3, ':');
+        let (Some(Ok(line_number)), Some(column), Some(message)) =
+            (parts.next().map(str::parse), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let diagnostic = CompileDiagnostic {
+            file: project_relative_path(project_path, &format!("{}.go", file)),
+            line: line_number,
+            column: column.parse().ok(),
+            message: message.trim().to_string(),
+        };
+        if !out.contains(&diagnostic) {
+            out.push(diagnostic);
+        }
+    }
+    out
+}
+
+/// A single line of `go test -json` output. See `go doc test2json`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TestEvent {
+    action: String,
+    test: Option<String>,
+    elapsed: Option<f64>,
+    output: Option<String>,
+}
+
+fn parse_go_test_report(
+    report_path: &Path,
+    student: &str,
+    test: &str,
+) -> std::result::Result<Vec<TestResult>, TestResultError> {
+    let report = OpenOptions::new().read(true).open(report_path).map_err(TestResultError::IOError)?;
+    let report = BufReader::new(report);
+
+    let mut out = Vec::new();
+    // Test name -> Output collected so far
+    let mut outputs: HashMap<String, String> = HashMap::new();
+    // Output not belonging to any test, eg. build failures
+    let mut package_output = String::new();
+    let mut package_failed = false;
+
+    for line in report.lines() {
+        let line = line.map_err(TestResultError::IOError)?;
+        if !line.starts_with('{') {
+            // Build errors may be written outside of json events
+            package_output.push_str(&line);
+            package_output.push('\n');
+            continue;
+        }
+        let event: TestEvent = serde_json::from_str(&line).map_err(|e| {
+            TestResultError::IOError(Error::other(format!(
+                "Failed to parse {}'s {} test results: {}",
+                student, test, e
+            )))
+        })?;
+
+        match (event.action.as_str(), event.test) {
+            ("output", Some(name)) => {
+                outputs.entry(name).or_default().push_str(&event.output.unwrap_or_default());
+            }
+            ("output", None) | ("build-output", None) => {
+                package_output.push_str(&event.output.unwrap_or_default());
+            }
+            ("pass", Some(name)) => {
+                outputs.remove(&name);
+                out.push(TestResult {
+                    name,
+                    classname: test.to_string(),
+                    time: elapsed(event.elapsed),
+                    msg: StatusMsg::None,
+                });
+            }
+            ("fail", Some(name)) => {
+                let output = outputs.remove(&name).unwrap_or_default();
+                out.push(TestResult {
+                    name,
+                    classname: test.to_string(),
+                    time: elapsed(event.elapsed),
+                    msg: failure_msg(output),
+                });
+            }
+            ("fail", None) => {
+                package_failed = true;
+            }
+            _ => {}
+        }
+    }
+
+    // The package failed without any test failing, eg. the test files do not compile or TestMain exited early
+    if package_failed && !out.iter().any(|r| r.msg != StatusMsg::None) {
+        out.push(TestResult {
+            name: test.to_string(),
+            classname: test.to_string(),
+            time: Duration::new(0, 0),
+            msg: StatusMsg::Error {
+                message: package_output.lines().find(|l| !l.trim().is_empty()).map(String::from),
+                type_: String::from("package failed"),
+                full_message: Some(package_output),
+            },
+        });
+    }
+
+    Ok(out)
+}
+
+/// Converts the output of a failed test into a `StatusMsg`. Panics are reported as errors, everything else as failures
+fn failure_msg(output: String) -> StatusMsg {
+    let message = output
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with("=== ") && !l.starts_with("--- "))
+        .map(String::from);
+    if output.contains("panic: ") {
+        StatusMsg::Error {
+            message,
+            type_: String::from("panic"),
+            full_message: Some(output),
+        }
+    } else {
+        StatusMsg::Failure {
+            message,
+            type_: String::from("fail"),
+            full_message: Some(output),
+        }
+    }
+}
+
+fn elapsed(seconds: Option<f64>) -> Duration {
+    Duration::from_secs_f64(seconds.unwrap_or(0.0).max(0.0))
+}
+
+/// `src.math.math_test` -> `src/math/math_test.go`
+fn test_file_path(test: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    path.extend(test.split('.'));
+    path.set_extension("go");
+    path
+}
+
+fn report_file_name(test: &str) -> String {
+    format!("TEST-{}.json", test)
+}
+
+/// Lists top level `func TestXxx(t *testing.T)` declarations
+fn list_test_functions(test_file: &Path) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(test_file)?;
+    let mut out = Vec::new();
+    for line in contents.lines() {
+        let Some(rest) = line.strip_prefix("func Test") else {
+            continue;
+        };
+        let suffix: String = rest
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+        // Go ignores TestXxx functions where Xxx starts with a lowercase letter
+        if suffix.starts_with(|c: char| c.is_lowercase()) {
+            continue;
+        }
+        let signature = rest[suffix.len()..].trim_start();
+        if signature.starts_with('(') && signature.contains("*testing.T)") {
+            out.push(format!("Test{}", suffix));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::Path;
+
+    use crate::types::StatusMsg;
+
+    use super::{
+        list_test_functions, parse_go_build_output, parse_go_test_report, split_package_report, test_file_path, test_files,
+    };
+
+    #[test]
+    fn test_parse_go_test_report() {
+        let mut report = tempfile::NamedTempFile::new().unwrap();
+        writeln!(report, r#"{{"Action":"run","Package":"m/src/math","Test":"TestAdd"}}"#).unwrap();
+        writeln!(report, r#"{{"Action":"output","Package":"m/src/math","Test":"TestAdd","Output":"=== RUN   TestAdd\n"}}"#).unwrap();
+        writeln!(report, r#"{{"Action":"pass","Package":"m/src/math","Test":"TestAdd","Elapsed":0.5}}"#).unwrap();
+        writeln!(report, r#"{{"Action":"run","Package":"m/src/math","Test":"TestSub"}}"#).unwrap();
+        writeln!(report, r#"{{"Action":"output","Package":"m/src/math","Test":"TestSub","Output":"    math_test.go:12: expected 1, got 2\n"}}"#).unwrap();
+        writeln!(report, r#"{{"Action":"fail","Package":"m/src/math","Test":"TestSub","Elapsed":0}}"#).unwrap();
+        writeln!(report, r#"{{"Action":"fail","Package":"m/src/math","Elapsed":0.6}}"#).unwrap();
+        report.flush().unwrap();
+
+        let results = parse_go_test_report(report.path(), "student", "src.math.math_test").unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].name, "TestAdd");
+        assert_eq!(results[0].msg, StatusMsg::None);
+        assert_eq!(results[1].name, "TestSub");
+        assert_eq!(
+            results[1].msg,
+            StatusMsg::Failure {
+                message: Some(String::from("math_test.go:12: expected 1, got 2")),
+                type_: String::from("fail"),
+                full_message: Some(String::from("    math_test.go:12: expected 1, got 2\n")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_list_test_functions() {
+        let mut test_file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            test_file,
+            "package math\n\nfunc TestAdd(t *testing.T) {{}}\nfunc Testhelper(t *testing.T) {{}}\nfunc TestMain(m *testing.M) {{}}\nfunc helper() {{}}\n"
+        )
+        .unwrap();
+        test_file.flush().unwrap();
+
+        let functions = list_test_functions(test_file.path()).unwrap();
+        assert_eq!(functions, vec!["TestAdd"]);
+        assert_eq!(test_file_path("src.math.math_test").to_string_lossy(), "src/math/math_test.go");
+    }
+
+    #[test]
+    fn test_split_package_report() {
+        let report = [
+            r#"{"Action":"run","Package":"m/src/math","Test":"TestAdd"}"#,
+            r#"{"Action":"pass","Package":"m/src/math","Test":"TestAdd/small","Elapsed":0}"#,
+            r#"{"Action":"run","Package":"m/src/math","Test":"TestSub"}"#,
+            r#"{"Action":"fail","Package":"m/src/math","Elapsed":0.6}"#,
+        ]
+        .join("\n");
+        let tests = [("src.math.add_test", vec![String::from("TestAdd")]), ("src.math.sub_test", vec![String::from("TestSub")])];
+        let reports = split_package_report(&report, &tests);
+        assert_eq!(reports[0].lines().collect::<Vec<&str>>(), [0, 1, 3].map(|i| report.lines().nth(i).unwrap()));
+        assert_eq!(reports[1].lines().collect::<Vec<&str>>(), [2, 3].map(|i| report.lines().nth(i).unwrap()));
+    }
+
+    #[test]
+    fn test_test_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["go.mod", "calc_test.go", "src/calc/calc.go", "src/calc/calc_test.go", "src/calc/testdata/x_test.go", "test/math/math_test.go"] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "package calc\n").unwrap();
+        }
+        let mut files: Vec<String> = test_files(dir.path())
+            .iter()
+            .map(|file| file.strip_prefix(dir.path()).unwrap().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["calc_test.go", "src/calc/calc_test.go", "test/math/math_test.go"]);
+    }
+
+    #[test]
+    fn test_parse_go_build_output() {
+        let output = "# m/src/calc\nvet: ./src/calc/calc.go:3:9: undefined: y\n# m/src/calc\nsrc/calc/calc.go:5:2: cannot use s (variable of type string) as int value in return statement\n\thave (string)\n\twant (int)\n";
+        let diagnostics = parse_go_build_output(output, Path::new("/work/student"));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file, Path::new("src/calc/calc.go"));
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, Some(9)));
+        assert_eq!(diagnostics[0].message, "undefined: y");
+        assert_eq!(
+            diagnostics[1].message,
+            "cannot use s (variable of type string) as int value in return statement\nhave (string)\nwant (int)"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashSet;
+use std::fs::rename;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::types::{CompileError, TestResult, TestResultError};
+
+use super::{maven, sandbox, Project};
+
+pub fn compile(project: &Project, project_path: &Path) -> Result<()> {
+    // gradle testClasses
+    let mut command = sandbox::command(project, project_path, "gradle")?;
+    command
+        .arg("testClasses")
+        .arg("--offline")
+        .arg("--console=plain")
+        .stdin(Stdio::null());
+    let (status, output) = command.output_timeout(project.compile_timeout)?;
+    if !status.success() {
+        return Err(CompileError {
+            summary: String::from("'gradle testClasses' failed"),
+            diagnostics: maven::parse_javac_output(&output, project_path),
+            output,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Gradle uses the same `src/test/java` layout as maven
+pub fn list_tests(project: &Project) -> HashSet<String> {
+    maven::list_tests(project)
+}
+
+/// Runs `gradle test --offline --tests {test} --tests {test}...`, which writes a report per test class
+pub fn run_test(project: &Project, project_path: &Path, tests: &[String]) -> Result<()> {
+    let mut command = sandbox::command(project, project_path, "gradle")?;
+    command.arg("test").arg("--offline");
+    for test in tests {
+        command.arg("--tests").arg(test);
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command.status_timeout(project.test_timeout * tests.len() as u32)?;
+
+    Ok(())
+}
+
+pub fn relocate_test_results(_: &Project, project_path: &Path, test: &str, dest_file: &Path) -> Result<()> {
+    let results_filename_from = format!("TEST-{}.xml", test);
+    let results_file_from = project_path
+        .join("build")
+        .join("test-results")
+        .join("test")
+        .join(results_filename_from);
+
+    if !results_file_from.exists() {
+        return Err(Error::new(ErrorKind::NotFound, "Results file was not found"));
+    }
+    rename(&results_file_from, dest_file).map_err(|e|Error::other(format!("Failed to rename {:?} to {:?}: {}", results_file_from, dest_file, e)))?;
+    Ok(())
+}
+
+pub fn parse_result_report(_: &Project, report_path: &Path, student: &str, test: &str) -> std::result::Result<Vec<TestResult>, TestResultError> {
+    maven::parse_surefire_report(report_path, student, test)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use crate::project_runner::gradle_project;
+    use crate::types::{CompileDiagnostic, StatusMsg, TestResult};
+
+    use super::{maven, parse_result_report, relocate_test_results};
+
+    #[test]
+    fn test_relocate_test_results() {
+        let project = gradle_project().unwrap();
+        let tmpdir = tempdir().unwrap();
+        let project_path = tmpdir.path().join("project");
+        let results = project_path.join("build").join("test-results").join("test");
+        fs::create_dir_all(&results).unwrap();
+        fs::write(results.join("TEST-a.FooTest.xml"), "<testsuite/>").unwrap();
+        let dest_file = tmpdir.path().join("a.FooTest.xml");
+
+        relocate_test_results(&project, &project_path, "a.FooTest", &dest_file).unwrap();
+        assert_eq!(fs::read_to_string(&dest_file).unwrap(), "<testsuite/>");
+        assert!(!results.join("TEST-a.FooTest.xml").exists());
+
+        assert!(relocate_test_results(&project, &project_path, "a.BarTest", &tmpdir.path().join("a.BarTest.xml")).is_err());
+    }
+
+    #[test]
+    fn test_parse_result_report() {
+        // Gradle escapes the stack trace instead of wrapping it in CDATA
+        let report = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="a.FooTest" tests="3" skipped="0" failures="1" errors="1" timestamp="2024-03-01T10:00:00" hostname="host" time="0.031">
+  <properties/>
+  <testcase name="adds()" classname="a.FooTest" time="0.012"/>
+  <testcase name="compares()" classname="a.FooTest" time="0.015">
+    <failure message="org.opentest4j.AssertionFailedError: expected: &lt;1&gt; but was: &lt;2&gt;" type="org.opentest4j.AssertionFailedError">org.opentest4j.AssertionFailedError: expected: &lt;1&gt; but was: &lt;2&gt;
+	at a.FooTest.compares(FooTest.java:14)
+</failure>
+  </testcase>
+  <testcase name="divides()" classname="a.FooTest" time="0.004">
+    <error message="java.lang.ArithmeticException: / by zero" type="java.lang.ArithmeticException">java.lang.ArithmeticException: / by zero
+	at a.Foo.divide(Foo.java:7)
+</error>
+  </testcase>
+  <system-out><![CDATA[Comparing &lt;things&gt;
+]]></system-out>
+  <system-err><![CDATA[]]></system-err>
+</testsuite>
+"#;
+        let tmpdir = tempdir().unwrap();
+        let report_path = tmpdir.path().join("a.FooTest.xml");
+        fs::write(&report_path, report).unwrap();
+
+        let results = parse_result_report(&gradle_project().unwrap(), &report_path, "Alice", "a.FooTest").unwrap();
+        assert_eq!(
+            results,
+            vec![
+                TestResult {
+                    name: String::from("adds()"),
+                    classname: String::from("a.FooTest"),
+                    time: Duration::from_secs_f32(0.012),
+                    msg: StatusMsg::None,
+                },
+                TestResult {
+                    name: String::from("compares()"),
+                    classname: String::from("a.FooTest"),
+                    time: Duration::from_secs_f32(0.015),
+                    msg: StatusMsg::Failure {
+                        message: Some(String::from("org.opentest4j.AssertionFailedError: expected: <1> but was: <2>")),
+                        type_: String::from("org.opentest4j.AssertionFailedError"),
+                        full_message: Some(String::from(
+                            "org.opentest4j.AssertionFailedError: expected: <1> but was: <2>\n\tat a.FooTest.compares(FooTest.java:14)\n"
+                        )),
+                    },
+                },
+                TestResult {
+                    name: String::from("divides()"),
+                    classname: String::from("a.FooTest"),
+                    time: Duration::from_secs_f32(0.004),
+                    msg: StatusMsg::Error {
+                        message: Some(String::from("java.lang.ArithmeticException: / by zero")),
+                        type_: String::from("java.lang.ArithmeticException"),
+                        full_message: Some(String::from("java.lang.ArithmeticException: / by zero\n\tat a.Foo.divide(Foo.java:7)\n")),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_javac_output() {
+        let output = "> Task :compileJava UP-TO-DATE
+> Task :processResources NO-SOURCE
+> Task :classes UP-TO-DATE
+
+> Task :compileTestJava FAILED
+/work/student/src/test/java/a/FooTest.java:12: error: cannot find symbol
+        assertEquals(1, foo.count());
+                           ^
+  symbol:   method count()
+  location: variable foo of type Foo
+1 error
+
+FAILURE: Build failed with an exception.
+
+* What went wrong:
+Execution failed for task ':compileTestJava'.
+> Compilation failed; see the compiler error output for details.
+
+* Try:
+> Run with --info option to get more log output.
+
+BUILD FAILED in 1s
+2 actionable tasks: 1 executed, 1 up-to-date
+";
+        assert_eq!(
+            maven::parse_javac_output(output, Path::new("/work/student")),
+            vec![CompileDiagnostic {
+                file: PathBuf::from("src/test/java/a/FooTest.java"),
+                line: 12,
+                column: Some(28),
+                message: String::from("cannot find symbol\nsymbol:   method count()\nlocation: variable foo of type Foo"),
+            }]
+        );
+    }
+}
@@ -1,41 +1,134 @@
 use std::collections::HashSet;
-use std::fs::{rename, OpenOptions};
+use std::fs::{remove_dir_all, rename, OpenOptions};
 use std::path::Path;
 use std::io::{BufReader, Error, ErrorKind, Result};
-use std::process::{Command, Stdio};
+use std::process::Stdio;
 use std::str::FromStr;
 use std::time::Duration;
 
+use tempfile::tempdir_in;
 use xml::attribute::OwnedAttribute;
 use xml::name::OwnedName;
 use xml::reader::XmlEvent;
 use xml::EventReader;
 
-use crate::config::skel_dir;
-use crate::types::{StatusMsg, TestResult, TestResultError};
+use crate::config::{darwin_root, dependency_cache_dir, skel_dir};
+use crate::darwin_config::SandboxLevel;
+use crate::types::{CompileDiagnostic, CompileError, StatusMsg, TestResult, TestResultError};
 use crate::util::dir_list_absolute_file_paths_recursively;
 
-use super::Project;
+use super::{project_relative_path, sandbox, Project};
 
+/// Resolving a skeleton's dependencies can mean downloading a few hundred megabytes
+const CACHE_DEPENDENCIES_TIMEOUT: Duration = Duration::from_secs(30 * 60);
 
-pub fn compile(_: &Project, project_path: &Path) -> Result<()> {
+pub fn compile(project: &Project, project_path: &Path) -> Result<()> {
     // mvn compile
-    let mut compile_command = Command::new("mvn")
-        .current_dir(project_path)
+    let mut command = sandbox::command(project, project_path, "mvn")?;
+    command
+        .arg("--batch-mode")
+        .args(offline_args()?)
         .arg("test-compile")
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()?;
-
-    let status = compile_command.wait()?;
+        .stdin(Stdio::null());
+    let (status, output) = command.output_timeout(project.compile_timeout)?;
     if !status.success() {
-        return Err(Error::new(ErrorKind::Other, "'mvn test-compile' failed"));
+        let missing = missing_artifacts(&output);
+        let summary = match missing.is_empty() {
+            true => String::from("'mvn test-compile' failed"),
+            false => format!("'mvn test-compile' failed: {}", missing_artifacts_message(&missing)),
+        };
+        return Err(CompileError {
+            summary,
+            diagnostics: parse_javac_output(&output, project_path),
+            output,
+        }
+        .into());
     }
 
     Ok(())
 }
 
+/// Resolves everything the student builds need into a fresh repository: the dependencies and plugins, then whatever
+/// test-compile and surefire:test resolve while running, such as surefire's junit provider.
+/// Skeletons usually do not compile or pass their tests, so those two only fail on artifacts that could not be resolved.
+/// The skeleton is trusted, so this runs outside the sandbox and with network access
+pub fn cache_dependencies(project: &Project, skeleton_path: &Path) -> Result<()> {
+    let repo = tempdir_in(darwin_root())?;
+    let repo_arg = format!("-Dmaven.repo.local={}", repo.path().canonicalize()?.display());
+    let unsandboxed = project.clone().with_sandbox(SandboxLevel::None);
+    // Goals, and whether the build has to succeed
+    let goals: [(&[&str], bool); 3] = [
+        (&["dependency:go-offline"], true),
+        (&["test-compile"], false),
+        (&["-Dmaven.test.failure.ignore=true", "surefire:test"], false),
+    ];
+    for (args, must_succeed) in goals {
+        let mut command = sandbox::command(&unsandboxed, skeleton_path, "mvn")?;
+        command
+            .arg("--batch-mode")
+            .arg(&repo_arg)
+            .args(args)
+            .stdin(Stdio::null());
+        let (status, output) = command.output_timeout(CACHE_DEPENDENCIES_TIMEOUT)?;
+        if !status.success() && (must_succeed || resolution_failed(&output)) {
+            let tail: Vec<&str> = output.lines().rev().take(20).collect();
+            return Err(Error::other(format!(
+                "'mvn {}' failed in the skeleton:\n{}",
+                args.join(" "),
+                tail.into_iter().rev().collect::<Vec<&str>>().join("\n")
+            )));
+        }
+    }
+
+    let cache = dependency_cache_dir();
+    if cache.exists() {
+        remove_dir_all(&cache)?;
+    }
+    rename(repo.into_path(), &cache)?;
+    Ok(())
+}
+
+/// Once dependencies are cached, builds use only the cache, so they work without network and do not depend on `~/.m2`
+fn offline_args() -> Result<Vec<String>> {
+    let cache = dependency_cache_dir();
+    if !cache.is_dir() {
+        return Ok(Vec::new());
+    }
+    Ok(vec![
+        String::from("--offline"),
+        format!("-Dmaven.repo.local={}", cache.canonicalize()?.display()),
+    ])
+}
+
+/// Artifacts an offline build failed to find in the repository, eg. a dependency a student added to their pom.xml
+fn missing_artifacts(output: &str) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut rest = output;
+    while let Some((_, after)) = rest.split_once("in offline mode and the artifact ") {
+        let Some((artifact, after)) = after.split_once(" has not been downloaded") else {
+            break;
+        };
+        if !out.iter().any(|a| a == artifact) {
+            out.push(artifact.to_string());
+        }
+        rest = after;
+    }
+    out
+}
+
+/// Whether maven failed to download a dependency or plugin, rather than eg. to compile the code
+fn resolution_failed(output: &str) -> bool {
+    let output = output.to_lowercase();
+    ["could not resolve", "could not be resolved", "could not transfer"].iter().any(|m| output.contains(m))
+}
+
+fn missing_artifacts_message(missing: &[String]) -> String {
+    format!(
+        "pom.xml requires {}, which is not in the dependency cache. Add it to the skeleton's pom.xml and run cache-dependencies again",
+        missing.join(", ")
+    )
+}
+
 pub fn list_tests(_: &Project) -> HashSet<String> {
     let test_dir = skel_dir().join("src").join("test").join("java");
     let test_dir_str = test_dir.to_str().unwrap();
@@ -43,7 +136,7 @@ pub fn list_tests(_: &Project) -> HashSet<String> {
 
     let mut out = HashSet::new();
     for file in files {
-        if file.extension().map_or(false, |ext| ext != "java") {
+        if file.extension().is_some_and(|ext| ext != "java") {
             continue;
         }
         let file = file.strip_prefix(test_dir_str).unwrap();
@@ -55,20 +148,21 @@ pub fn list_tests(_: &Project) -> HashSet<String> {
     out
 }
 
-/// Runs `mvn -Dtest={test_str} surefire:test`
+/// Runs `mvn -Dtest={test},{test}... surefire:test`, which writes a report per test class
 /// Returns results file destination
-pub fn run_test(_: &Project, project_path: &Path, test: &str) -> Result<()> {
-
-    let mut run_tests_command = Command::new("mvn")
-        .current_dir(project_path)
-        .arg(format!("-Dtest={}", test))
+pub fn run_test(project: &Project, project_path: &Path, tests: &[String]) -> Result<()> {
+    let mut command = sandbox::command(project, project_path, "mvn")?;
+    command
+        .arg("--batch-mode")
+        .args(offline_args()?)
+        .arg(format!("-Dtest={}", tests.join(",")))
         .arg("surefire:test")
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()?;
-
-    run_tests_command.wait()?;
+        .stdin(Stdio::null());
+    let (_, output) = command.output_timeout(project.test_timeout * tests.len() as u32)?;
+    let missing = missing_artifacts(&output);
+    if !missing.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, missing_artifacts_message(&missing)));
+    }
 
     Ok(())
 }
@@ -83,7 +177,7 @@ pub fn relocate_test_results(_: &Project, project_path: &Path, test: &str, dest_
     if !results_file_from.exists() {
         return Err(Error::new(ErrorKind::NotFound, "Results file was not found"));
     }
-    rename(&results_file_from, dest_file).map_err(|e|Error::new(ErrorKind::Other, format!("Failed to rename {:?} to {:?}: {}", results_file_from, dest_file, e)))?;
+    rename(&results_file_from, dest_file).map_err(|e|Error::other(format!("Failed to rename {:?} to {:?}: {}", results_file_from, dest_file, e)))?;
     Ok(())
 }
 
@@ -91,7 +185,8 @@ pub fn parse_result_report(_: &Project, report_path: &Path, student: &str, test:
     parse_surefire_report(report_path, student, test)
 }
 
-fn parse_surefire_report(
+/// Parses a JUnit XML report as written by surefire (and gradle and pytest, which use the same format)
+pub(super) fn parse_surefire_report(
     report_path: &Path,
     student: &str,
     test: &str,
@@ -110,6 +205,8 @@ fn parse_surefire_report(
     let mut classname = String::new();
     let mut msg = StatusMsg::None;
     let mut time = Duration::new(0, 0);
+    // Only the body of <failure> and <error> is kept, not <system-out> etc.
+    let mut in_msg = false;
     for e in parser {
         match e {
             Ok(XmlEvent::StartElement {
@@ -130,10 +227,10 @@ fn parse_surefire_report(
                     time = Duration::from_secs_f32(time_seconds);
                 } else if _name == failure {
                     let message = get_attr(&attributes, "message");
-                    let type_ = get_attr(&attributes, "type").unwrap_or_else(|| {
-                        panic!("{}: XML Failure must have type attribute", name)
-                    });
+                    // pytest does not set type on failures
+                    let type_ = get_attr(&attributes, "type").unwrap_or_else(|| String::from("failure"));
                     let full_message = None;
+                    in_msg = true;
                     msg = StatusMsg::Failure {
                         message,
                         type_,
@@ -141,10 +238,9 @@ fn parse_surefire_report(
                     };
                 } else if _name == error {
                     let message = get_attr(&attributes, "message");
-                    let type_ = get_attr(&attributes, "type").unwrap_or_else(|| {
-                        panic!("{}: XML Failure must have type attribute", name)
-                    });
+                    let type_ = get_attr(&attributes, "type").unwrap_or_else(|| String::from("error"));
                     let full_message = None;
+                    in_msg = true;
                     msg = StatusMsg::Error {
                         message,
                         type_,
@@ -152,7 +248,8 @@ fn parse_surefire_report(
                     };
                 }
             }
-            Ok(XmlEvent::CData(data)) => match msg {
+            // Surefire wraps messages in CDATA, gradle escapes them
+            Ok(XmlEvent::CData(data)) | Ok(XmlEvent::Characters(data)) if in_msg => match msg {
                 StatusMsg::None => {}
                 StatusMsg::Error {
                     ref mut full_message,
@@ -167,6 +264,9 @@ fn parse_surefire_report(
                     let _ = full_message.insert(data);
                 }
             },
+            Ok(XmlEvent::EndElement { name: _name }) if _name == failure || _name == error => {
+                in_msg = false;
+            }
             Ok(XmlEvent::EndElement { name: _name }) if _name == testcase => {
                 out.push(TestResult {
                     name,
@@ -181,8 +281,7 @@ fn parse_surefire_report(
                 time = Duration::new(0, 0);
             }
             Err(e) => {
-                return Err(TestResultError::IOError(Error::new(
-                    ErrorKind::Other,
+                return Err(TestResultError::IOError(Error::other(
                     format!("Failed to parse {}'s {} test results: {}", student, test, e),
                 )));
             }
@@ -192,6 +291,79 @@ fn parse_surefire_report(
     Ok(out)
 }
 
+/// Parses javac errors out of build output, both as printed by maven (`[ERROR] /path/A.java:[12,5] message`)
+/// and by javac itself (`/path/A.java:12: error: message`, which gradle passes through).
+/// Indented detail lines such as `symbol:` and `location:` are appended to the message
+pub(super) fn parse_javac_output(output: &str, project_path: &Path) -> Vec<CompileDiagnostic> {
+    let mut out: Vec<CompileDiagnostic> = Vec::new();
+    let mut current: Option<CompileDiagnostic> = None;
+    // javac prints the offending source line, then a caret under the column
+    let mut source_lines_left = 0;
+
+    for line in output.lines() {
+        let line = line.strip_prefix("[ERROR] ").unwrap_or(line);
+        let diagnostic = parse_maven_diagnostic(line)
+            .map(|d| (d, 0))
+            .or_else(|| parse_javac_diagnostic(line).map(|d| (d, 2)));
+        if let Some(((file, line_number, column, message), source_lines)) = diagnostic {
+            out.extend(current.take());
+            current = Some(CompileDiagnostic {
+                file: project_relative_path(project_path, file),
+                line: line_number,
+                column,
+                message: message.to_string(),
+            });
+            source_lines_left = source_lines;
+            continue;
+        }
+
+        let Some(diagnostic) = current.as_mut() else {
+            continue;
+        };
+        if source_lines_left == 2 {
+            source_lines_left = 1;
+        } else if source_lines_left == 1 {
+            source_lines_left = 0;
+            diagnostic.column = line.find('^').map(|i| i + 1);
+        } else if line.starts_with("  ") && !line.starts_with("   ") {
+            diagnostic.message.push('\n');
+            diagnostic.message.push_str(line.trim());
+        } else {
+            out.extend(current.take());
+        }
+    }
+    out.extend(current);
+
+    // Maven repeats every error in its build failure summary
+    let mut unique: Vec<CompileDiagnostic> = Vec::new();
+    for diagnostic in out {
+        if !unique.contains(&diagnostic) {
+            unique.push(diagnostic);
+        }
+    }
+    unique
+}
+
+/// `/path/A.java:[12,5] message` -> (file, line, column, message)
+fn parse_maven_diagnostic(line: &str) -> Option<(&str, usize, Option<usize>, &str)> {
+    let (file, rest) = line.split_once(".java:[")?;
+    let (position, message) = rest.split_once("] ")?;
+    let (line_number, column) = position.split_once(',')?;
+    Some((
+        &line[..file.len() + 5],
+        line_number.parse().ok()?,
+        Some(column.parse().ok()?),
+        message,
+    ))
+}
+
+/// `/path/A.java:12: error: message` -> (file, line, column, message). Warnings are ignored
+fn parse_javac_diagnostic(line: &str) -> Option<(&str, usize, Option<usize>, &str)> {
+    let (file, rest) = line.split_once(".java:")?;
+    let (line_number, message) = rest.split_once(": error: ")?;
+    Some((&line[..file.len() + 5], line_number.parse().ok()?, None, message))
+}
+
 fn get_attr(owned_attributes: &[OwnedAttribute], attr: &str) -> Option<String> {
     owned_attributes.iter().find_map(|a| {
         if a.name == OwnedName::from_str(attr).unwrap() {
@@ -206,3 +378,74 @@ fn get_attr(owned_attributes: &[OwnedAttribute], attr: &str) -> Option<String> {
 // Create universal test result format for report
 
 // Display test results in report
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::types::CompileDiagnostic;
+
+    use super::{missing_artifacts, parse_javac_output};
+
+    #[test]
+    fn test_missing_artifacts() {
+        let output = "[ERROR] Failed to execute goal on project impl: Could not resolve dependencies for project a:impl:jar:1.0: \
+The following artifacts could not be resolved: com.google.guava:guava:jar:31.0-jre (absent): Cannot access central \
+(https://repo.maven.apache.org/maven2) in offline mode and the artifact com.google.guava:guava:jar:31.0-jre has not been \
+downloaded from it before. -> [Help 1]
+[ERROR] Plugin org.example:foo-maven-plugin:1.0 or one of its dependencies could not be resolved: Cannot access central \
+(https://repo.maven.apache.org/maven2) in offline mode and the artifact org.example:foo-maven-plugin:jar:1.0 has not been \
+downloaded from it before.
+[ERROR] Cannot access central in offline mode and the artifact com.google.guava:guava:jar:31.0-jre has not been downloaded from it before.
+";
+        assert_eq!(
+            missing_artifacts(output),
+            vec!["com.google.guava:guava:jar:31.0-jre", "org.example:foo-maven-plugin:jar:1.0"]
+        );
+        assert!(missing_artifacts("[ERROR] COMPILATION ERROR").is_empty());
+    }
+
+    #[test]
+    fn test_parse_javac_output() {
+        let maven = "[INFO] -------------------------------------------------------------
+[ERROR] COMPILATION ERROR : 
+[INFO] -------------------------------------------------------------
+[ERROR] /work/student/src/main/java/a/Foo.java:[4,16] cannot find symbol
+  symbol:   variable x
+  location: class a.Foo
+[INFO] 1 error
+[INFO] BUILD FAILURE
+[ERROR] Failed to execute goal org.apache.maven.plugins:maven-compiler-plugin:3.1:compile (default-compile) on project impl: Compilation failure
+[ERROR] /work/student/src/main/java/a/Foo.java:[4,16] cannot find symbol
+[ERROR]   symbol:   variable x
+[ERROR]   location: class a.Foo
+[ERROR] -> [Help 1]
+";
+        let expected = vec![CompileDiagnostic {
+            file: PathBuf::from("src/main/java/a/Foo.java"),
+            line: 4,
+            column: Some(16),
+            message: String::from("cannot find symbol\nsymbol:   variable x\nlocation: class a.Foo"),
+        }];
+        assert_eq!(parse_javac_output(maven, Path::new("/work/student")), expected);
+
+        let javac = "> Task :compileJava FAILED
+/work/student/src/main/java/a/Foo.java:4: error: cannot find symbol
+        return x;
+               ^
+  symbol:   variable x
+  location: class a.Foo
+/work/student/src/main/java/a/Foo.java:6: warning: [removal] Integer(int) in Integer has been deprecated
+/work/student/src/main/java/a/Foo.java:7: error: incompatible types: int cannot be converted to String
+    void g() { String s = 1; }
+                          ^
+2 errors
+";
+        let diagnostics = parse_javac_output(javac, Path::new("/work/student"));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0], expected[0]);
+        assert_eq!(diagnostics[1].line, 7);
+        assert_eq!(diagnostics[1].column, Some(27));
+        assert_eq!(diagnostics[1].message, "incompatible types: int cannot be converted to String");
+    }
+}
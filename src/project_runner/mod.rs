@@ -4,15 +4,34 @@ use std::{
     io::{Error, ErrorKind, Result},
     os::unix::fs::symlink,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
+use tempfile::tempdir_in;
 use zip::ZipArchive;
 
 use crate::{
-    config::{darwin_root, diff_exclude_dir, skel_dir}, darwin_config::ProjectType, types::{TestResult, TestResultError}, util::{self, create_diff, extract_zipfile, patch, path_remove_trailing_slash, project_root_in_zip}
+    config::{darwin_root, diff_exclude_dir, skel_dir, student_diff_file}, darwin_config::{student_project_root, ProjectType, SandboxLevel, DEFAULT_COMPILE_TIMEOUT_SECS, DEFAULT_TEST_TIMEOUT_SECS}, submission_blobs::restore_blobs, types::{TestResult, TestResultError}, util::{self, directory_transform, extract_zipfile, patch, path_remove_trailing_slash, project_root_in_zip}
 };
 
+mod go;
+mod gradle;
 mod maven;
+mod python;
+pub mod sandbox;
+
+/// Compiler output refers to files by absolute path or relative to the project directory.
+/// Returns the path relative to the project directory
+fn project_relative_path(project_path: &Path, file: &str) -> PathBuf {
+    let file = Path::new(file);
+    let project_path = project_path.canonicalize().unwrap_or_else(|_| project_path.to_path_buf());
+    let relative = file.strip_prefix(&project_path).unwrap_or(file);
+    relative.strip_prefix(".").unwrap_or(relative).to_path_buf()
+}
+
+/// project, report_path, student, test
+type ParseResultReportFn =
+    fn(&Project, &Path, &str, &str) -> std::result::Result<Vec<TestResult>, TestResultError>;
 
 #[derive(Clone)]
 pub struct Project {
@@ -31,6 +50,15 @@ pub struct Project {
     /// This is useful for entries that should not be modified by students, for example testfiles.
     pub diff_exclude: HashSet<PathBuf>,
 
+    /// How compile_fn and run_test_fn isolate student code. Runners must create their commands with `sandbox::command`
+    pub sandbox: SandboxLevel,
+
+    /// Wall clock limit for compile_fn. Runners must wait on their commands with `SandboxedCommand::status_timeout`
+    pub compile_timeout: Duration,
+
+    /// Wall clock limit for run_test_fn
+    pub test_timeout: Duration,
+
     /// Given a normalized skeleton (access via `skeleton_dir()`), lists all test names to be used as input for 
     /// * run_test_fn
     /// * relocate_test_results_fn
@@ -40,25 +68,38 @@ pub struct Project {
     /// Given a normalized project (`&Path`), compiles the project
     compile_fn: fn(&Project, &Path) -> Result<()>,
 
-    /// Given a normalized project (`&Path`), and a test name (`&str`), run tests and produce a test report
-    run_test_fn: fn(&Project, &Path, &str) -> Result<()>,
+    /// Given the normalized skeleton (`&Path`), resolves its dependencies into `dependency_cache_dir()`
+    cache_dependencies_fn: fn(&Project, &Path) -> Result<()>,
+
+    /// Given a normalized project (`&Path`), and test names (`&[String]`), run the tests and produce a test report for each.
+    /// Runners that can should run them all in one invocation, which may take `test_timeout` per test
+    run_test_fn: fn(&Project, &Path, &[String]) -> Result<()>,
 
     /// May remove
     relocate_test_results_fn: fn(&Project, &Path, &str, &Path) -> Result<()>, // project, project_path, test, dest_file
 
     /// Given a test report (&Path), parse into `Vec<TestResult>`
-    parse_result_report_fn:
-        fn(&Project, &Path, &str, &str) -> std::result::Result<Vec<TestResult>, TestResultError>,
+    parse_result_report_fn: ParseResultReportFn,
 }
 
 pub fn project_type_to_project(project_type: &ProjectType) -> Result<Project> {
     match project_type {
         ProjectType::None => no_project(),
         ProjectType::MavenSurefire => maven_project(), 
+        ProjectType::Gradle => gradle_project(),
         ProjectType::Go => go_project(),
+        ProjectType::Pytest => pytest_project(),
     }
 }
 
+/// Builds of these project types resolve their dependencies as usual
+fn no_dependency_cache(project: &Project, _: &Path) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        format!("{:?} projects do not support caching dependencies", project.project_type),
+    ))
+}
+
 pub fn no_project() -> Result<Project> {
     maven_project()
 }
@@ -88,6 +129,7 @@ pub fn maven_project() -> Result<Project> {
         submission_zipfile_mapping,
         ignore,
         maven::compile,
+        maven::cache_dependencies,
         maven::list_tests,
         maven::run_test,
         maven::relocate_test_results,
@@ -95,39 +137,135 @@ pub fn maven_project() -> Result<Project> {
     )
 }
 
+/// `settings.gradle` is taken from the skeleton, so students cannot change the project name or included builds
+pub fn gradle_project() -> Result<Project> {
+    let mut skel_mapping = HashMap::new();
+    skel_mapping.insert(PathBuf::from("src/main/"), PathBuf::from("src/main/"));
+    skel_mapping.insert(PathBuf::from("src/test/"), PathBuf::from("src/test/"));
+    skel_mapping.insert(PathBuf::from("build.gradle"), PathBuf::from("build.gradle"));
+    skel_mapping.insert(PathBuf::from("settings.gradle"), PathBuf::from("settings.gradle"));
+
+    let mut submission_zipfile_mapping = HashMap::new();
+    submission_zipfile_mapping.insert(PathBuf::from("build.gradle"), PathBuf::from("build.gradle"));
+    submission_zipfile_mapping.insert(PathBuf::from("src/main/"), PathBuf::from("src/main/"));
+
+    let mut ignore = HashSet::new();
+    ignore.insert(String::from(".DS_Store"));
+    ignore.insert(String::from(".gradle"));
+    ignore.insert(String::from("build"));
+    ignore.insert(String::from("gradlew"));
+    ignore.insert(String::from("gradlew.bat"));
+    ignore.insert(String::from(".settings"));
+    ignore.insert(String::from(".project"));
+    ignore.insert(String::from(".classpath"));
+    ignore.insert(String::from(".git"));
+    ignore.insert(String::from(".gitignore"));
+
+    Project::new(
+        ProjectType::Gradle,
+        skel_mapping,
+        submission_zipfile_mapping,
+        ignore,
+        gradle::compile,
+        no_dependency_cache,
+        gradle::list_tests,
+        gradle::run_test,
+        gradle::relocate_test_results,
+        gradle::parse_result_report,
+    )
+}
+
+/// Expects the skeleton to contain `go.mod` and the students' packages under `src/`, with the `*_test.go` files next to
+/// the packages they test. The skeleton's test files replace the students' before compiling
 pub fn go_project() -> Result<Project> {
-    todo!();
+    let mut skel_mapping = HashMap::new();
+    skel_mapping.insert(PathBuf::from("go.mod"), PathBuf::from("go.mod"));
+    skel_mapping.insert(PathBuf::from("src/"), PathBuf::from("src/"));
+
+    let mut submission_zipfile_mapping = HashMap::new();
+    submission_zipfile_mapping.insert(PathBuf::from("go.mod"), PathBuf::from("go.mod"));
+    submission_zipfile_mapping.insert(PathBuf::from("src/"), PathBuf::from("src/"));
+
+    let mut ignore = HashSet::new();
+    ignore.insert(String::from(".DS_Store"));
+    ignore.insert(String::from(".git"));
+    ignore.insert(String::from(".gitignore"));
+    ignore.insert(String::from(".idea"));
+    ignore.insert(String::from(".vscode"));
+
+    Project::new(
+        ProjectType::Go,
+        skel_mapping,
+        submission_zipfile_mapping,
+        ignore,
+        go::compile,
+        no_dependency_cache,
+        go::list_tests,
+        go::run_test,
+        go::relocate_test_results,
+        go::parse_result_report,
+    )
+}
+
+/// Expects the skeleton to contain the students' modules under `src/` and the pytest files under `tests/`
+pub fn pytest_project() -> Result<Project> {
+    let mut skel_mapping = HashMap::new();
+    skel_mapping.insert(PathBuf::from("src/"), PathBuf::from("src/"));
+    skel_mapping.insert(PathBuf::from("tests/"), PathBuf::from("tests/"));
+
+    let mut submission_zipfile_mapping = HashMap::new();
+    submission_zipfile_mapping.insert(PathBuf::from("src/"), PathBuf::from("src/"));
+
+    let mut ignore = HashSet::new();
+    ignore.insert(String::from(".DS_Store"));
+    ignore.insert(String::from("__pycache__"));
+    ignore.insert(String::from(".pytest_cache"));
+    ignore.insert(String::from(".venv"));
+    ignore.insert(String::from("venv"));
+    ignore.insert(String::from(".idea"));
+    ignore.insert(String::from(".vscode"));
+    ignore.insert(String::from(".git"));
+    ignore.insert(String::from(".gitignore"));
+
+    Project::new(
+        ProjectType::Pytest,
+        skel_mapping,
+        submission_zipfile_mapping,
+        ignore,
+        python::compile,
+        no_dependency_cache,
+        python::list_tests,
+        python::run_test,
+        python::relocate_test_results,
+        python::parse_result_report,
+    )
 }
 
 impl Project {
     /// skel_mapping may not map 2 src paths to the same dest path
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         project_type: ProjectType,
         skel_mapping: HashMap<PathBuf, PathBuf>,
         submission_zipfile_mapping: HashMap<PathBuf, PathBuf>,
         _ignore: HashSet<String>,
         compile_fn: fn(&Project, &Path) -> Result<()>,
+        cache_dependencies_fn: fn(&Project, &Path) -> Result<()>,
         list_tests_fn: fn(&Project) -> HashSet<String>,
-        run_test_fn: fn(&Project, &Path, &str) -> Result<()>,
+        run_test_fn: fn(&Project, &Path, &[String]) -> Result<()>,
         relocate_test_results_fn: fn(&Project, &Path, &str, &Path) -> Result<()>, // project, project_path, test, dest_file
-        parse_result_report_fn: fn(
-            &Project,
-            &Path,
-            &str,
-            &str,
-        )
-            -> std::result::Result<Vec<TestResult>, TestResultError>,
+        parse_result_report_fn: ParseResultReportFn,
     ) -> Result<Self> {
         if skel_mapping.values().collect::<HashSet<&PathBuf>>().len() != skel_mapping.values().len() {
-            return Err(Error::new(ErrorKind::Other, "skel_mapping cannot map multiple source directories to the same dest directory"));
+            return Err(Error::other("skel_mapping cannot map multiple source directories to the same dest directory"));
         }
         let skel_destinations: HashSet<PathBuf> = skel_mapping.values().cloned().collect();
         let submission_destinations: HashSet<PathBuf> =
             submission_zipfile_mapping.values().cloned().collect();
         let diff_exclude = skel_destinations
             .iter()
+            .filter(|&skel_dest| !submission_destinations.contains(skel_dest))
             .cloned()
-            .filter(|skel_dest| !submission_destinations.contains(skel_dest))
             .collect();
 
         Ok(Project {
@@ -136,7 +274,11 @@ impl Project {
             submission_zipfile_mapping,
             _ignore,
             diff_exclude,
+            sandbox: SandboxLevel::None,
+            compile_timeout: Duration::from_secs(DEFAULT_COMPILE_TIMEOUT_SECS),
+            test_timeout: Duration::from_secs(DEFAULT_TEST_TIMEOUT_SECS),
             compile_fn,
+            cache_dependencies_fn,
             list_tests_fn,
             run_test_fn,
             relocate_test_results_fn,
@@ -144,11 +286,21 @@ impl Project {
         })
     }
 
+    pub fn with_sandbox(mut self, sandbox: SandboxLevel) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    pub fn with_timeouts(mut self, compile_timeout: Duration, test_timeout: Duration) -> Self {
+        self.compile_timeout = compile_timeout;
+        self.test_timeout = test_timeout;
+        self
+    }
+
     pub fn init_skeleton(&self, skeleton_path: &Path) -> Result<()> {
         for (from, to) in self.skel_mapping.iter() {
             if !skeleton_path.join(from).exists() {
-                return Err(Error::new(
-                    ErrorKind::Other,
+                return Err(Error::other(
                     format!("Expected {:?} to exist in skeleton", from),
                 ));
             }
@@ -163,8 +315,7 @@ impl Project {
             .join(to);
             if !from.to_string_lossy().ends_with('/') {
                 if !from.is_file() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
+                    return Err(Error::other(
                         format!("Expected {:?} to be a file", &from),
                     ));
                 } else {
@@ -172,8 +323,7 @@ impl Project {
                         create_dir_all(parent)?;
                     }
                     fs::copy(&from, &to).map_err(|e| {
-                        Error::new(
-                            ErrorKind::Other,
+                        Error::other(
                             format!("Failed to copy {:?} to {:?}: {}", from, to, e),
                         )
                     })?;
@@ -181,14 +331,12 @@ impl Project {
             }
             if from.to_string_lossy().ends_with('/') {
                 if !from.is_dir() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
+                    return Err(Error::other(
                         format!("Expected {:?} to be a directory", from),
                     ));
                 } else {
                     util::copy_dir_all(&from, &to, None).map_err(|e| {
-                        Error::new(
-                            ErrorKind::Other,
+                        Error::other(
                             format!("Failed to copy {:?} to {:?}: {}", from, to, e),
                         )
                     })?;
@@ -198,12 +346,14 @@ impl Project {
         Ok(())
     }
 
+    /// Returns the directory within the zip the project was found in. This should be recorded
+    /// so `recreate_original_project` can restore the student's structure
     pub fn zip_submission_to_normalized_form(
         &self,
         zip: &mut ZipArchive<File>,
         dest_dir: &Path,
         copy_ignore_set: Option<&HashSet<&str>>,
-    ) -> Result<()> {
+    ) -> Result<PathBuf> {
         if zip.is_empty() {
             return Err(Error::new(
                 ErrorKind::NotFound,
@@ -243,19 +393,29 @@ impl Project {
             extract_zipfile(zipfile, &dest_dir.join(&dest))?;
         }
 
-        Ok(())
+        Ok(root)
     }
 
     /// Symlink all entries of self.diff_exclude in
     /// Invariants:
     /// - darwin_path is an existing .darwin project root directory
     /// - dest does not exist
-    pub fn recreate_normalized_project(&self, dest: &Path, diff_path: &Path) -> Result<()> {
+    pub fn recreate_normalized_project(&self, student: &str, dest: &Path) -> Result<()> {
         assert!(darwin_root().is_dir());
         assert!(!dest.exists());
 
-        patch(&skel_dir(), diff_path, dest, true)?;
+        patch(&skel_dir(), &student_diff_file(student), dest)?;
+        restore_blobs(&darwin_root(), student, dest)?;
+        self.link_diff_exclude(dest)
+    }
 
+    /// The skeleton as a student's project would look without any changes, eg. to resolve its dependencies in
+    pub fn recreate_normalized_skeleton(&self, dest: &Path) -> Result<()> {
+        util::copy_dir_all(&skel_dir(), dest, None)?;
+        self.link_diff_exclude(dest)
+    }
+
+    fn link_diff_exclude(&self, dest: &Path) -> Result<()> {
         for excluded in self.diff_exclude.iter() {
             let original = diff_exclude_dir().join(excluded).canonicalize()?;
             let mut link = dest.join(excluded);
@@ -264,8 +424,7 @@ impl Project {
             }
             link = path_remove_trailing_slash(&link);
             symlink(&original, &link).map_err(|e| {
-                Error::new(
-                    ErrorKind::Other,
+                Error::other(
                     format!("Failed to symlink {:?} to {:?}: {}", link, original, e),
                 )
             })?;
@@ -274,21 +433,100 @@ impl Project {
         Ok(())
     }
 
-    /// Recreates students original project structure`
-    /// May later implement symlinking all diff_exclude files back in
-    /// symlink field for now does nothing
+    /// Recreates students original project structure in place, under the root their project was found at in their zip
+    /// diff_exclude entries are symlinked in if `symlink`, otherwise copied. Copy if the files will be moved or modified
     /// 
     /// Invariants:
-    /// - project_path is an existing .darwin project root directory
-    /// - dest is an empty directory
-    pub fn recreate_original_project(&self, normalized_project: &Path, symlink: bool) -> Result<()> {
-        todo!();
+    /// - darwin_path is an existing .darwin project root directory
+    /// - normalized_project was created by `recreate_normalized_project`
+    pub fn recreate_original_project(&self, student: &str, normalized_project: &Path, symlink: bool) -> Result<()> {
+        let root = student_project_root(student)?;
+        self._recreate_original_project(normalized_project, &root, symlink)
+    }
+
+    fn _recreate_original_project(&self, normalized_project: &Path, root: &Path, symlink: bool) -> Result<()> {
+        // Remove the links first, otherwise directory_transform moves the files out of diff_exclude
+        for excluded in self.diff_exclude.iter() {
+            let link = path_remove_trailing_slash(&normalized_project.join(excluded));
+            if link.is_symlink() {
+                remove_file(&link)?;
+            }
+        }
+
+        // Inverse of submission_zipfile_mapping
+        let transformation: HashMap<PathBuf, PathBuf> = self
+            .submission_zipfile_mapping
+            .iter()
+            .map(|(from, to)| (path_remove_trailing_slash(to), root.join(path_remove_trailing_slash(from))))
+            .collect();
+        directory_transform(normalized_project, &transformation)?;
+
+        for excluded in self.diff_exclude.iter() {
+            // Inverse of skel_mapping
+            let Some(from) = self.skel_mapping.iter().find_map(|(from, to)| (to == excluded).then_some(from)) else {
+                continue;
+            };
+            let original = diff_exclude_dir().join(excluded).canonicalize()?;
+            let dest = path_remove_trailing_slash(&normalized_project.join(root).join(from));
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if symlink {
+                std::os::unix::fs::symlink(&original, &dest).map_err(|e| {
+                    Error::other(
+                        format!("Failed to symlink {:?} to {:?}: {}", dest, original, e),
+                    )
+                })?;
+            } else if original.is_dir() {
+                util::copy_dir_all(&original, &dest, None)?;
+            } else {
+                fs::copy(&original, &dest).map_err(|e| {
+                    Error::other(
+                        format!("Failed to copy {:?} to {:?}: {}", original, dest, e),
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Maps a path within a normalized project to where `recreate_original_project` puts it
+    pub fn original_path(&self, student: &str, normalized_path: &Path) -> Result<PathBuf> {
+        let root = student_project_root(student)?;
+        Ok(self._original_path(&root, normalized_path))
+    }
+
+    fn _original_path(&self, root: &Path, normalized_path: &Path) -> PathBuf {
+        // Excluded entries are restored from skel_mapping, everything else from submission_zipfile_mapping
+        let excluded = self.skel_mapping.iter().filter(|(_, to)| self.diff_exclude.contains(*to));
+        self.submission_zipfile_mapping
+            .iter()
+            .chain(excluded)
+            .filter_map(|(from, to)| {
+                let rest = normalized_path.strip_prefix(path_remove_trailing_slash(to)).ok()?;
+                Some((from, to.components().count(), rest))
+            })
+            // The most specific mapping wins
+            .max_by_key(|(_, depth, _)| *depth)
+            .map(|(from, _, rest)| {
+                let from = root.join(path_remove_trailing_slash(from));
+                if rest.as_os_str().is_empty() { from } else { from.join(rest) }
+            })
+            .unwrap_or_else(|| root.join(normalized_path))
     }
 
     pub fn compile(&self, project_path: &Path) -> Result<()> {
         (self.compile_fn)(self, project_path)
     }
 
+    /// Resolves the skeleton's dependencies into `dependency_cache_dir()`, replacing any earlier cache
+    pub fn cache_dependencies(&self) -> Result<()> {
+        let skeleton = tempdir_in(darwin_root())?;
+        self.recreate_normalized_skeleton(skeleton.path())?;
+        (self.cache_dependencies_fn)(self, skeleton.path())
+    }
+
     /// This should only be used when creating a project
     pub fn list_tests(&self) -> HashSet<String> {
         if let Err(e) = self.denormalize_skel() {
@@ -316,8 +554,7 @@ impl Project {
             let mut link = skel_dir().join(excluded);
             link = path_remove_trailing_slash(&link);
             symlink(&original, &link).map_err(|e| {
-                Error::new(
-                    ErrorKind::Other,
+                Error::other(
                     format!("Failed to symlink {:?} to {:?}: {}", link, original, e),
                 )
             })?;
@@ -335,8 +572,7 @@ impl Project {
                 continue;
             }
             remove_file(&remove).map_err(|e| {
-                Error::new(
-                    ErrorKind::Other,
+                Error::other(
                     format!("Failed to remove what should be a symlink {:?}: {}", &remove, e),
                 )
             })?;
@@ -346,8 +582,8 @@ impl Project {
 
     }
 
-    pub fn run_test(&self, project_path: &Path, test: &str) -> Result<()> {
-        (self.run_test_fn)(self, project_path, test)
+    pub fn run_test(&self, project_path: &Path, tests: &[String]) -> Result<()> {
+        (self.run_test_fn)(self, project_path, tests)
     }
 
     pub fn relocate_test_results(
@@ -370,18 +606,21 @@ impl Project {
 }
 
 
-pub mod test {
-    use std::fs::{remove_file, File};
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+    use std::fs::File;
+    use std::path::PathBuf;
     use std::{fs::remove_dir_all, path::Path};
 
-    use assert_fs::fixture::ChildPath;
     use zip::ZipArchive;
 
     use crate::config::darwin_root;
     use crate::util::create_diff;
 
-    use super::maven_project;
-    use assert_fs::{self, assert::PathAssert, prelude::{FileTouch, FileWriteStr, PathChild}};
+    use super::{maven, maven_project, Project};
+    use crate::darwin_config::ProjectType;
+    use assert_fs::{self, assert::PathAssert, prelude::PathChild};
     use predicates::prelude::*;
 
     #[test]
@@ -400,10 +639,11 @@ pub mod test {
         let file = File::open("./testing/Impl.zip").unwrap();
         let mut zip = ZipArchive::new(file).unwrap();
         let project = maven_project().unwrap();
-        project
+        let root = project
             .zip_submission_to_normalized_form(&mut zip, temp_dir.path(), None)
             .unwrap();
 
+        assert_eq!(root, Path::new("Impl"));
         temp_dir.child("src").assert(predicate::path::exists());
         temp_dir.close().unwrap();
     }
@@ -421,14 +661,65 @@ pub mod test {
             .zip_submission_to_normalized_form(&mut zip, &normalized_project_dest, None)
             .unwrap();
         normalized_project_dest.child("src").assert(predicate::path::exists());
-        create_diff(&Path::new("./testing/Skel"), &normalized_project_dest, &diff_dest)
+        create_diff(Path::new("./testing/Skel"), &normalized_project_dest, &diff_dest)
             .unwrap();
 
         normalized_project_dest.close().unwrap();
         diff_dest_dir.close().unwrap();
     }
 
+    #[test]
     fn test_recreate_original_project() {
+        // No diff_exclude entries, which would require an initialized .darwin
+        let mut mapping = HashMap::new();
+        mapping.insert(PathBuf::from("pom.xml"), PathBuf::from("pom.xml"));
+        mapping.insert(PathBuf::from("src/main/"), PathBuf::from("src/main/"));
+        let project = Project::new(
+            ProjectType::MavenSurefire,
+            mapping.clone(),
+            mapping,
+            HashSet::new(),
+            maven::compile,
+            maven::cache_dependencies,
+            maven::list_tests,
+            maven::run_test,
+            maven::relocate_test_results,
+            maven::parse_result_report,
+        )
+        .unwrap();
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let normalized_project = temp_dir.child("student");
+        let file = File::open("./testing/Impl.zip").unwrap();
+        let mut zip = ZipArchive::new(file).unwrap();
+        let root = project
+            .zip_submission_to_normalized_form(&mut zip, &normalized_project, None)
+            .unwrap();
+
+        project
+            ._recreate_original_project(&normalized_project, &root, true)
+            .unwrap();
 
+        normalized_project.child("Impl/pom.xml").assert(predicate::path::exists());
+        normalized_project
+            .child("Impl/src/main/java/test/test1/Math.java")
+            .assert(predicate::path::exists());
+        normalized_project.child("src").assert(predicate::path::missing());
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_original_path() {
+        let project = maven_project().unwrap();
+        let root = Path::new("Impl");
+        assert_eq!(
+            project._original_path(root, Path::new("src/main/java/a/Foo.java")),
+            Path::new("Impl/src/main/java/a/Foo.java")
+        );
+        assert_eq!(
+            project._original_path(root, Path::new("src/test/java/a/FooTest.java")),
+            Path::new("Impl/src/test/java/a/FooTest.java")
+        );
+        assert_eq!(project._original_path(root, Path::new("pom.xml")), Path::new("Impl/pom.xml"));
     }
 }
@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+use std::fs::{self, create_dir_all, rename};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::config::skel_dir;
+use crate::types::{CompileDiagnostic, CompileError, TestResult, TestResultError};
+use crate::util::dir_list_absolute_file_paths_recursively;
+
+use super::{maven, project_relative_path, sandbox, Project};
+
+/// Directory (relative to the project root) containing the students' modules
+const SRC_DIR: &str = "src";
+
+/// Directory (relative to the project root) containing the `test_*.py` files
+const TEST_DIR: &str = "tests";
+
+/// Directory (relative to the project root) the junit xml reports are written to
+const REPORT_DIR: &str = "test-reports";
+
+/// Byte compiles every python file so syntax errors are reported as compilation errors
+pub fn compile(project: &Project, project_path: &Path) -> Result<()> {
+    let files: Vec<PathBuf> = [SRC_DIR, TEST_DIR]
+        .iter()
+        .flat_map(|dir| dir_list_absolute_file_paths_recursively(&project_path.join(dir)))
+        .filter(|file| file.extension().is_some_and(|ext| ext == "py"))
+        // py_compile runs inside the project directory
+        .filter_map(|file| file.strip_prefix(project_path).ok().map(Path::to_path_buf))
+        .collect();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let mut command = sandbox::command(project, project_path, "python3")?;
+    command
+        // tests/ is symlinked into diff_exclude, keep the bytecode out of it
+        .env("PYTHONPYCACHEPREFIX", ".pycache")
+        .arg("-m")
+        .arg("py_compile")
+        .args(files)
+        .stdin(Stdio::null());
+    let (status, output) = command.output_timeout(project.compile_timeout)?;
+    if !status.success() {
+        return Err(CompileError {
+            summary: String::from("'python3 -m py_compile' failed"),
+            diagnostics: parse_py_compile_output(&output, project_path),
+            output,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Parses the traceback py_compile prints for the first file with a syntax error:
+/// `File "src/a.py", line 3`, the source line, a caret line, then `SyntaxError: message`
+fn parse_py_compile_output(output: &str, project_path: &Path) -> Vec<CompileDiagnostic> {
+    let mut out = Vec::new();
+    let mut lines = output.lines();
+    while let Some(line) = lines.next() {
+        let Some((file, line_number)) = line
+            .trim_start()
+            .strip_prefix("File \"")
+            .and_then(|rest| rest.split_once("\", line "))
+        else {
+            continue;
+        };
+        let Ok(line_number) = line_number.trim().parse() else {
+            continue;
+        };
+        // The exception is the first line that is not indented
+        let Some(message) = lines.by_ref().find(|l| !l.starts_with(' ')) else {
+            continue;
+        };
+        out.push(CompileDiagnostic {
+            file: project_relative_path(project_path, file),
+            line: line_number,
+            column: None,
+            message: message.to_string(),
+        });
+    }
+    out
+}
+
+/// Lists every `test_*.py` file under `tests/` that declares at least one test function as `package.test_module`
+pub fn list_tests(_: &Project) -> HashSet<String> {
+    let test_dir = skel_dir().join(TEST_DIR);
+    let files = dir_list_absolute_file_paths_recursively(&test_dir);
+
+    let mut out = HashSet::new();
+    for file in files {
+        let is_test_file = file
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .is_some_and(|n| n.starts_with("test_") && n.ends_with(".py"));
+        if !is_test_file || !has_test_functions(&file) {
+            continue;
+        }
+        let file = file.strip_prefix(&test_dir).unwrap().with_extension("");
+        let test_name = file.to_string_lossy().replace('/', ".");
+        out.insert(test_name);
+    }
+
+    out
+}
+
+/// Runs each test separately, since pytest writes a single junit xml report for all of them
+pub fn run_test(project: &Project, project_path: &Path, tests: &[String]) -> Result<()> {
+    for test in tests {
+        run_test_file(project, project_path, test)?;
+    }
+    Ok(())
+}
+
+/// Runs `python3 -m pytest tests/{test}.py --junitxml=test-reports/TEST-{test}.xml` with `src/` on the PYTHONPATH
+fn run_test_file(project: &Project, project_path: &Path, test: &str) -> Result<()> {
+    let report_dir = project_path.join(REPORT_DIR);
+    create_dir_all(&report_dir)?;
+
+    let mut command = sandbox::command(project, project_path, "python3")?;
+    command
+        .env("PYTHONPATH", SRC_DIR)
+        .env("PYTHONDONTWRITEBYTECODE", "1")
+        .arg("-m")
+        .arg("pytest")
+        .arg(test_file_path(test))
+        .arg(format!("--junitxml={}/{}", REPORT_DIR, report_file_name(test)))
+        .arg("-p")
+        .arg("no:cacheprovider")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command.status_timeout(project.test_timeout)?;
+
+    Ok(())
+}
+
+pub fn relocate_test_results(_: &Project, project_path: &Path, test: &str, dest_file: &Path) -> Result<()> {
+    let results_file_from = project_path.join(REPORT_DIR).join(report_file_name(test));
+
+    if !results_file_from.exists() {
+        return Err(Error::new(ErrorKind::NotFound, "Results file was not found"));
+    }
+    rename(&results_file_from, dest_file).map_err(|e|Error::other(format!("Failed to rename {:?} to {:?}: {}", results_file_from, dest_file, e)))?;
+    Ok(())
+}
+
+/// pytest writes the same junit xml format as surefire
+pub fn parse_result_report(_: &Project, report_path: &Path, student: &str, test: &str) -> std::result::Result<Vec<TestResult>, TestResultError> {
+    maven::parse_surefire_report(report_path, student, test)
+}
+
+/// `pkg.test_math` -> `tests/pkg/test_math.py`
+fn test_file_path(test: &str) -> PathBuf {
+    let mut path = PathBuf::from(TEST_DIR);
+    path.extend(test.split('.'));
+    path.set_extension("py");
+    path
+}
+
+fn report_file_name(test: &str) -> String {
+    format!("TEST-{}.xml", test)
+}
+
+/// Whether the file declares a `def test_*` function, either top level or as a method of a `Test*` class
+fn has_test_functions(test_file: &Path) -> bool {
+    let Ok(contents) = fs::read_to_string(test_file) else {
+        return false;
+    };
+    contents.lines().any(|line| {
+        let line = line.trim_start();
+        line.strip_prefix("async ")
+            .unwrap_or(line)
+            .starts_with("def test")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::types::StatusMsg;
+
+    use super::{maven, parse_py_compile_output, test_file_path};
+
+    #[test]
+    fn test_parse_pytest_report() {
+        let mut report = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            report,
+            r#"<?xml version="1.0" encoding="utf-8"?><testsuites><testsuite name="pytest" errors="0" failures="1" skipped="0" tests="2" time="0.02"><testcase classname="test_math" name="test_add" time="0.001" /><testcase classname="test_math" name="test_sub" time="0.002"><failure message="assert 2 == 1">def test_sub():
+&gt;       assert sub(3, 1) == 1
+E       assert 2 == 1</failure><system-out>captured</system-out></testcase></testsuite></testsuites>"#
+        )
+        .unwrap();
+        report.flush().unwrap();
+
+        let results = maven::parse_surefire_report(report.path(), "student", "test_math").unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].msg, StatusMsg::None);
+        assert_eq!(
+            results[1].msg,
+            StatusMsg::Failure {
+                message: Some(String::from("assert 2 == 1")),
+                type_: String::from("failure"),
+                full_message: Some(String::from("def test_sub():\n>       assert sub(3, 1) == 1\nE       assert 2 == 1")),
+            }
+        );
+        assert_eq!(test_file_path("pkg.test_math").to_string_lossy(), "tests/pkg/test_math.py");
+    }
+
+    #[test]
+    fn test_parse_py_compile_output() {
+        let output = "  File \"src/calc.py\", line 1\n    def add(a,b) return a+b\n                 ^^^^^^\nSyntaxError: expected ':'\n";
+        let diagnostics = parse_py_compile_output(output, std::path::Path::new("/work/student"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file.to_string_lossy(), "src/calc.py");
+        assert_eq!(diagnostics[0].line, 1);
+        assert_eq!(diagnostics[0].message, "SyntaxError: expected ':'");
+    }
+}
@@ -0,0 +1,401 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::ops::{Deref, DerefMut};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tempfile::{tempdir, tempfile, TempDir};
+
+use crate::config::dependency_cache_dir;
+use crate::darwin_config::SandboxLevel;
+
+use super::Project;
+
+/// Toolchains and dependency caches commonly installed into the home directory.
+/// Mounted read only into the scratch home so builds keep working.
+const SHARED_HOME_PATHS: [&str; 6] = [
+    ".m2/repository",
+    "go/pkg/mod",
+    ".pyenv",
+    ".local/lib",
+    ".local/bin",
+    ".sdkman",
+];
+
+/// Sets up the mounts for `SandboxLevel::Full` when bubblewrap is not available, then execs the command.
+/// Arguments: project dir, home dir, scratch dir, number of shared paths, shared paths relative to home, command...
+///
+/// The project is stashed in the scratch dir before the home directory is hidden, since the project
+/// usually lives inside the home directory. Mountinfo escapes spaces and backslashes in mount points as
+/// octal, eg. `\040`, which `printf %b` decodes
+const UNSHARE_SCRIPT: &str = r#"set -e
+P="$1"; H="$2"; T="$3"; N="$4"; shift 4
+mount --bind "$P" "$T/project"
+while [ "$N" -gt 0 ]; do
+    c="$1"; shift; N=$((N - 1))
+    if [ -d "$H/$c" ]; then
+        mkdir -p "$T/home/$c"
+        mount --bind "$H/$c" "$T/home/$c"
+    fi
+done
+mount --rbind "$T/home" "$H"
+mkdir -p "$P"
+mount --bind "$T/project" "$P"
+cut -d ' ' -f 5 /proc/self/mountinfo | sort -r | while IFS= read -r m; do
+    m=$(printf '%b' "$m")
+    if [ "$m" != "$P" ] && [ "$m" != "$H" ]; then
+        mount -o remount,bind,ro "$m" 2>/dev/null || true
+    fi
+done
+cd "$P"
+exec "$@""#;
+
+/// How often a running command is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long starting an empty sandbox may take before it is considered broken
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command wrapped according to the project's `SandboxLevel`.
+///
+/// Owns the scratch directory used by the sandbox, so it must be kept alive until the process exits
+pub struct SandboxedCommand {
+    command: Command,
+    _scratch: Option<TempDir>,
+}
+
+impl SandboxedCommand {
+    /// Spawns the command in its own process group and waits for it to exit.
+    ///
+    /// If it is still running after `timeout` the whole process group, including any JVMs or test
+    /// processes it forked, is killed and an `ErrorKind::TimedOut` error is returned. Processes left
+    /// behind by a command that exits on its own are killed as well
+    pub fn status_timeout(&mut self, timeout: Duration) -> Result<ExitStatus> {
+        let mut child = self.command.process_group(0).spawn()?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                kill_process_group(&child);
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                kill_process_group(&child);
+                child.wait()?;
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("Timed out after {}s", timeout.as_secs()),
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Like `status_timeout`, but also returns everything the command wrote to stdout and stderr
+    pub fn output_timeout(&mut self, timeout: Duration) -> Result<(ExitStatus, String)> {
+        // A file rather than pipes, so a chatty build cannot block on a full pipe while it is polled
+        let mut log = tempfile()?;
+        self.command.stdout(log.try_clone()?).stderr(log.try_clone()?);
+        let status = self.status_timeout(timeout)?;
+
+        let mut output = Vec::new();
+        log.seek(SeekFrom::Start(0))?;
+        log.read_to_end(&mut output)?;
+        Ok((status, String::from_utf8_lossy(&output).into_owned()))
+    }
+}
+
+impl Deref for SandboxedCommand {
+    type Target = Command;
+
+    fn deref(&self) -> &Command {
+        &self.command
+    }
+}
+
+impl DerefMut for SandboxedCommand {
+    fn deref_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+}
+
+/// Creates a command running `program` in `project_path`, isolated according to `project.sandbox`.
+/// Arguments should be added to the returned command as usual
+pub fn command(project: &Project, project_path: &Path, program: &str) -> Result<SandboxedCommand> {
+    let project_path = project_path.canonicalize()?;
+    match project.sandbox {
+        SandboxLevel::None => {
+            let mut command = Command::new(program);
+            command.current_dir(&project_path);
+            Ok(SandboxedCommand { command, _scratch: None })
+        }
+        SandboxLevel::Network => {
+            require_on_path("unshare", project.sandbox)?;
+            let mut command = Command::new("unshare");
+            command
+                .current_dir(&project_path)
+                .args(["--user", "--map-root-user", "--net", "--fork", "--"])
+                .arg(program);
+            Ok(SandboxedCommand { command, _scratch: None })
+        }
+        SandboxLevel::Full if on_path("bwrap") => bwrap_command(&project_path, program),
+        SandboxLevel::Full => {
+            require_on_path("unshare", project.sandbox)?;
+            unshare_command(&project_path, program)
+        }
+    }
+}
+
+/// Runs `true` inside the sandbox, so missing tools or disabled user namespaces are reported
+/// once instead of as a compile error for every student
+pub fn check(project: &Project) -> Result<()> {
+    if project.sandbox == SandboxLevel::None {
+        return Ok(());
+    }
+    let dir = tempdir()?;
+    let mut command = command(project, dir.path(), "true")?;
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+    let status = command.status_timeout(CHECK_TIMEOUT).map_err(|e| {
+        if e.kind() == ErrorKind::TimedOut {
+            Error::new(
+                ErrorKind::TimedOut,
+                format!("{:?} sandbox did not start within {}s", project.sandbox, CHECK_TIMEOUT.as_secs()),
+            )
+        } else {
+            e
+        }
+    })?;
+    if !status.success() {
+        return Err(Error::other(format!(
+            "Failed to start {:?} sandbox. User namespaces may be disabled on this machine, \
+            run `darwin_cli set-sandbox none` to run without one",
+            project.sandbox
+        )));
+    }
+    Ok(())
+}
+
+fn bwrap_command(project_path: &Path, program: &str) -> Result<SandboxedCommand> {
+    let home = home_dir()?;
+    let mut command = Command::new("bwrap");
+    command
+        .current_dir(project_path)
+        .args(["--unshare-all", "--die-with-parent", "--new-session"])
+        .args(["--ro-bind", "/", "/"])
+        .args(["--dev", "/dev"])
+        .args(["--proc", "/proc"])
+        .args(["--tmpfs", "/tmp"])
+        .arg("--tmpfs")
+        .arg(&home);
+    for shared in shared_paths(&home) {
+        let shared = home.join(shared);
+        command.arg("--ro-bind-try").arg(&shared).arg(&shared);
+    }
+    command
+        .arg("--bind")
+        .arg(project_path)
+        .arg(project_path)
+        .arg("--chdir")
+        .arg(project_path)
+        .arg("--setenv")
+        .arg("HOME")
+        .arg(&home)
+        .arg("--")
+        .arg(program);
+    Ok(SandboxedCommand { command, _scratch: None })
+}
+
+fn unshare_command(project_path: &Path, program: &str) -> Result<SandboxedCommand> {
+    let home = home_dir()?;
+    let scratch = tempdir()?;
+    std::fs::create_dir(scratch.path().join("project"))?;
+    std::fs::create_dir(scratch.path().join("home"))?;
+
+    let mut command = Command::new("unshare");
+    command
+        .current_dir(project_path)
+        .args(["--user", "--map-root-user", "--mount", "--net", "--fork", "--"])
+        .arg("sh")
+        .arg("-c")
+        .arg(UNSHARE_SCRIPT)
+        .arg("sh")
+        .arg(project_path)
+        .arg(&home)
+        .arg(scratch.path());
+    let shared = shared_paths(&home);
+    command
+        .arg(shared.len().to_string())
+        .args(shared)
+        .arg(program)
+        .env("TMPDIR", &home);
+    Ok(SandboxedCommand { command, _scratch: Some(scratch) })
+}
+
+/// Paths relative to `home` that stay readable once it is hidden: `SHARED_HOME_PATHS`, and the project's dependency
+/// cache when .darwin is inside the home directory. Anything outside it is readable anyway
+fn shared_paths(home: &Path) -> Vec<PathBuf> {
+    let mut out: Vec<PathBuf> = SHARED_HOME_PATHS.iter().map(PathBuf::from).collect();
+    let home = home.canonicalize().unwrap_or_else(|_| home.to_path_buf());
+    let cache = dependency_cache_dir().canonicalize().ok();
+    if let Some(cache) = cache.as_ref().and_then(|cache| cache.strip_prefix(&home).ok()) {
+        out.push(cache.to_path_buf());
+    }
+    out
+}
+
+/// The child was spawned with `process_group(0)`, so its pid is also the id of its process group
+fn kill_process_group(child: &Child) {
+    // Fails with ESRCH once every process in the group has exited, which is fine
+    unsafe {
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+fn home_dir() -> Result<PathBuf> {
+    env::var_os("HOME")
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "HOME is not set"))
+}
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| dir.join(OsStr::new(program)).is_file())
+    })
+}
+
+fn require_on_path(program: &str, level: SandboxLevel) -> Result<()> {
+    if !on_path(program) {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{:?} sandbox requires '{}' on the PATH, or run `darwin_cli set-sandbox none`", level, program),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::net::TcpListener;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    use tempfile::tempdir;
+
+    use crate::darwin_config::SandboxLevel;
+    use crate::project_runner::{maven_project, Project};
+
+    use super::{check, command, on_path};
+
+    /// None when the machine cannot start the sandbox, eg. because user namespaces are disabled
+    fn full_sandbox() -> Option<Project> {
+        let project = maven_project().unwrap().with_sandbox(SandboxLevel::Full);
+        match check(&project) {
+            Ok(()) => Some(project),
+            Err(e) => {
+                eprintln!("Skipping: {}", e);
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_sandbox_only_writes_project() {
+        let Some(project) = full_sandbox() else {
+            return;
+        };
+        let dir = tempdir().unwrap();
+        // Mountinfo escapes the space, which must not make the project read only
+        let project_path = dir.path().join("student project");
+        std::fs::create_dir(&project_path).unwrap();
+
+        let mut student = command(&project, &project_path, "sh").unwrap();
+        student.arg("-c").arg("echo a > inside; echo b > ../outside");
+        student.status_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(std::fs::read_to_string(project_path.join("inside")).unwrap(), "a\n");
+        assert!(!dir.path().join("outside").exists());
+    }
+
+    #[test]
+    fn test_full_sandbox_makes_mounts_with_spaces_read_only() {
+        if !on_path("unshare") {
+            eprintln!("Skipping: unshare is needed to mount outside the sandbox");
+            return;
+        }
+        let Some(project) = full_sandbox() else {
+            return;
+        };
+        let dir = tempdir().unwrap();
+        let shared = dir.path().join("shared dir");
+        let project_path = dir.path().join("project");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::create_dir(&project_path).unwrap();
+
+        let mut student = command(&project, &project_path, "sh").unwrap();
+        student.arg("-c").arg("echo b > \"$1/f\"").arg("sh").arg(&shared);
+        // Mountinfo lists the mount point as `shared\040dir`
+        let mut mounted = Command::new("unshare");
+        mounted
+            .args(["--user", "--map-root-user", "--mount", "--fork", "--"])
+            .args(["sh", "-c", r#"mount --bind "$0" "$0" && exec "$@""#])
+            .arg(&shared)
+            .arg(student.get_program())
+            .args(student.get_args())
+            .envs(student.get_envs().filter_map(|(key, value)| Some((key, value?))))
+            .current_dir(&project_path);
+        mounted.status().unwrap();
+        assert!(!shared.join("f").exists());
+    }
+
+    #[test]
+    fn test_full_sandbox_blocks_network() {
+        if !on_path("bash") {
+            eprintln!("Skipping: bash is needed to connect");
+            return;
+        }
+        let Some(project) = full_sandbox() else {
+            return;
+        };
+        let dir = tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connect = format!("echo > /dev/tcp/127.0.0.1/{}", listener.local_addr().unwrap().port());
+
+        let mut unsandboxed = command(&maven_project().unwrap(), dir.path(), "bash").unwrap();
+        unsandboxed.arg("-c").arg(&connect);
+        assert!(unsandboxed.status_timeout(Duration::from_secs(10)).unwrap().success());
+
+        let mut sandboxed = command(&project, dir.path(), "bash").unwrap();
+        sandboxed.arg("-c").arg(&connect);
+        assert!(!sandboxed.status_timeout(Duration::from_secs(10)).unwrap().success());
+    }
+
+    #[test]
+    fn test_status_timeout_kills_process_group() {
+        let project = maven_project().unwrap();
+        let dir = tempdir().unwrap();
+
+        let mut quick = command(&project, dir.path(), "true").unwrap();
+        assert!(quick.status_timeout(Duration::from_secs(10)).unwrap().success());
+
+        let start = Instant::now();
+        let mut hanging = command(&project, dir.path(), "sh").unwrap();
+        hanging.arg("-c").arg("sleep 30 & echo $! > pid; wait");
+        let err = hanging.status_timeout(Duration::from_millis(500)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // The backgrounded sleep is reparented once sh is killed and may linger as a zombie until reaped
+        let pid = std::fs::read_to_string(dir.path().join("pid")).unwrap();
+        let stat = format!("/proc/{}/stat", pid.trim());
+        let alive = || std::fs::read_to_string(&stat).is_ok_and(|s| !s.contains(") Z "));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while alive() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(!alive());
+    }
+}
@@ -0,0 +1,502 @@
+//! Every student's test results, compile errors and timeouts, in results.json keyed by student and then test. Test
+//! reports and build output are kept beside it in reports/, named by the sha256 of their content. Updates hold a lock
+//! on results.lock, so students tested in parallel do not overwrite each other's results, and replace results.json
+//! whole, so it can be read without the lock
+//!
+//! Projects created before the store kept results in `results/{student}_{test}` files and compile errors and timeouts
+//! as lines in text files, which are migrated the first time the store is used
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Error, ErrorKind, Result, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{
+    config::{
+        darwin_root, in_darwin_root, legacy_compile_errors_file, legacy_compile_output_dir,
+        legacy_results_dir, legacy_student_compile_output_file, legacy_student_result_file, legacy_timeouts_file,
+        report_file, reports_dir, result_store_file, result_store_lock_file,
+    },
+    darwin_config::read_config,
+    list_students::list_students,
+    submission_blobs::store_content_addressed,
+    util::is_student_line,
+};
+
+/// Version of results.json this build writes. Newer stores are refused rather than misread
+pub const RESULT_STORE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResultStore {
+    pub version: u32,
+    /// By student, then test
+    pub results: BTreeMap<String, BTreeMap<String, TestRecord>>,
+    /// By student. A project that fails to compile fails every test
+    pub compile_errors: BTreeMap<String, CompileRecord>,
+    /// Journal of test runs by student and then test, so runs that were interrupted or failed can be found
+    #[serde(default)]
+    pub runs: BTreeMap<String, BTreeMap<String, RunEntry>>,
+}
+
+impl Default for ResultStore {
+    fn default() -> Self {
+        ResultStore {
+            version: RESULT_STORE_VERSION,
+            results: BTreeMap::new(),
+            compile_errors: BTreeMap::new(),
+            runs: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// The test ran, and its report says which test cases passed
+    Completed,
+    /// Compiling or running the test was killed for exceeding its time limit
+    Timeout,
+}
+
+/// One run of a test on a student's project. Timings are missing from results migrated from before the store
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TestRecord {
+    pub status: RunStatus,
+    /// Seconds since the unix epoch
+    pub finished_at: Option<u64>,
+    pub compile_secs: Option<f64>,
+    pub test_secs: Option<f64>,
+    /// Hash of the test report in reports/, which only completed runs have
+    pub report: Option<String>,
+}
+
+/// Where a student's run of a test got to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunState {
+    /// Waiting for a thread
+    Queued,
+    /// Started, or interrupted if darwin is no longer running
+    Running,
+    /// Its result, compile error or timeout is recorded
+    Done,
+    /// Ended without a result, eg. because the build tool crashed or the report was missing
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunEntry {
+    pub state: RunState,
+    /// Seconds since the unix epoch
+    pub updated_at: u64,
+    /// Why a failed run failed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompileRecord {
+    /// The test the project was compiled for. Unknown for compile errors migrated from before the store
+    pub test: Option<String>,
+    /// One line description, eg. `'mvn test-compile' failed`
+    pub reason: String,
+    /// Seconds since the unix epoch
+    pub finished_at: Option<u64>,
+    pub compile_secs: Option<f64>,
+    /// Hash of the CompileError json in reports/, with the build output and diagnostics
+    pub output: Option<String>,
+}
+
+impl ResultStore {
+    pub fn test_record(&self, student: &str, test: &str) -> Option<&TestRecord> {
+        self.results.get(student)?.get(test)
+    }
+
+    pub fn insert_test_record(&mut self, student: &str, test: &str, record: TestRecord) {
+        self.results.entry(student.to_string()).or_default().insert(test.to_string(), record);
+    }
+
+    pub fn has_compile_error(&self, student: &str) -> bool {
+        self.compile_errors.contains_key(student)
+    }
+
+    pub fn run_state(&self, student: &str, test: &str) -> Option<RunState> {
+        Some(self.runs.get(student)?.get(test)?.state)
+    }
+
+    pub fn set_run_state(&mut self, student: &str, test: &str, state: RunState, error: Option<String>) {
+        let entry = RunEntry { state, updated_at: unix_time(), error };
+        self.runs.entry(student.to_string()).or_default().insert(test.to_string(), entry);
+    }
+
+    /// Students and tests of the runs in one of the states, by test and then student
+    pub fn runs_in(&self, states: &[RunState]) -> BTreeMap<String, Vec<String>> {
+        let mut runs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (student, tests) in &self.runs {
+            for (test, entry) in tests {
+                if states.contains(&entry.state) {
+                    runs.entry(test.clone()).or_default().push(student.clone());
+                }
+            }
+        }
+        runs
+    }
+
+    /// Forgets the student's results, compile error and runs. Returns the reports no other result refers to
+    pub fn remove_student(&mut self, student: &str) -> Vec<String> {
+        self.runs.remove(student);
+        let mut removed: Vec<String> = self
+            .results
+            .remove(student)
+            .into_iter()
+            .flat_map(|tests| tests.into_values())
+            .filter_map(|record| record.report)
+            .chain(self.compile_errors.remove(student).and_then(|record| record.output))
+            .collect();
+        self.retain_unreferenced(&mut removed);
+        removed
+    }
+
+    /// Forgets the student's results and runs of `tests`, and their compile error since the project has to be compiled
+    /// again. Returns the reports no other result refers to
+    pub fn remove_student_tests(&mut self, student: &str, tests: &[String]) -> Vec<String> {
+        let mut removed = Vec::new();
+        if let Some(results) = self.results.get_mut(student) {
+            removed.extend(tests.iter().filter_map(|test| results.remove(test)?.report));
+            if results.is_empty() {
+                self.results.remove(student);
+            }
+        }
+        if let Some(runs) = self.runs.get_mut(student) {
+            runs.retain(|test, _| !tests.contains(test));
+            if runs.is_empty() {
+                self.runs.remove(student);
+            }
+        }
+        removed.extend(self.compile_errors.remove(student).and_then(|record| record.output));
+        self.retain_unreferenced(&mut removed);
+        removed
+    }
+
+    pub fn rename_student(&mut self, from: &str, to: &str) {
+        if let Some(tests) = self.results.remove(from) {
+            self.results.insert(to.to_string(), tests);
+        }
+        if let Some(compile_error) = self.compile_errors.remove(from) {
+            self.compile_errors.insert(to.to_string(), compile_error);
+        }
+        if let Some(runs) = self.runs.remove(from) {
+            self.runs.insert(to.to_string(), runs);
+        }
+    }
+
+    fn retain_unreferenced(&self, hashes: &mut Vec<String>) {
+        let referenced = self.reports();
+        hashes.retain(|hash| !referenced.contains(hash.as_str()));
+        hashes.sort();
+        hashes.dedup();
+    }
+
+    /// Hashes of every report referred to
+    fn reports(&self) -> HashSet<&str> {
+        self.results
+            .values()
+            .flat_map(|tests| tests.values())
+            .filter_map(|record| record.report.as_deref())
+            .chain(self.compile_errors.values().filter_map(|record| record.output.as_deref()))
+            .collect()
+    }
+}
+
+/// Seconds since the unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+pub fn read_result_store() -> Result<ResultStore> {
+    let path = result_store_file();
+    if path.is_file() {
+        return read_store_file(&path);
+    }
+    // Migrates the results of a project created before the store
+    update_result_store(|_| ())?;
+    read_store_file(&path)
+}
+
+/// Applies `update` to the store while no one else can
+pub fn update_result_store<T>(update: impl FnOnce(&mut ResultStore) -> T) -> Result<T> {
+    let root = darwin_root();
+    let _lock = lock_result_store(&root)?;
+    let path = result_store_file();
+    let migrate = !path.is_file();
+    let mut store = if migrate {
+        migrate_legacy_results(&root, &list_students(), &read_config()?.tests)?
+    } else {
+        read_store_file(&path)?
+    };
+
+    let out = update(&mut store);
+    write_store_file(&root, &store)?;
+    // Only once their results are safely in the store
+    if migrate && legacy_results_exist(&root) {
+        remove_legacy_results(&root)?;
+        eprintln!("Moved results to {}", path.display());
+    }
+    Ok(out)
+}
+
+/// Empties the store and removes every report
+pub fn clear_result_store() -> Result<()> {
+    let root = darwin_root();
+    let _lock = lock_result_store(&root)?;
+    write_store_file(&root, &ResultStore::default())?;
+    if reports_dir().is_dir() {
+        fs::remove_dir_all(reports_dir())?;
+    }
+    remove_legacy_results(&root)
+}
+
+/// Forgets the student's results and compile error, so their tests run again
+pub fn remove_student_results(student: &str) -> Result<()> {
+    remove_reports(update_result_store(|store| store.remove_student(student))?)
+}
+
+/// Forgets the student's results of `tests` and their compile error, so those tests run again
+pub fn remove_student_test_results(student: &str, tests: &[String]) -> Result<()> {
+    remove_reports(update_result_store(|store| store.remove_student_tests(student, tests))?)
+}
+
+fn remove_reports(hashes: Vec<String>) -> Result<()> {
+    for hash in hashes {
+        let report = report_file(&hash);
+        if report.is_file() {
+            fs::remove_file(report)?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds a test report or build output to reports/, returning its hash
+pub fn store_report(content: &[u8]) -> Result<String> {
+    store_content_addressed(&darwin_root(), &reports_dir(), content)
+}
+
+/// Released when dropped
+struct StoreLock {
+    _file: File,
+}
+
+fn lock_result_store(root: &Path) -> Result<StoreLock> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(in_darwin_root(root, &result_store_lock_file()))?;
+    // Separate opens of the file are separate locks, so threads exclude each other as well as processes
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(StoreLock { _file: file })
+}
+
+fn read_store_file(path: &Path) -> Result<ResultStore> {
+    let store: ResultStore = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|e| Error::other(format!("Failed to parse {}: {}", path.display(), e)))?;
+    if store.version > RESULT_STORE_VERSION {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("{} was written by a newer version of darwin", path.display()),
+        ));
+    }
+    Ok(store)
+}
+
+fn write_store_file(root: &Path, store: &ResultStore) -> Result<()> {
+    // Next to results.json, so it is replaced in one rename
+    let tmp = NamedTempFile::new_in(root)?;
+    let mut writer = BufWriter::new(tmp.as_file());
+    serde_json::to_writer(&mut writer, store)?;
+    writer.flush()?;
+    drop(writer);
+    tmp.persist(in_darwin_root(root, &result_store_file()))?;
+    Ok(())
+}
+
+fn legacy_results_exist(root: &Path) -> bool {
+    in_darwin_root(root, &legacy_results_dir()).is_dir()
+        || in_darwin_root(root, &legacy_compile_errors_file()).is_file()
+        || in_darwin_root(root, &legacy_timeouts_file()).is_file()
+        || in_darwin_root(root, &legacy_compile_output_dir()).is_dir()
+}
+
+/// Builds a store from the results kept before it, storing their reports. `root` is the .darwin folder
+fn migrate_legacy_results(root: &Path, students: &[String], tests: &[String]) -> Result<ResultStore> {
+    let mut store = ResultStore::default();
+    let reports = in_darwin_root(root, &reports_dir());
+
+    for student in students {
+        for test in tests {
+            let legacy = in_darwin_root(root, &legacy_student_result_file(student, test));
+            if !legacy.is_file() {
+                continue;
+            }
+            let finished_at = fs::metadata(&legacy)?
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            let report = store_content_addressed(root, &reports, &fs::read(&legacy)?)?;
+            let record = TestRecord {
+                status: RunStatus::Completed,
+                finished_at,
+                compile_secs: None,
+                test_secs: None,
+                report: Some(report),
+            };
+            store.insert_test_record(student, test, record);
+        }
+    }
+
+    // `student:test` lines
+    let runs: HashMap<String, (&String, &String)> = students
+        .iter()
+        .flat_map(|student| tests.iter().map(move |test| (format!("{}:{}", student, test), (student, test))))
+        .collect();
+    for line in read_lines(&in_darwin_root(root, &legacy_timeouts_file()))? {
+        if let Some((student, test)) = runs.get(&line) {
+            let record =
+                TestRecord { status: RunStatus::Timeout, finished_at: None, compile_secs: None, test_secs: None, report: None };
+            store.insert_test_record(student, test, record);
+        }
+    }
+
+    // `student:reason` lines. The longest name matching is the student's, in case another student's name is the start
+    // of theirs followed by a `:`
+    for line in read_lines(&in_darwin_root(root, &legacy_compile_errors_file()))? {
+        let Some(student) = students.iter().filter(|student| is_student_line(&line, student)).max_by_key(|s| s.len()) else {
+            continue;
+        };
+        let reason = line[student.len()..].strip_prefix(':').unwrap_or_default().to_string();
+        let output_file = in_darwin_root(root, &legacy_student_compile_output_file(student));
+        let output = match output_file.is_file() {
+            true => Some(store_content_addressed(root, &reports, &fs::read(output_file)?)?),
+            false => None,
+        };
+        store
+            .compile_errors
+            .insert(student.clone(), CompileRecord { test: None, reason, finished_at: None, compile_secs: None, output });
+    }
+
+    Ok(store)
+}
+
+/// Lines of the file, or none if it does not exist
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?.lines().map(str::to_string).collect())
+}
+
+fn remove_legacy_results(root: &Path) -> Result<()> {
+    for dir in [legacy_results_dir(), legacy_compile_output_dir()] {
+        let dir = in_darwin_root(root, &dir);
+        if dir.is_dir() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+    for file in [legacy_compile_errors_file(), legacy_timeouts_file()] {
+        let file = in_darwin_root(root, &file);
+        if file.is_file() {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::{migrate_legacy_results, remove_legacy_results, ResultStore, RunState, RunStatus};
+    use crate::config::{
+        in_darwin_root, legacy_compile_errors_file, legacy_compile_output_dir, legacy_results_dir,
+        legacy_student_compile_output_file, legacy_student_result_file, legacy_timeouts_file, report_file,
+    };
+
+    #[test]
+    fn test_migrate_legacy_results() {
+        let root = tempdir().unwrap();
+        let root = root.path();
+        // Names the legacy files' separators appear in
+        let students: Vec<String> = ["al", "al_x", "bo", "bo:b"].iter().map(|s| s.to_string()).collect();
+        let tests: Vec<String> = ["T", "U"].iter().map(|s| s.to_string()).collect();
+        fs::create_dir_all(in_darwin_root(root, &legacy_results_dir())).unwrap();
+        fs::create_dir_all(in_darwin_root(root, &legacy_compile_output_dir())).unwrap();
+        fs::write(in_darwin_root(root, &legacy_student_result_file("al_x", "T")), "<testsuite/>").unwrap();
+        fs::write(in_darwin_root(root, &legacy_timeouts_file()), "al:U\n").unwrap();
+        fs::write(in_darwin_root(root, &legacy_compile_errors_file()), "bo:b:'mvn test-compile' failed\n").unwrap();
+        fs::write(in_darwin_root(root, &legacy_student_compile_output_file("bo:b")), "{}").unwrap();
+
+        let store = migrate_legacy_results(root, &students, &tests).unwrap();
+        let report = store.test_record("al_x", "T").unwrap().report.clone().unwrap();
+        assert_eq!(fs::read_to_string(in_darwin_root(root, &report_file(&report))).unwrap(), "<testsuite/>");
+        assert_eq!(store.test_record("al", "U").unwrap().status, RunStatus::Timeout);
+        assert!(store.test_record("al", "T").is_none());
+        // The line starts with bo's name too
+        assert!(store.has_compile_error("bo:b") && !store.has_compile_error("bo"));
+        assert_eq!(store.compile_errors["bo:b"].reason, "'mvn test-compile' failed");
+        assert!(store.compile_errors["bo:b"].output.is_some());
+
+        remove_legacy_results(root).unwrap();
+        assert!(!in_darwin_root(root, &legacy_results_dir()).exists());
+        assert!(!in_darwin_root(root, &legacy_compile_errors_file()).exists());
+    }
+
+    #[test]
+    fn test_remove_student() {
+        let root = tempdir().unwrap();
+        let root = root.path();
+        let students: Vec<String> = ["a", "b"].iter().map(|s| s.to_string()).collect();
+        let tests = vec!["T".to_string()];
+        fs::create_dir_all(in_darwin_root(root, &legacy_results_dir())).unwrap();
+        // Identical reports are stored once
+        fs::write(in_darwin_root(root, &legacy_student_result_file("a", "T")), "same").unwrap();
+        fs::write(in_darwin_root(root, &legacy_student_result_file("b", "T")), "same").unwrap();
+
+        let mut store = migrate_legacy_results(root, &students, &tests).unwrap();
+        assert!(store.remove_student_tests("a", &["U".to_string()]).is_empty());
+        assert!(store.test_record("a", "T").is_some());
+        assert!(store.remove_student("a").is_empty());
+        assert_eq!(store.remove_student("b").len(), 1);
+        assert!(store.results.is_empty());
+    }
+
+    #[test]
+    fn test_runs_in() {
+        let mut store = ResultStore::default();
+        store.set_run_state("a", "T", RunState::Done, None);
+        store.set_run_state("b", "T", RunState::Running, None);
+        store.set_run_state("c", "T", RunState::Queued, None);
+        store.set_run_state("a", "U", RunState::Failed, Some("Results file was not found".to_string()));
+        store.set_run_state("b", "U", RunState::Queued, None);
+
+        let unfinished = store.runs_in(&[RunState::Queued, RunState::Running]);
+        assert_eq!(unfinished["T"], ["b", "c"]);
+        assert_eq!(unfinished["U"], ["b"]);
+        assert_eq!(store.runs_in(&[RunState::Failed])["U"], ["a"]);
+
+        store.rename_student("a", "0");
+        assert_eq!(store.run_state("0", "U"), Some(RunState::Failed));
+        store.remove_student("b");
+        assert_eq!(store.runs_in(&[RunState::Queued, RunState::Running])["T"], ["c"]);
+    }
+}
@@ -1,85 +1,271 @@
 use std::{
-    fs::{remove_dir_all, remove_file},
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs::{self, remove_dir_all, remove_file},
     io::{self, Error, ErrorKind, Result},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
 };
+use tempfile::tempdir_in;
 use threadpool::ThreadPool;
 
 use crate::{
-    config::{
-        compile_errors_file, darwin_root, diff_dir, student_diff_file, student_project_file,
-        student_result_file
-    }, darwin_config::{read_config, write_config}, project_runner::Project, util::{file_append_line, is_student, is_test}
+    config::{darwin_root, student_project_file}, darwin_config::{self, read_config, write_config}, list_students::list_students, progress::{ProgressReporter, RunSummary}, project_runner::{sandbox, Project}, result_store::{read_result_store, remove_student_test_results, store_report, unix_time, update_result_store, CompileRecord, RunState, RunStatus, TestRecord}, types::CompileError, util::{is_student, is_test}, view_student_results::parse_test_results
 };
 
+/// Runs `tests` on every student. Each student's project is built and compiled once for all of them
 pub fn concurrent_run_test(
     project: &Project,
-    test: &str,
+    tests: &[String],
     num_threads: usize,
-    on_thread_start: fn(&str),
-    on_thread_err: fn(&str, Error),
-    on_thread_end: fn(&str),
-) -> Result<()> {
-    if !is_test(project, test) {
-        return Err(io::Error::new(
-            ErrorKind::NotFound,
-            format!("Test {} not recognized", test),
-        ));
+    progress: &Arc<ProgressReporter>,
+) -> Result<RunSummary> {
+    if tests.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "No tests given"));
     }
-    if read_config()?.tests_run.contains(&test.to_string()) {
-        return Err(io::Error::new(
-            ErrorKind::NotFound,
-            format!("Test {} already ran", test),
-        ));
+    let tests_run = read_config()?.tests_run;
+    for test in tests {
+        if !is_test(project, test) {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("Test {} not recognized", test),
+            ));
+        }
+        if tests_run.contains(test) {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("Test {} already ran", test),
+            ));
+        }
     }
+    sandbox::check(project)?;
 
+    let students = list_students();
+    progress.add_total(students.len());
     _concurrent_run_test(
         project,
-        test,
+        tests,
+        &students,
         num_threads,
-        on_thread_start,
-        on_thread_err,
-        on_thread_end,
-    )
+        progress,
+    )?;
+    for test in tests {
+        mark_test_run(test)?;
+    }
+    summarize(project, &[(tests.to_vec(), students)])
+}
+
+/// Runs the students whose runs were interrupted before they finished, eg. by Ctrl-C, or never started. Returns how
+/// how they did
+pub fn resume(
+    project: &Project,
+    num_threads: usize,
+    progress: &Arc<ProgressReporter>,
+) -> Result<RunSummary> {
+    let runs = read_result_store()?.runs_in(&[RunState::Queued, RunState::Running]);
+    run_again(project, runs, num_threads, progress)
+}
+
+/// Runs the students whose runs ended without a result again, only of `test` if given. Returns how they did
+pub fn retry_failed(
+    project: &Project,
+    test: Option<&str>,
+    num_threads: usize,
+    progress: &Arc<ProgressReporter>,
+) -> Result<RunSummary> {
+    if let Some(test) = test {
+        if !is_test(project, test) {
+            return Err(Error::new(ErrorKind::NotFound, format!("Test {} not recognized", test)));
+        }
+    }
+    let mut runs = read_result_store()?.runs_in(&[RunState::Failed]);
+    runs.retain(|run_test, _| test.is_none_or(|test| test == run_test));
+    run_again(project, runs, num_threads, progress)
+}
+
+/// Runs the students of each test. Students that are no longer in the project are left out, and students to be run on
+/// the same tests are run together, so each is built once
+fn run_again(
+    project: &Project,
+    runs: BTreeMap<String, Vec<String>>,
+    num_threads: usize,
+    progress: &Arc<ProgressReporter>,
+) -> Result<RunSummary> {
+    if runs.is_empty() {
+        return Ok(RunSummary::default());
+    }
+    sandbox::check(project)?;
+    let groups = group_runs(runs, &darwin_config::list_tests(), &list_students());
+
+    progress.add_total(groups.values().map(Vec::len).sum());
+    for (tests, group) in &groups {
+        _concurrent_run_test(project, tests, group, num_threads, progress)?;
+    }
+    mark_finished_tests_run(groups.keys().flatten())?;
+    summarize(project, &groups.into_iter().collect::<Vec<_>>())
+}
+
+/// Turns the students to run of each test into the students to run on each set of tests, leaving out tests and
+/// students that are not in the project
+fn group_runs(runs: BTreeMap<String, Vec<String>>, tests: &[String], students: &[String]) -> BTreeMap<Vec<String>, Vec<String>> {
+    let mut student_tests: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (test, run_students) in runs {
+        if !tests.contains(&test) {
+            continue;
+        }
+        for student in run_students.into_iter().filter(|student| students.contains(student)) {
+            student_tests.entry(student).or_default().push(test.clone());
+        }
+    }
+    let mut groups: BTreeMap<Vec<String>, Vec<String>> = BTreeMap::new();
+    for (student, tests) in student_tests {
+        groups.entry(tests).or_default().push(student);
+    }
+    groups
+}
+
+/// Runs `tests` on some of the students, eg. for `serve`. Returns how they did
+pub fn run_students(
+    project: &Project,
+    tests: &[String],
+    students: &[String],
+    num_threads: usize,
+    progress: &Arc<ProgressReporter>,
+) -> Result<RunSummary> {
+    validate_run(project, tests, students)?;
+    sandbox::check(project)?;
+    progress.add_total(students.len());
+    _concurrent_run_test(project, tests, students, num_threads, progress)?;
+    mark_finished_tests_run(tests)?;
+    summarize(project, &[(tests.to_vec(), students.to_vec())])
+}
+
+/// Discards the students' results of `tests`, and their compile errors, then runs the tests again
+pub fn regrade(
+    project: &Project,
+    tests: &[String],
+    students: &[String],
+    num_threads: usize,
+    progress: &Arc<ProgressReporter>,
+) -> Result<RunSummary> {
+    validate_run(project, tests, students)?;
+    for student in students {
+        remove_student_test_results(student, tests)?;
+    }
+    run_students(project, tests, students, num_threads, progress)
+}
+
+fn validate_run(project: &Project, tests: &[String], students: &[String]) -> Result<()> {
+    if tests.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "No tests given"));
+    }
+    if let Some(test) = tests.iter().find(|test| !is_test(project, test)) {
+        return Err(Error::new(ErrorKind::NotFound, format!("Test {} not recognized", test)));
+    }
+    if let Some(student) = students.iter().find(|student| !is_student(student)) {
+        return Err(Error::new(ErrorKind::NotFound, format!("Student {} not recognized", student)));
+    }
+    Ok(())
+}
+
+/// Only finishing a run of every student counts as the test having run, not eg. a resumed test-student
+fn mark_finished_tests_run<'a>(tests: impl IntoIterator<Item = &'a String>) -> Result<()> {
+    let students = list_students();
+    let store = read_result_store()?;
+    let tests: BTreeSet<&String> = tests.into_iter().collect();
+    for test in tests {
+        if students.iter().all(|student| store.run_state(student, test).is_some_and(|state| state != RunState::Queued && state != RunState::Running)) {
+            mark_test_run(test)?;
+        }
+    }
+    Ok(())
+}
+
+/// How the students did on the tests they were run on, from the result store. Pairs of tests and students
+pub fn summarize(project: &Project, runs: &[(Vec<String>, Vec<String>)]) -> Result<RunSummary> {
+    let store = read_result_store()?;
+    let mut summary = RunSummary::default();
+    for (tests, students) in runs {
+        summary.runs += tests.len() * students.len();
+        for student in students {
+            if store.has_compile_error(student) {
+                summary.compile_errors.push(student.clone());
+                continue;
+            }
+            let (mut passed, mut total) = (0, 0);
+            for test in tests {
+                if store.run_state(student, test) == Some(RunState::Failed) {
+                    summary.infrastructure_errors.push(format!("{} ({})", student, test));
+                }
+                match store.test_record(student, test).map(|record| record.status) {
+                    Some(RunStatus::Timeout) => summary.timeouts.push(format!("{} ({})", student, test)),
+                    Some(RunStatus::Completed) => {
+                        let Ok(results) = parse_test_results(project, student, test) else {
+                            continue;
+                        };
+                        let results = results.to_summary();
+                        passed += results.correct;
+                        total += results.correct + results.errored + results.failed;
+                    }
+                    None => {}
+                }
+            }
+            if total > 0 {
+                summary.pass_rates.push(passed as f64 / total as f64);
+            }
+        }
+    }
+    Ok(summary)
 }
 
 fn _concurrent_run_test(
     project: &Project,
-    test: &str,
+    tests: &[String],
+    students: &[String],
     num_threads: usize,
-    on_thread_start: fn(&str),
-    on_thread_err: fn(&str, Error),
-    on_thread_end: fn(&str),
+    progress: &Arc<ProgressReporter>,
 ) -> io::Result<()> {
+    // Before any start, so students still waiting for a thread when the run is interrupted are resumed too
+    update_result_store(|store| {
+        for student in students {
+            for test in tests {
+                let state = match store.test_record(student, test) {
+                    Some(_) => RunState::Done,
+                    None => RunState::Queued,
+                };
+                store.set_run_state(student, test, state, None);
+            }
+        }
+    })?;
+
     let threadpool = ThreadPool::new(num_threads);
 
-    for diff_path in diff_dir().read_dir()? {
-        let diff_path = diff_path.unwrap();
-        let student = diff_path.file_name().into_string().expect("?");
-        let test_clone = test.to_string();
+    for student in students {
+        let student = student.clone();
+        let tests = tests.to_vec();
         let project_copy = project.clone();
+        let progress = progress.clone();
         threadpool.execute(move || {
-            on_thread_start(&student);
-            // let darwin_path_clone = darwin_path.to_pat
-            match run_test_for_student(&project_copy, &student, &test_clone) {
-                Ok(()) => {
-                    on_thread_end(&student);
-                }
-                Err(e) => {
-                    on_thread_err(&student, e);
-                }
-            }
+            progress.start(&student);
+            let result = run_test_for_student(&project_copy, &student, &tests);
+            progress.end(&student, &result);
         })
     }
     threadpool.join();
+    Ok(())
+}
 
+fn mark_test_run(test: &str) -> Result<()> {
     let mut config = read_config()?;
-    config.tests_run.push(test.to_string());
-    write_config(config)?;
+    if !config.tests_run.iter().any(|t| t == test) {
+        config.tests_run.push(test.to_string());
+        write_config(config)?;
+    }
     Ok(())
 }
 
-pub fn run_test_for_student(project: &Project, student: &str, test: &str) -> Result<()> {
+/// Builds and compiles the student's project once, then runs every test that has no result yet
+pub fn run_test_for_student(project: &Project, student: &str, tests: &[String]) -> Result<()> {
     // Validate Inputs
     if !darwin_root().is_dir() {
         return Err(Error::new(
@@ -87,11 +273,13 @@ pub fn run_test_for_student(project: &Project, student: &str, test: &str) -> Res
             "darwin project not initialized in this directory",
         ));
     }
-    if !is_test(project, test) {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            format!("Test '{}' was not found", test),
-        ));
+    for test in tests {
+        if !is_test(project, test) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Test '{}' was not found", test),
+            ));
+        }
     }
     if !is_student(student) {
         return Err(Error::new(
@@ -109,37 +297,232 @@ pub fn run_test_for_student(project: &Project, student: &str, test: &str) -> Res
     }
 
     // Don't recompute
-    let dest_file = student_result_file(student, test);
-    if dest_file.exists() {
+    let store = read_result_store()?;
+    let (done, tests): (Vec<&String>, Vec<&String>) = tests.iter().partition(|test| store.test_record(student, test).is_some());
+    if done.iter().any(|test| store.run_state(student, test) != Some(RunState::Done)) {
+        update_result_store(|store| {
+            for test in &done {
+                store.set_run_state(student, test, RunState::Done, None);
+            }
+        })?;
+    }
+    let tests: Vec<String> = tests.into_iter().cloned().collect();
+    if tests.is_empty() {
         return Ok(());
     }
 
-    _run_test_for_student(
+    update_result_store(|store| {
+        for test in &tests {
+            store.set_run_state(student, test, RunState::Running, None);
+        }
+    })?;
+    let result = _run_test_for_student(
         project,
         student_project_path.as_path(),
         student,
-        test,
-        dest_file.as_path(),
-    )
+        &tests,
+    );
+    if let Err(e) = &result {
+        // Compile errors, timeouts and the tests that did produce a report are recorded as done
+        update_result_store(|store| {
+            for test in &tests {
+                if store.run_state(student, test) == Some(RunState::Running) {
+                    store.set_run_state(student, test, RunState::Failed, Some(e.to_string()));
+                }
+            }
+        })?;
+    }
+    result
 }
 
 fn _run_test_for_student(
     project: &Project,
     project_path: &Path,
     student: &str,
-    test: &str,
-    dest_file: &Path,
+    tests: &[String],
 ) -> Result<()> {
-    let diff_path = student_diff_file(student);
-    project.recreate_normalized_project(project_path, &diff_path)?;
+    project.recreate_normalized_project(student, project_path)?;
+    let compile_start = Instant::now();
     if let Err(e) = project.compile(project_path) {
-        file_append_line(&compile_errors_file(), &format!("{}:{}", student, e.to_string()))?;
+        let compile_secs = Some(compile_start.elapsed().as_secs_f64());
+        if e.kind() == ErrorKind::TimedOut {
+            let record = TestRecord { status: RunStatus::Timeout, finished_at: Some(unix_time()), compile_secs, test_secs: None, report: None };
+            record_tests(student, tests.iter().map(|test| (test, record.clone())))?;
+        } else {
+            let output = CompileError::from_io_error(&e).map(|compile_error| store_report(&serde_json::to_vec_pretty(compile_error)?)).transpose()?;
+            let record = CompileRecord { test: tests.first().cloned(), reason: e.to_string(), finished_at: Some(unix_time()), compile_secs, output };
+            update_result_store(|store| {
+                store.compile_errors.insert(student.to_string(), record);
+                for test in tests {
+                    store.set_run_state(student, test, RunState::Done, None);
+                }
+            })?;
+        }
         remove_dir_all(project_path)?;
         return Err(e);
     }
-    project.run_test(project_path, test)?;
-    project.relocate_test_results(project_path, test, dest_file)?;
+    let compile_secs = Some(compile_start.elapsed().as_secs_f64());
+
+    // Within .darwin, so the reports can be moved there
+    let report_dir = tempdir_in(darwin_root())?;
+    let outcomes = match run_batch(project, project_path, tests, report_dir.path()) {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            remove_dir_all(project_path)?;
+            return Err(e);
+        }
+    };
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+    for (test, outcome, test_secs) in &outcomes {
+        let test_secs = Some(*test_secs);
+        match outcome {
+            TestOutcome::Report(report_file) => {
+                let report = store_report(&fs::read(report_file)?)?;
+                records.push((test, TestRecord { status: RunStatus::Completed, finished_at: Some(unix_time()), compile_secs, test_secs, report: Some(report) }));
+            }
+            TestOutcome::TimedOut(e) => {
+                records.push((test, TestRecord { status: RunStatus::Timeout, finished_at: Some(unix_time()), compile_secs, test_secs, report: None }));
+                errors.push(format!("{}: {}", test, e));
+            }
+            TestOutcome::NoReport(e) => errors.push(format!("{}: {}", test, e)),
+        }
+    }
+    record_tests(student, records)?;
     remove_dir_all(project_path)?;
 
+    if !errors.is_empty() {
+        let kind = match outcomes.iter().any(|(_, outcome, _)| matches!(outcome, TestOutcome::TimedOut(_))) {
+            true => ErrorKind::TimedOut,
+            false => ErrorKind::Other,
+        };
+        return Err(Error::new(kind, errors.join(", ")));
+    }
     Ok(())
 }
+
+/// How a test of a student's run ended
+#[derive(Debug)]
+enum TestOutcome {
+    /// Its report, moved into the report dir
+    Report(PathBuf),
+    TimedOut(Error),
+    NoReport(Error),
+}
+
+/// Runs the tests in one invocation, so they share its time. If it times out, the tests it wrote no report for are
+/// run again on their own, each with its own time limit as before they were run together, so a test that hangs does
+/// not take the others' results with it. Returns each test's outcome, and how long the run that ended it took
+fn run_batch(project: &Project, project_path: &Path, tests: &[String], report_dir: &Path) -> Result<Vec<(String, TestOutcome, f64)>> {
+    let mut outcomes = Vec::new();
+    let mut runs = VecDeque::from([tests.to_vec()]);
+    while let Some(run) = runs.pop_front() {
+        let test_start = Instant::now();
+        let timed_out = match project.run_test(project_path, &run) {
+            Ok(()) => None,
+            Err(e) if e.kind() == ErrorKind::TimedOut => Some(e),
+            Err(e) => return Err(e),
+        };
+        let test_secs = test_start.elapsed().as_secs_f64();
+        for test in run.iter().cloned() {
+            let report_file = report_dir.join(&test);
+            match (project.relocate_test_results(project_path, &test, &report_file), &timed_out) {
+                (Ok(()), _) => outcomes.push((test, TestOutcome::Report(report_file), test_secs)),
+                (Err(_), Some(_)) if run.len() > 1 => runs.push_back(vec![test]),
+                (Err(_), Some(e)) => outcomes.push((test, TestOutcome::TimedOut(Error::new(e.kind(), e.to_string())), test_secs)),
+                (Err(e), None) => outcomes.push((test, TestOutcome::NoReport(e), test_secs)),
+            }
+        }
+    }
+    Ok(outcomes)
+}
+
+fn record_tests<'a>(student: &str, records: impl IntoIterator<Item = (&'a String, TestRecord)>) -> Result<()> {
+    update_result_store(|store| {
+        for (test, record) in records {
+            store.insert_test_record(student, test, record);
+            store.set_run_state(student, test, RunState::Done, None);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fs,
+        io::{Error, ErrorKind},
+        path::Path,
+    };
+
+    use tempfile::tempdir;
+
+    use super::{group_runs, run_batch, TestOutcome};
+    use crate::{darwin_config::ProjectType, project_runner::Project};
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_group_runs() {
+        let runs = BTreeMap::from([
+            (String::from("A"), strings(&["alice", "bob", "carol", "gone"])),
+            (String::from("B"), strings(&["alice", "bob"])),
+            (String::from("C"), strings(&["carol"])),
+            (String::from("Removed"), strings(&["alice"])),
+        ]);
+        let groups = group_runs(runs, &strings(&["A", "B", "C"]), &strings(&["alice", "bob", "carol"]));
+        assert_eq!(
+            groups,
+            BTreeMap::from([(strings(&["A", "B"]), strings(&["alice", "bob"])), (strings(&["A", "C"]), strings(&["carol"]))])
+        );
+    }
+
+    /// Writes a report for each test in turn, like surefire does after each class, and hangs on `Hang`
+    fn run_test(_: &Project, project_path: &Path, tests: &[String]) -> std::io::Result<()> {
+        fs::create_dir_all(project_path.join("reports"))?;
+        for test in tests {
+            if test == "Hang" {
+                return Err(Error::new(ErrorKind::TimedOut, "Timed out after 1s"));
+            }
+            fs::write(project_path.join("reports").join(test), format!("<testsuite name=\"{}\"/>", test))?;
+        }
+        Ok(())
+    }
+
+    fn relocate_test_results(_: &Project, project_path: &Path, test: &str, dest_file: &Path) -> std::io::Result<()> {
+        fs::rename(project_path.join("reports").join(test), dest_file)
+    }
+
+    #[test]
+    fn test_run_batch_timeout() {
+        let project = Project::new(
+            ProjectType::MavenSurefire,
+            HashMap::new(),
+            HashMap::new(),
+            HashSet::new(),
+            |_, _| Ok(()),
+            |_, _| Ok(()),
+            |_| HashSet::new(),
+            run_test,
+            relocate_test_results,
+            |_, _, _, _| Ok(Vec::new()),
+        )
+        .unwrap();
+        let project_path = tempdir().unwrap();
+        let report_dir = tempdir().unwrap();
+
+        let outcomes = run_batch(&project, project_path.path(), &strings(&["First", "Hang", "Last"]), report_dir.path()).unwrap();
+        let outcomes: HashMap<&str, &TestOutcome> = outcomes.iter().map(|(test, outcome, _)| (test.as_str(), outcome)).collect();
+        assert_eq!(outcomes.len(), 3);
+        // Reported before the run hung
+        assert!(matches!(outcomes["First"], TestOutcome::Report(file) if fs::read_to_string(file).unwrap() == "<testsuite name=\"First\"/>"));
+        assert!(matches!(outcomes["Hang"], TestOutcome::TimedOut(_)));
+        // Never reached by the batch, so run on its own
+        assert!(matches!(outcomes["Last"], TestOutcome::Report(_)));
+
+        let outcomes = run_batch(&project, project_path.path(), &strings(&["First", "Last"]), report_dir.path()).unwrap();
+        assert!(outcomes.iter().all(|(_, outcome, _)| matches!(outcome, TestOutcome::Report(_))));
+    }
+}
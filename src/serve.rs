@@ -0,0 +1,605 @@
+//! `serve`: a local HTTP server over the .darwin project, so a group of TAs can share one instance. Renders the report
+//! pages on demand, at the same paths create-report writes them to, and has a small json API to read results and
+//! queue test runs
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use handlebars::Handlebars;
+use serde::{Deserialize, Serialize};
+use threadpool::ThreadPool;
+
+use crate::{
+    create_report::{initialize_handlebars, render_student_list, render_student_page, render_tests_page, REPORT_STYLES},
+    darwin_config,
+    grade::{grade_students, read_rubric},
+    list_students::list_students,
+    progress::{ProgressReporter, RunSummary},
+    project_runner::Project,
+    run_tests,
+    util::{is_student, is_test},
+    view_student_results::{result_summary, verbose_result},
+};
+
+/// Connections handled at once. Test runs happen on their own thread, so this only limits page renders
+const CONNECTION_THREADS: usize = 8;
+
+/// A client that has not sent its whole request by then is dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest request read, headers and body. Longer bodies are refused before anything is allocated for them
+const MAX_REQUEST_BYTES: u64 = 1 << 20;
+
+struct Server {
+    project: Project,
+    /// The address the server is bound to, which the `Host` of every request must name
+    address: SocketAddr,
+    /// Other host names the server is reached by
+    allowed_hosts: Vec<String>,
+    handlebars: Handlebars<'static>,
+    /// Threads each queued run uses
+    num_threads: usize,
+    /// Every job since the server started, by id
+    jobs: Mutex<Vec<Job>>,
+    /// Ids of queued jobs. They run one at a time, since runs of the same student share a project directory
+    queue: Sender<usize>,
+    /// The job being run, to report how far it has got
+    current: Mutex<Option<(usize, Arc<ProgressReporter>)>>,
+    /// Rubric scores shown on the student pages, updated after each job
+    grades: RwLock<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Clone)]
+struct Job {
+    id: usize,
+    /// Whether the students' earlier results of the tests were discarded first
+    regrade: bool,
+    tests: Vec<String>,
+    students: Vec<String>,
+    state: JobState,
+    /// Students finished so far
+    completed: usize,
+    error: Option<String>,
+    summary: Option<RunSummary>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Body of `POST /api/runs`
+#[derive(Deserialize)]
+struct RunRequest {
+    tests: Vec<String>,
+    /// Defaults to every student
+    #[serde(default)]
+    students: Option<Vec<String>>,
+    #[serde(default)]
+    regrade: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    /// Without the query string
+    path: String,
+    /// Names are lowercase
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn html(result: Result<String>) -> Response {
+        match result {
+            Ok(html) => Response { status: 200, content_type: "text/html; charset=utf-8", body: html.into_bytes() },
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    fn json<T: Serialize + ?Sized>(status: u16, value: &T) -> Response {
+        match serde_json::to_vec_pretty(value) {
+            Ok(body) => Response { status, content_type: "application/json", body },
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    /// `{"error": message}`
+    fn error(status: u16, message: &str) -> Response {
+        let body = serde_json::json!({ "error": message }).to_string().into_bytes();
+        Response { status, content_type: "application/json", body }
+    }
+
+    fn not_found() -> Response {
+        Response::error(404, "Not found")
+    }
+}
+
+/// Serves the project until the process is stopped. Requests must name the server by its address, localhost, or one of
+/// `allowed_hosts`
+pub fn serve(project: &Project, address: &str, allowed_hosts: &[String], num_threads: usize) -> Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let mut handlebars = Handlebars::new();
+    initialize_handlebars(&mut handlebars)?;
+
+    let (queue, queued) = channel();
+    let server = Arc::new(Server {
+        project: project.clone(),
+        address: listener.local_addr()?,
+        allowed_hosts: allowed_hosts.to_vec(),
+        handlebars,
+        num_threads,
+        jobs: Mutex::new(Vec::new()),
+        queue,
+        current: Mutex::new(None),
+        grades: RwLock::new(grades(project)?),
+    });
+    let job_server = server.clone();
+    thread::spawn(move || run_jobs(&job_server, queued));
+
+    println!("Serving on http://{}", listener.local_addr()?);
+    let pool = ThreadPool::new(CONNECTION_THREADS);
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let server = server.clone();
+        pool.execute(move || {
+            if let Err(e) = handle_connection(&server, stream) {
+                eprintln!("Failed to answer request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn grades(project: &Project) -> Result<HashMap<String, String>> {
+    let Some(rubric) = read_rubric()? else {
+        return Ok(HashMap::new());
+    };
+    Ok(grade_students(project, &rubric, &list_students())?
+        .into_iter()
+        .map(|grade| (grade.student.clone(), grade.to_string()))
+        .collect())
+}
+
+fn run_jobs(server: &Server, queued: Receiver<usize>) {
+    for id in queued {
+        let (tests, students, regrade) = {
+            let mut jobs = server.jobs.lock().unwrap();
+            let job = &mut jobs[id];
+            job.state = JobState::Running;
+            (job.tests.clone(), job.students.clone(), job.regrade)
+        };
+        let progress = ProgressReporter::new();
+        *server.current.lock().unwrap() = Some((id, progress.clone()));
+        let run = match regrade {
+            true => run_tests::regrade,
+            false => run_tests::run_students,
+        };
+        let result = run(&server.project, &tests, &students, server.num_threads, &progress);
+        progress.finish();
+        *server.current.lock().unwrap() = None;
+
+        match grades(&server.project) {
+            Ok(grades) => *server.grades.write().unwrap() = grades,
+            Err(e) => eprintln!("Failed to grade students: {}", e),
+        }
+        let mut jobs = server.jobs.lock().unwrap();
+        let job = &mut jobs[id];
+        job.completed = progress.counts().0;
+        match result {
+            Ok(summary) => {
+                job.state = JobState::Done;
+                job.summary = Some(summary);
+            }
+            Err(e) => {
+                job.state = JobState::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+fn handle_connection(server: &Server, mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_BYTES));
+    let response = match read_request(&mut reader) {
+        Ok(request) => route(server, &request),
+        Err(e) if e.kind() == ErrorKind::InvalidInput => Response::error(413, &e.to_string()),
+        Err(e) => Response::error(400, &e.to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Reads the request line, the headers, and a body of `Content-Length` bytes. A body over `MAX_REQUEST_BYTES` is an
+/// `InvalidInput` error, anything malformed is `InvalidData`
+fn read_request(reader: &mut impl BufRead) -> Result<Request> {
+    let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let method = method.to_string();
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("Request ended in the headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| invalid("Invalid Content-Length"))?;
+                if content_length as u64 > MAX_REQUEST_BYTES {
+                    return Err(Error::new(ErrorKind::InvalidInput, "Request body too large"));
+                }
+            }
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, headers, body })
+}
+
+fn route(server: &Server, request: &Request) -> Response {
+    let Some(segments) = request.path.trim_start_matches('/').split('/').map(percent_decode).collect::<Option<Vec<String>>>() else {
+        return Response::error(400, "Invalid path");
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    if !request.header("host").is_some_and(|host| host_matches(server.address, &server.allowed_hosts, host)) {
+        return Response::error(403, "Host does not name this server");
+    }
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", [""] | ["index.html"]) => Response::html(render_student_list(&list_students(), &server.handlebars)),
+        ("GET", ["tests.html"]) => Response::html(render_tests_page(&server.project, &server.handlebars)),
+        ("GET", ["styles", name]) => style(name),
+        ("GET", ["students", student, page]) => student_page(server, student, page),
+        ("GET", ["api", "students"]) => Response::json(200, &list_students()),
+        ("GET", ["api", "tests"]) => Response::json(200, &darwin_config::list_tests()),
+        ("GET", ["api", "results", test]) => test_results(server, test),
+        ("GET", ["api", "results", test, student]) => student_results(server, test, student),
+        ("GET", ["api", "runs"]) => Response::json(200, &jobs(server)),
+        ("GET", ["api", "runs", id]) => match id.parse::<usize>().ok().and_then(|id| jobs(server).into_iter().nth(id)) {
+            Some(job) => Response::json(200, &job),
+            None => Response::not_found(),
+        },
+        ("POST", ["api", "runs"]) => match same_origin_json(request) {
+            Ok(()) => queue_run(server, &request.body),
+            Err(message) => Response::error(403, message),
+        },
+        _ => Response::not_found(),
+    }
+}
+
+fn style(name: &str) -> Response {
+    let Some((_, content)) = REPORT_STYLES.iter().find(|(style, _)| *style == name) else {
+        return Response::not_found();
+    };
+    let content_type = match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("ttf") => "font/ttf",
+        _ => "text/plain; charset=utf-8",
+    };
+    Response { status: 200, content_type, body: content.to_vec() }
+}
+
+/// `students/{student}/` is their index page
+fn student_page(server: &Server, student: &str, page: &str) -> Response {
+    let page = if page.is_empty() { "index.html" } else { page };
+    let grades = server.grades.read().unwrap();
+    let rendered = render_student_page(
+        &server.project,
+        &darwin_config::list_tests(),
+        &list_students(),
+        student,
+        page,
+        grades.get(student).map(String::as_str),
+        &server.handlebars,
+    );
+    match rendered {
+        Ok(Some(html)) => Response::html(Ok(html)),
+        Ok(None) => Response::not_found(),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// Every student's summary of the test, as `view-all-students-results-summary --format json` prints
+fn test_results(server: &Server, test: &str) -> Response {
+    if !is_test(&server.project, test) {
+        return Response::not_found();
+    }
+    let summaries: Result<Vec<_>> = list_students().iter().map(|student| result_summary(&server.project, student, test)).collect();
+    match summaries {
+        Ok(summaries) => Response::json(200, &summaries),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// As `view-student-results-verbose --format json` prints
+fn student_results(server: &Server, test: &str, student: &str) -> Response {
+    if !is_test(&server.project, test) || !is_student(student) {
+        return Response::not_found();
+    }
+    match verbose_result(&server.project, student, test) {
+        Ok(result) => Response::json(200, &result),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// With how far the running job has got
+fn jobs(server: &Server) -> Vec<Job> {
+    let mut jobs = server.jobs.lock().unwrap().clone();
+    if let Some((id, progress)) = server.current.lock().unwrap().as_ref() {
+        jobs[*id].completed = progress.counts().0;
+    }
+    jobs
+}
+
+/// Requests that change results must come from a page served by this server, or from a client outside the browser.
+/// A json `Content-Type` can't be sent cross origin without a CORS preflight, which is never answered. The `Host` was
+/// checked by `route`
+fn same_origin_json(request: &Request) -> std::result::Result<(), &'static str> {
+    let is_json = request
+        .header("content-type")
+        .is_some_and(|content_type| content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("application/json"));
+    if !is_json {
+        return Err("Content-Type must be application/json");
+    }
+    let host = request.header("host").unwrap_or_default();
+    if request.header("origin").is_some_and(|origin| origin != format!("http://{}", host)) {
+        return Err("Cross origin requests are not allowed");
+    }
+    Ok(())
+}
+
+/// Whether `host` (`name[:port]`) names the server: the address it is bound to, any address when bound to every
+/// interface, localhost, or one of `allowed_hosts`. Any other name could be a web site's own, pointed at this machine
+/// by DNS rebinding so its pages can read the results
+fn host_matches(address: SocketAddr, allowed_hosts: &[String], host: &str) -> bool {
+    let (name, port) = match host.rsplit_once(':') {
+        // Not the end of an IPv6 address
+        Some((name, port)) if !port.ends_with(']') => (name, port.parse().ok()),
+        _ => (host, Some(80)),
+    };
+    if port != Some(address.port()) {
+        return false;
+    }
+    match name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => address.ip().is_unspecified() || ip == address.ip(),
+        Err(_) => {
+            (name.eq_ignore_ascii_case("localhost") && (address.ip().is_loopback() || address.ip().is_unspecified()))
+                || allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(name))
+        }
+    }
+}
+
+fn queue_run(server: &Server, body: &[u8]) -> Response {
+    let request: RunRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return Response::error(400, &format!("Invalid run request: {}", e)),
+    };
+    let students = request.students.unwrap_or_else(list_students);
+    if request.tests.is_empty() {
+        return Response::error(400, "No tests given");
+    }
+    if let Some(test) = request.tests.iter().find(|test| !is_test(&server.project, test)) {
+        return Response::error(400, &format!("Test {} not recognized", test));
+    }
+    if let Some(student) = students.iter().find(|student| !is_student(student)) {
+        return Response::error(400, &format!("Student {} not recognized", student));
+    }
+
+    let mut jobs = server.jobs.lock().unwrap();
+    let job = Job {
+        id: jobs.len(),
+        regrade: request.regrade,
+        tests: request.tests,
+        students,
+        state: JobState::Queued,
+        completed: 0,
+        error: None,
+        summary: None,
+    };
+    if server.queue.send(job.id).is_err() {
+        return Response::error(500, "Test runs have stopped");
+    }
+    jobs.push(job.clone());
+    Response::json(202, &job)
+}
+
+/// Student names in links are percent encoded by the browser, eg. spaces. None if the result is not UTF-8
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{Cursor, ErrorKind},
+        net::SocketAddr,
+        sync::{mpsc::channel, Mutex, RwLock},
+    };
+
+    use handlebars::Handlebars;
+
+    use super::{host_matches, percent_decode, read_request, route, same_origin_json, Request, Server, MAX_REQUEST_BYTES};
+    use crate::project_runner::maven_project;
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: Vec::new(),
+        }
+    }
+
+    fn server(address: &str, allowed_hosts: &[&str]) -> Server {
+        Server {
+            project: maven_project().unwrap(),
+            address: address.parse().unwrap(),
+            allowed_hosts: allowed_hosts.iter().map(|host| host.to_string()).collect(),
+            handlebars: Handlebars::new(),
+            num_threads: 1,
+            jobs: Mutex::new(Vec::new()),
+            queue: channel().0,
+            current: Mutex::new(None),
+            grades: RwLock::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn test_read_request() {
+        let body = r#"{"tests": ["MathTests"]}"#;
+        let raw = format!(
+            "POST /api/runs?x=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        assert_eq!(
+            read_request(&mut Cursor::new(raw)).unwrap(),
+            Request {
+                method: String::from("POST"),
+                path: String::from("/api/runs"),
+                headers: vec![
+                    (String::from("host"), String::from("localhost")),
+                    (String::from("content-length"), body.len().to_string()),
+                ],
+                body: body.as_bytes().to_vec()
+            }
+        );
+        assert!(read_request(&mut Cursor::new("GET / HTTP/1.1\r\nHost: localhost\r\n")).is_err());
+    }
+
+    #[test]
+    fn test_read_request_too_large() {
+        let raw = "POST /api/runs HTTP/1.1\r\nContent-Length: 99999999999999\r\n\r\n{}";
+        assert_eq!(read_request(&mut Cursor::new(raw)).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let raw = format!("POST /api/runs HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_REQUEST_BYTES + 1);
+        assert_eq!(read_request(&mut Cursor::new(raw)).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_same_origin_json() {
+        let post = |headers: &[(&str, &str)]| request("POST", "/api/runs", headers);
+        let json = ("content-type", "application/json; charset=utf-8");
+        assert!(same_origin_json(&post(&[json, ("host", "localhost:8080")])).is_ok());
+        assert!(same_origin_json(&post(&[json, ("host", "127.0.0.1:8080"), ("origin", "http://127.0.0.1:8080")])).is_ok());
+        assert!(same_origin_json(&post(&[("content-type", "text/plain"), ("host", "localhost:8080")])).is_err());
+        assert!(same_origin_json(&post(&[json, ("host", "localhost:8080"), ("origin", "http://evil.example")])).is_err());
+    }
+
+    #[test]
+    fn test_host_matches() {
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let every: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        let allowed = [String::from("lab-machine")];
+        assert!(host_matches(local, &[], "localhost:8080"));
+        assert!(host_matches(local, &[], "127.0.0.1:8080"));
+        assert!(!host_matches(local, &[], "evil.example:8080"));
+        assert!(!host_matches(local, &[], "127.0.0.1:80"));
+        // Bound to every interface, names still have to be allowed
+        assert!(!host_matches(every, &[], "lab-machine:8080"));
+        assert!(host_matches(every, &allowed, "lab-machine:8080"));
+        assert!(host_matches(every, &allowed, "192.168.1.20:8080"));
+        assert!(!host_matches(every, &allowed, "lab-machine:80"));
+        assert!(host_matches("[::1]:8080".parse().unwrap(), &[], "[::1]:8080"));
+        assert!(host_matches("0.0.0.0:80".parse().unwrap(), &allowed, "lab-machine"));
+        assert!(host_matches("[::]:80".parse().unwrap(), &[], "[::1]"));
+    }
+
+    #[test]
+    fn test_route_checks_host() {
+        let local = server("127.0.0.1:8080", &[]);
+        for host in [&[("host", "evil.example:8080")][..], &[]] {
+            for path in ["/api/students", "/students/alice/index.html", "/api/results/MathTests", "/styles/global.css"] {
+                assert_eq!(route(&local, &request("GET", path, host)).status, 403);
+            }
+        }
+        assert_eq!(route(&local, &request("GET", "/styles/global.css", &[("host", "localhost:8080")])).status, 200);
+
+        let every = server("0.0.0.0:8080", &["lab-machine"]);
+        assert_eq!(route(&every, &request("GET", "/styles/global.css", &[("host", "rebound.example:8080")])).status, 403);
+        assert_eq!(route(&every, &request("GET", "/styles/global.css", &[("host", "lab-machine:8080")])).status, 200);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("Alice%20Smith").as_deref(), Some("Alice Smith"));
+        assert_eq!(percent_decode("Zo%C3%AB").as_deref(), Some("Zoë"));
+        assert_eq!(percent_decode("bad%2"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Error, ErrorKind, Result, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::{
+    config::{blob_file, blobs_dir, in_darwin_root, student_blobs_file, submission_blobs_dir},
+    util::dir_list_absolute_file_paths_recursively,
+};
+
+/// The files of a student's submission that their diff leaves out: binary files, files that are not UTF-8 text, and
+/// empty files added or removed. Diffs only mention that binary files differ, are read as text by the plagiarism
+/// checker, and can not tell an empty file from a missing one. Paths are relative to the normalized project
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SubmissionBlobs {
+    /// File to the sha256 of its content in the blob store
+    pub files: BTreeMap<PathBuf, String>,
+    /// Skeleton files the student removed, which the diff can not remove either
+    pub deleted: BTreeSet<PathBuf>,
+}
+
+impl SubmissionBlobs {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// Moves the files of normalized_project that a diff against skel can not hold into the blob store of the .darwin
+/// folder `root`, and puts the skeleton's version back in their place so the diff leaves them alone
+pub fn store_blobs(root: &Path, skel: &Path, normalized_project: &Path) -> Result<SubmissionBlobs> {
+    let mut paths = BTreeSet::new();
+    for dir in [skel, normalized_project] {
+        for file in dir_list_absolute_file_paths_recursively(dir) {
+            paths.insert(file.strip_prefix(dir).map_err(|_| Error::from(ErrorKind::Other))?.to_path_buf());
+        }
+    }
+
+    let blobs_dir = in_darwin_root(root, &blobs_dir());
+    let mut blobs = SubmissionBlobs::default();
+    for path in paths {
+        let skel_file = skel.join(&path);
+        let student_file = normalized_project.join(&path);
+        let skel_content = skel_file.is_file().then(|| fs::read(&skel_file)).transpose()?;
+        let student_content = student_file.is_file().then(|| fs::read(&student_file)).transpose()?;
+        let all_text = [&skel_content, &student_content].iter().all(|c| c.as_deref().is_none_or(is_text));
+        // The diff reads a missing file as empty, so it has nothing to say about an empty file only one side has
+        let empty_on_one_side = matches!((&skel_content, &student_content), (None, Some(c)) | (Some(c), None) if c.is_empty());
+        if skel_content == student_content || (all_text && !empty_on_one_side) {
+            continue;
+        }
+
+        match &student_content {
+            Some(content) => {
+                blobs.files.insert(path, store_content_addressed(root, &blobs_dir, content)?);
+            }
+            None => {
+                blobs.deleted.insert(path);
+            }
+        }
+        match skel_content {
+            Some(content) => {
+                if let Some(parent) = student_file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&student_file, content)?;
+            }
+            None => fs::remove_file(&student_file)?,
+        }
+    }
+    Ok(blobs)
+}
+
+/// Whether diff prints the file's changes, and they are readable as text
+fn is_text(content: &[u8]) -> bool {
+    !content.contains(&0) && std::str::from_utf8(content).is_ok()
+}
+
+/// Writes the content to `dir` named by its sha256, which is returned, unless it is already there. Identical files,
+/// eg. a library every student includes, are stored once. `root` is the .darwin folder `dir` is in
+pub fn store_content_addressed(root: &Path, dir: &Path, content: &[u8]) -> Result<String> {
+    let hash = format!("{:x}", Sha256::digest(content));
+    let file = dir.join(&hash);
+    if !file.is_file() {
+        fs::create_dir_all(dir)?;
+        // Outside of dir, where every file is complete
+        let mut tmp = NamedTempFile::new_in(root)?;
+        tmp.write_all(content)?;
+        tmp.persist(&file)?;
+    }
+    Ok(hash)
+}
+
+/// Replaces the student's blobs in the .darwin folder `root`. Students without any have no blobs file
+pub fn write_submission_blobs(root: &Path, student: &str, blobs: &SubmissionBlobs) -> Result<()> {
+    let path = in_darwin_root(root, &student_blobs_file(student));
+    if blobs.is_empty() {
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    fs::create_dir_all(in_darwin_root(root, &submission_blobs_dir()))?;
+    let tmp = NamedTempFile::new_in(root)?;
+    let mut writer = BufWriter::new(tmp.as_file());
+    serde_json::to_writer(&mut writer, blobs)?;
+    writer.flush()?;
+    drop(writer);
+    tmp.persist(path)?;
+    Ok(())
+}
+
+/// Projects created before blobs were stored have none
+pub fn read_submission_blobs(root: &Path, student: &str) -> Result<SubmissionBlobs> {
+    let path = in_darwin_root(root, &student_blobs_file(student));
+    if !path.is_file() {
+        return Ok(SubmissionBlobs::default());
+    }
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+/// Writes the student's blobs into their project recreated from skeleton and diff, making it match their submission.
+/// `root` is the .darwin folder the student is in
+pub fn restore_blobs(root: &Path, student: &str, dest: &Path) -> Result<()> {
+    let blobs = read_submission_blobs(root, student)?;
+    for (path, hash) in &blobs.files {
+        let file = dest.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(in_darwin_root(root, &blob_file(hash)), &file).map_err(|e| {
+            Error::new(e.kind(), format!("Failed to restore {} of {}: {}", path.display(), student, e))
+        })?;
+    }
+    for path in &blobs.deleted {
+        let file = dest.join(path);
+        if file.is_file() {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashSet},
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use tempfile::tempdir;
+
+    use super::{is_text, restore_blobs, store_blobs, write_submission_blobs};
+    use crate::{
+        diff::{apply_patch, diff_trees},
+        util::{copy_dir_all, dir_list_absolute_file_paths_recursively},
+    };
+
+    fn read_tree(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        dir_list_absolute_file_paths_recursively(dir)
+            .into_iter()
+            .map(|file| (file.strip_prefix(dir).unwrap().to_path_buf(), fs::read(&file).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_is_text() {
+        assert!(is_text(b"public class Impl {}\n"));
+        assert!(is_text("caf\u{e9}\n".as_bytes()));
+        assert!(!is_text(b"caf\xe9\n"));
+        assert!(!is_text(b"PK\x03\x04\0\0"));
+    }
+
+    #[test]
+    fn test_store_blobs_deleted() {
+        // Only deletions, which need no blob store
+        let skel = tempdir().unwrap();
+        let project = tempdir().unwrap();
+        fs::write(skel.path().join("Impl.java"), "class Impl {}\n").unwrap();
+        fs::write(project.path().join("Impl.java"), "class Impl { int x; }\n").unwrap();
+        fs::write(skel.path().join("data.bin"), b"\x00\x01").unwrap();
+
+        let blobs = store_blobs(tempdir().unwrap().path(), skel.path(), project.path()).unwrap();
+        assert!(blobs.files.is_empty());
+        assert_eq!(blobs.deleted.len(), 1);
+        assert!(blobs.deleted.contains(Path::new("data.bin")));
+        // Put back, so the diff does not mention it
+        assert_eq!(fs::read(project.path().join("data.bin")).unwrap(), b"\x00\x01");
+        assert_eq!(fs::read_to_string(project.path().join("Impl.java")).unwrap(), "class Impl { int x; }\n");
+    }
+
+    #[test]
+    fn test_store_blobs_round_trip() {
+        let root = tempdir().unwrap();
+        let skel = tempdir().unwrap();
+        let submission = tempdir().unwrap();
+        for (dir, files) in [
+            (skel.path(), vec![("Impl.java", &b"class Impl {}\n"[..]), ("logo.png", b"\x89PNG\0"), ("removed.txt", b"")]),
+            (
+                submission.path(),
+                vec![
+                    ("Impl.java", &b"class Impl { int x; }\n"[..]),
+                    ("logo.png", b"\x89PNG\0"),
+                    ("data.bin", b"\x00\x01"),
+                    ("src/main/empty.txt", b""),
+                    ("calc/__init__.py", b""),
+                ],
+            ),
+        ] {
+            for (name, content) in files {
+                fs::create_dir_all(dir.join(name).parent().unwrap()).unwrap();
+                fs::write(dir.join(name), content).unwrap();
+            }
+        }
+        let project = tempdir().unwrap();
+        copy_dir_all(submission.path(), project.path(), Some(&HashSet::new())).unwrap();
+
+        let blobs = store_blobs(root.path(), skel.path(), project.path()).unwrap();
+        write_submission_blobs(root.path(), "alice", &blobs).unwrap();
+        assert_eq!(blobs.files.len(), 3);
+        assert!(blobs.deleted.contains(Path::new("removed.txt")));
+        let mut diff = Vec::new();
+        diff_trees(skel.path(), project.path(), &mut diff).unwrap();
+
+        let recreated = tempdir().unwrap();
+        copy_dir_all(skel.path(), recreated.path(), Some(&HashSet::new())).unwrap();
+        apply_patch(&diff, recreated.path(), skel.path().components().count()).unwrap();
+        restore_blobs(root.path(), "alice", recreated.path()).unwrap();
+        assert_eq!(read_tree(recreated.path()), read_tree(submission.path()));
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{copy, Error, ErrorKind, Result, Seek},
+    path::Path,
+};
+
+use tempfile::{tempdir, tempfile};
+use zip::ZipArchive;
+
+use crate::util::extract_zipfile;
+
+use super::{OnSubmission, Submission};
+
+/// Canvas' "Download Submissions" zip holds every uploaded file, named `{name}_[late_]{user id}_{file id}_{file name}`.
+/// A student's single zip upload is used as is, otherwise all their uploads make up the project directory
+pub fn for_each_submission(path: &Path, on_submission: &mut OnSubmission) -> Result<()> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+
+    // Student, (user id, [(index in zip, uploaded file name)])
+    let mut uploads: BTreeMap<String, (String, Vec<(usize, String)>)> = BTreeMap::new();
+    for i in 0..zip.len() {
+        let entry = zip.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        match parse_file_name(&name) {
+            Some((student, user_id, file_name)) => {
+                uploads.entry(student.to_string())
+                    .or_insert_with(|| (user_id.to_string(), Vec::new()))
+                    .1
+                    .push((i, file_name.to_string()));
+            }
+            None => on_submission(&name, None, Err(Error::new(
+                ErrorKind::InvalidData,
+                "Canvas submission files are named '{name}_[late_]{user id}_{file id}_{file name}'. Perhaps this isn't a canvas submission zipfile.",
+            ))),
+        }
+    }
+
+    for (student, (user_id, files)) in uploads {
+        let submission = match files.as_slice() {
+            [(i, file_name)] if file_name.to_lowercase().ends_with(".zip") => {
+                extract_upload(&mut zip, *i)
+            }
+            _ => extract_uploads(&mut zip, &files),
+        };
+        on_submission(&student, Some(&user_id), submission);
+    }
+
+    Ok(())
+}
+
+fn extract_upload(zip: &mut ZipArchive<File>, index: usize) -> Result<Submission> {
+    let mut file = tempfile()?;
+    copy(&mut zip.by_index(index)?, &mut file)?;
+    file.rewind()?;
+    Ok(Submission::Zip(file))
+}
+
+fn extract_uploads(zip: &mut ZipArchive<File>, files: &[(usize, String)]) -> Result<Submission> {
+    let dir = tempdir()?;
+    for (i, file_name) in files {
+        extract_zipfile(zip.by_index(*i)?, &dir.path().join(file_name))?;
+    }
+    Ok(Submission::Extracted(dir))
+}
+
+/// `doejane_late_1234_5678_App.java` is ("doejane", "1234", "App.java")
+fn parse_file_name(name: &str) -> Option<(&str, &str, &str)> {
+    // Uploads are at the top level, but tolerate the zip being wrapped in a folder
+    let name = name.rsplit('/').next()?;
+    let (student, rest) = name.split_once('_')?;
+    let rest = rest.strip_prefix("late_").or_else(|| rest.strip_prefix("LATE_")).unwrap_or(rest);
+    let (user_id, rest) = rest.split_once('_')?;
+    let (file_id, file_name) = rest.split_once('_')?;
+    let is_id = |id: &str| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit());
+    if student.is_empty() || matches!(file_name, "" | "." | "..") || !is_id(user_id) || !is_id(file_id) {
+        return None;
+    }
+    Some((student, user_id, file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_file_name;
+
+    #[test]
+    fn test_parse_file_name() {
+        assert_eq!(parse_file_name("doejane_1234_5678_Impl.zip"), Some(("doejane", "1234", "Impl.zip")));
+        assert_eq!(parse_file_name("doejane_late_1234_5678_App_Test.java"), Some(("doejane", "1234", "App_Test.java")));
+        assert_eq!(parse_file_name("submissions/doejane_LATE_1234_5678_App.java"), Some(("doejane", "1234", "App.java")));
+        assert_eq!(parse_file_name("doejane_Impl.zip"), None);
+        assert_eq!(parse_file_name("doejane_1234_Impl.zip"), None);
+    }
+}
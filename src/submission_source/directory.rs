@@ -0,0 +1,65 @@
+use std::{
+    io::Result,
+    path::{Path, PathBuf},
+};
+
+use tempfile::tempdir;
+
+use crate::util::extract_tarball;
+
+use super::{student_submission, visible_entries, OnSubmission};
+
+/// One entry per student, named after them: a project directory, a zip, or a tarball
+pub fn for_each_submission(path: &Path, on_submission: &mut OnSubmission) -> Result<()> {
+    for entry in visible_entries(path)? {
+        let student = student_name(&entry);
+        on_submission(&student, None, student_submission(&entry));
+    }
+    Ok(())
+}
+
+/// A tarball of a directory source, which may be wrapped in a single top level directory
+pub fn for_each_submission_in_tarball(path: &Path, on_submission: &mut OnSubmission) -> Result<()> {
+    let extracted = tempdir()?;
+    extract_tarball(path, extracted.path())?;
+    let mut root: PathBuf = extracted.path().to_path_buf();
+    if let [entry] = visible_entries(&root)?.as_slice() {
+        if entry.is_dir() {
+            root = entry.clone();
+        }
+    }
+    for_each_submission(&root, on_submission)
+}
+
+/// The entry's name without its archive extension
+fn student_name(entry: &Path) -> String {
+    let name = entry.file_name().unwrap_or_default().to_string_lossy().to_string();
+    if entry.is_dir() {
+        return name;
+    }
+    let lower = name.to_ascii_lowercase();
+    let extension = [".zip", ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz"]
+        .iter()
+        .filter(|ext| lower.ends_with(*ext))
+        .map(|ext| ext.len())
+        .max();
+    match extension {
+        Some(len) if len < name.len() => name[..name.len() - len].to_string(),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::student_name;
+
+    #[test]
+    fn test_student_name() {
+        assert_eq!(student_name(Path::new("/nonexistent/Jane Doe.zip")), "Jane Doe");
+        assert_eq!(student_name(Path::new("/nonexistent/jdoe.tar.gz")), "jdoe");
+        assert_eq!(student_name(Path::new("/nonexistent/jdoe.TGZ")), "jdoe");
+        assert_eq!(student_name(Path::new("/nonexistent/notes.txt")), "notes.txt");
+    }
+}
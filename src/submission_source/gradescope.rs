@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+use tempfile::tempdir;
+use zip::ZipArchive;
+
+use super::{student_submission, visible_entries, OnSubmission};
+
+const METADATA_FILE: &str = "submission_metadata.yml";
+
+/// Gradescope's "Export Submissions" zip, or the directory it extracts to, holds a `submission_{id}/` directory of
+/// uploaded files per submission and `submission_metadata.yml` naming each submission's submitters
+pub fn for_each_submission(path: &Path, on_submission: &mut OnSubmission) -> Result<()> {
+    if path.is_dir() {
+        return for_each_submission_in_dir(path, on_submission);
+    }
+    let extracted = tempdir()?;
+    ZipArchive::new(File::open(path)?)?.extract(extracted.path())?;
+    for_each_submission_in_dir(extracted.path(), on_submission)
+}
+
+fn for_each_submission_in_dir(path: &Path, on_submission: &mut OnSubmission) -> Result<()> {
+    let root = export_root(path)?;
+    let submitters = fs::read_to_string(root.join(METADATA_FILE))
+        .map(|metadata| parse_submitters(&metadata))
+        .unwrap_or_default();
+
+    for entry in visible_entries(&root)? {
+        let dir_name = entry.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if !entry.is_dir() || !dir_name.starts_with("submission_") {
+            continue;
+        }
+        // Without metadata the submission id is the best name there is
+        let student = submitters.get(&dir_name).unwrap_or(&dir_name);
+        on_submission(student, None, student_submission(&entry));
+    }
+    Ok(())
+}
+
+/// The export is usually wrapped in an `assignment_{id}_export/` directory
+fn export_root(path: &Path) -> Result<PathBuf> {
+    if path.join(METADATA_FILE).is_file() {
+        return Ok(path.to_path_buf());
+    }
+    for entry in visible_entries(path)? {
+        if entry.join(METADATA_FILE).is_file() {
+            return Ok(entry);
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!("No {} in gradescope export. Perhaps this isn't a gradescope export.", METADATA_FILE),
+    ))
+}
+
+/// Maps each submission directory to the name of its first submitter. Only reads the part of the metadata that is
+/// needed, which looks like
+/// ```yaml
+/// submission_123456:
+///   :submitters:
+///   - :name: Jane Doe
+///     :sid: '1234'
+/// ```
+fn parse_submitters(metadata: &str) -> HashMap<String, String> {
+    let mut submitters = HashMap::new();
+    let mut submission: Option<&str> = None;
+    for line in metadata.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            submission = line.strip_suffix(':').filter(|key| key.starts_with("submission_"));
+            continue;
+        }
+        let field = line.trim_start().trim_start_matches("- ");
+        if let (Some(submission), Some(name)) = (submission, field.strip_prefix(":name:")) {
+            let name = name.trim().trim_matches(|c| c == '\'' || c == '"');
+            if !name.is_empty() {
+                submitters.entry(submission.to_string()).or_insert_with(|| name.to_string());
+            }
+        }
+    }
+    submitters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_submitters;
+
+    #[test]
+    fn test_parse_submitters() {
+        let metadata = "\
+submission_1:
+  :submitters:
+  - :name: Jane Doe
+    :sid: '1234'
+    :email: jane@example.com
+  - :name: John Doe
+  :created_at: 2024-01-01
+  :score: 10.0
+submission_2:
+  :submitters:
+  - :name: 'Alex Smith'
+";
+        let submitters = parse_submitters(metadata);
+        assert_eq!(submitters.len(), 2);
+        assert_eq!(submitters["submission_1"], "Jane Doe");
+        assert_eq!(submitters["submission_2"], "Alex Smith");
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs::{self, File},
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+use tempfile::{tempdir, tempfile, TempDir};
+use zip::ZipArchive;
+
+use crate::{
+    darwin_config::SubmissionSource,
+    util::{extract_tarball, is_tarball, zip_dir},
+};
+
+mod canvas;
+mod directory;
+mod gradescope;
+mod moodle;
+
+/// A single student's project, as found in a source
+pub enum Submission {
+    /// A zip of the project, eg. the zip the student uploaded
+    Zip(File),
+    /// A directory containing the project
+    Directory(PathBuf),
+    /// The project extracted into a temporary directory, removed once the submission is dropped
+    Extracted(TempDir),
+}
+
+/// Student, the id the source knows them by if any, eg. Moodle's participant id, and their submission
+pub type OnSubmission<'a> = dyn FnMut(&str, Option<&str>, Result<Submission>) + 'a;
+
+impl Submission {
+    /// Every source goes through the same zip normalization, so directories are zipped first
+    pub fn into_zip(self) -> Result<ZipArchive<File>> {
+        let file = match self {
+            Submission::Zip(file) => file,
+            Submission::Directory(dir) => zip_dir(&dir, tempfile()?)?,
+            Submission::Extracted(dir) => zip_dir(dir.path(), tempfile()?)?,
+        };
+        Ok(ZipArchive::new(file)?)
+    }
+}
+
+/// Calls on_submission with every submission in path.
+/// Errors for a single student are passed to on_submission, errors reading the source itself are returned
+pub fn for_each_submission(
+    source: SubmissionSource,
+    path: &Path,
+    on_submission: &mut OnSubmission,
+) -> Result<()> {
+    validate(source, path)?;
+    // Student names end up in file names and in `student:reason` lines
+    let on_submission = &mut |student: &str, id: Option<&str>, submission: Result<Submission>| {
+        on_submission(&sanitize_student_name(student), id, submission)
+    };
+    match source {
+        SubmissionSource::Moodle => moodle::for_each_submission(path, on_submission),
+        SubmissionSource::Canvas => canvas::for_each_submission(path, on_submission),
+        SubmissionSource::Gradescope => gradescope::for_each_submission(path, on_submission),
+        SubmissionSource::Directory => directory::for_each_submission(path, on_submission),
+        SubmissionSource::Tarball => directory::for_each_submission_in_tarball(path, on_submission),
+    }
+}
+
+fn validate(source: SubmissionSource, path: &Path) -> Result<()> {
+    let valid = match source {
+        SubmissionSource::Moodle | SubmissionSource::Canvas => path.is_file() && is_zip(path),
+        SubmissionSource::Gradescope => path.is_dir() || (path.is_file() && is_zip(path)),
+        SubmissionSource::Directory => path.is_dir(),
+        SubmissionSource::Tarball => path.is_file() && is_tarball(path),
+    };
+    if !valid {
+        let expected = match source {
+            SubmissionSource::Moodle | SubmissionSource::Canvas => "a zipfile",
+            SubmissionSource::Gradescope => "a zipfile or directory",
+            SubmissionSource::Directory => "a directory",
+            SubmissionSource::Tarball => "a tarball",
+        };
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} submissions path {} is not {}", source, path.display(), expected),
+        ));
+    }
+    Ok(())
+}
+
+/// A single student's submission: a zip, a tarball, or a project directory.
+/// A directory containing nothing but a zip or tarball is treated as that archive
+pub fn student_submission(path: &Path) -> Result<Submission> {
+    if path.is_dir() {
+        let entries = visible_entries(path)?;
+        if let [entry] = entries.as_slice() {
+            if entry.is_file() && (is_zip(entry) || is_tarball(entry)) {
+                return student_submission(entry);
+            }
+        }
+        return Ok(Submission::Directory(path.to_path_buf()));
+    }
+    if is_tarball(path) {
+        let extracted = tempdir()?;
+        extract_tarball(path, extracted.path())?;
+        return Ok(Submission::Extracted(extracted));
+    }
+    if path.is_file() && is_zip(path) {
+        return Ok(Submission::Zip(File::open(path)?));
+    }
+    Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!("{} is not a zipfile, tarball or directory", path.display()),
+    ))
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Directory entries, sorted, without hidden files and the __MACOSX folders macOS adds to zips
+fn visible_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if !name.starts_with('.') && name != "__MACOSX" {
+            entries.push(path);
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn sanitize_student_name(student: &str) -> String {
+    student.trim().replace(['/', '\\', ':', '\n', '\r'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize_student_name;
+
+    #[test]
+    fn test_sanitize_student_name() {
+        assert_eq!(sanitize_student_name("Jane Doe"), "Jane Doe");
+        assert_eq!(sanitize_student_name(" a/b:c\n"), "a_b_c");
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+};
+
+use tempfile::tempfile;
+use zip::ZipArchive;
+
+use crate::util::extract_file;
+
+use super::{OnSubmission, Submission};
+
+/// Moodle's "Download all submissions" zip holds one `{full name}_{id}_assignsubmission_file_{file name}.zip` per student
+pub fn for_each_submission(path: &Path, on_submission: &mut OnSubmission) -> Result<()> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+
+    let file_names: Vec<String> = zip.file_names().map(String::from).collect();
+    for file_name in file_names {
+        if Path::new(&file_name).extension().is_none_or(|x| x != "zip") {
+            continue;
+        }
+
+        let Some((student, participant_id)) = parse_file_name(&file_name) else {
+            on_submission(&file_name, None, Err(Error::new(
+                ErrorKind::InvalidData,
+                "Moodle submission zipfiles are named '{full name}_{id}_...'. Perhaps moodle changed its naming scheme or this isn't a moodle submission zipfile.",
+            )));
+            continue;
+        };
+
+        let mut student_submission_file = tempfile()?;
+        let submission = extract_file(&mut zip, &file_name, &mut student_submission_file)
+            .map(|_| Submission::Zip(student_submission_file))
+            .map_err(|e| Error::other(format!("Error extracting submission: {}", e)));
+        on_submission(student, participant_id, submission);
+    }
+
+    Ok(())
+}
+
+/// Student and participant id
+fn parse_file_name(file_name: &str) -> Option<(&str, Option<&str>)> {
+    let (student, rest) = file_name.split_once('_')?;
+    if student.is_empty() {
+        return None;
+    }
+    let participant_id = rest
+        .split_once('_')
+        .map(|(id, _)| id)
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()));
+    Some((student, participant_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_file_name;
+
+    #[test]
+    fn test_parse_file_name() {
+        assert_eq!(parse_file_name("Jane Doe_123456_assignsubmission_file_Impl.zip"), Some(("Jane Doe", Some("123456"))));
+        assert_eq!(parse_file_name("Jane Doe_123456_assignsubmission_file_/Impl.zip"), Some(("Jane Doe", Some("123456"))));
+        assert_eq!(parse_file_name("Jane Doe_Impl.zip"), Some(("Jane Doe", None)));
+        assert_eq!(parse_file_name("Impl.zip"), None);
+        assert_eq!(parse_file_name("_Impl.zip"), None);
+    }
+}
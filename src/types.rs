@@ -1,15 +1,23 @@
-use std::{collections::HashMap, fmt::Display, io::Error, time::Duration};
+use std::{collections::HashMap, fmt::Display, io::Error, path::PathBuf, time::Duration};
 
+use serde::{Deserialize, Serialize, Serializer};
 
+
+#[derive(Serialize)]
 pub struct TestResults {
     pub student: String,
     pub test: String,
+    #[serde(flatten)]
     pub state: TestState,
 }
 
-#[derive(Debug)]
+/// Serialized as a `state` field next to the student and test, with `results` when it is `ok`
+#[derive(Debug, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
 pub enum TestState {
     CompilationError,
+    /// Compiling or running the test was killed for exceeding its time limit
+    Timeout,
     Ok { results: Vec<TestResult> },
 }
 
@@ -19,40 +27,170 @@ pub enum TestResultError {
     TestsNotRun,
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
+#[derive(Debug, PartialEq, Serialize)]
 pub struct TestResult {
     pub name: String,
     pub classname: String,
+    /// Serialized in seconds
+    #[serde(serialize_with = "serialize_secs")]
     pub time: Duration,
+    #[serde(flatten)]
     pub msg: StatusMsg,
 }
 
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
 impl Display for TestResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}: {:?}", self.name, self.msg)
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Serialized as a `status` field of `passed`, `failure` or `error`
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
 pub enum StatusMsg {
+    #[serde(rename = "passed")]
     None,
     Failure {
         message: Option<String>,
+        #[serde(rename = "type")]
         type_: String,
         full_message: Option<String>,
     },
     Error {
         message: Option<String>,
+        #[serde(rename = "type")]
         type_: String,
         full_message: Option<String>,
     },
 }
 
+/// How far a student got with a test. Unlike TestState it includes tests that have not been run
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultState {
+    Ok,
+    CompilationError,
+    Timeout,
+    NotRun,
+}
+
+impl Display for ResultState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResultState::Ok => write!(f, "Ok"),
+            ResultState::CompilationError => write!(f, "Compilation Error"),
+            ResultState::Timeout => write!(f, "Timed Out"),
+            ResultState::NotRun => write!(f, "Not Run"),
+        }
+    }
+}
+
+/// Number of a student's tests that passed, errored and failed
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ResultSummary {
+    pub student: String,
+    pub test: String,
+    pub state: ResultState,
+    pub correct: usize,
+    pub errored: usize,
+    pub failed: usize,
+}
+
+/// ResultSummary for each test class
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ClassNameSummary {
+    pub student: String,
+    pub test: String,
+    pub state: ResultState,
+    /// Sorted by classname
+    pub classes: Vec<ClassSummary>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ClassSummary {
+    pub classname: String,
+    pub correct: usize,
+    pub errored: usize,
+    pub failed: usize,
+}
+
+/// A single error reported by the compiler
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompileDiagnostic {
+    /// Relative to the normalized project
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Display for CompileDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "{}:{}:{}: {}", self.file.display(), self.line, column, self.message),
+            None => write!(f, "{}:{}: {}", self.file.display(), self.line, self.message),
+        }
+    }
+}
+
+/// Why a student's project failed to compile. compile_fn returns it wrapped in an `io::Error`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompileError {
+    /// One line description, eg. `'mvn test-compile' failed`
+    pub summary: String,
+    /// Everything the build wrote to stdout and stderr
+    pub output: String,
+    pub diagnostics: Vec<CompileDiagnostic>,
+}
+
+impl CompileError {
+    pub fn from_io_error(e: &Error) -> Option<&CompileError> {
+        e.get_ref().and_then(|e| e.downcast_ref::<CompileError>())
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.summary)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<CompileError> for Error {
+    fn from(e: CompileError) -> Error {
+        Error::other(e)
+    }
+}
+
+impl ResultSummary {
+    pub fn not_run(student: &str, test: &str) -> ResultSummary {
+        ResultSummary {
+            student: student.to_string(),
+            test: test.to_string(),
+            state: ResultState::NotRun,
+            correct: 0,
+            errored: 0,
+            failed: 0,
+        }
+    }
+}
+
+impl ClassNameSummary {
+    pub fn not_run(student: &str, test: &str) -> ClassNameSummary {
+        ClassNameSummary { student: student.to_string(), test: test.to_string(), state: ResultState::NotRun, classes: Vec::new() }
+    }
+}
+
 impl TestResults {
     pub fn summary(&self) -> (bool, usize, usize, usize) {
         match &self.state {
             TestState::CompilationError => (true, 0, 0, 0),
+            TestState::Timeout => (false, 0, 0, 0),
             TestState::Ok { results } => {
                 let num_correct = results.iter().filter(|r| r.msg == StatusMsg::None).count();
                 let num_errored = results
@@ -69,6 +207,9 @@ impl TestResults {
         // Correct, errored, failed
     }
     pub fn summarize(&self) -> String {
+        if matches!(self.state, TestState::Timeout) {
+            return format!("{}_{}: Timed Out", self.student, self.test);
+        }
         let summary = self.summary();
         format!(
             "{}_{}: Compilation Error: {}, Correct: {}, Errored: {}, Failed: {}",
@@ -78,21 +219,50 @@ impl TestResults {
 
     pub fn group_by_classname(&self) -> Option<HashMap<String, Vec<&TestResult>>> {
         match &self.state {
-            TestState::CompilationError => None,
+            TestState::CompilationError | TestState::Timeout => None,
             TestState::Ok { results } => {
                 let mut m: HashMap<String, Vec<&TestResult>> = HashMap::new();
                 for result in results.iter() {
-                    m.entry(result.classname.clone())
-                        .and_modify(|e| e.push(result))
-                        .or_default();
+                    m.entry(result.classname.clone()).or_default().push(result);
                 }
                 Some(m)
             }
         }
     }
 
+    /// A line per test with its status and time, followed by the message of failures and errors, indented
     pub fn everything(&self) -> String {
-        format!("{:?}", self.state)
+        let results = match &self.state {
+            TestState::CompilationError => return format!("{}_{}: Compilation Error", self.student, self.test),
+            TestState::Timeout => return format!("{}_{}: Timed Out", self.student, self.test),
+            TestState::Ok { results } => results,
+        };
+        let mut out = format!("{}_{}", self.student, self.test);
+        for result in results {
+            let (status, message) = match &result.msg {
+                StatusMsg::None => ("Passed", None),
+                StatusMsg::Failure { message, type_, full_message } => ("Failure", Some((type_, message, full_message))),
+                StatusMsg::Error { message, type_, full_message } => ("Error", Some((type_, message, full_message))),
+            };
+            out.push_str(&format!(
+                "\n  {}.{}: {} ({:.3}s)",
+                result.classname,
+                result.name,
+                status,
+                result.time.as_secs_f64()
+            ));
+            let Some((type_, message, full_message)) = message else {
+                continue;
+            };
+            match message {
+                Some(message) => out.push_str(&format!(" {}: {}", type_, message.lines().next().unwrap_or_default())),
+                None => out.push_str(&format!(" {}", type_)),
+            }
+            for line in full_message.iter().flat_map(|m| m.lines()) {
+                out.push_str(&format!("\n      {}", line));
+            }
+        }
+        out
     }
 
     pub fn summarize_by_classname(&self) -> Option<HashMap<String, (i32, i32, i32)>> {
@@ -102,15 +272,12 @@ impl TestResults {
                 let mut m: HashMap<String, (i32, i32, i32)> = HashMap::new();
                 for (_, v) in s.iter() {
                     for res in v.iter() {
-                        m.entry(res.classname.clone())
-                            .and_modify(|item| {
-                                match res.msg {
-                                    StatusMsg::None => item.0 += 1,
-                                    StatusMsg::Error { .. } => item.1 += 1,
-                                    StatusMsg::Failure { .. } => item.2 += 1,
-                                };
-                            })
-                            .or_insert((0, 0, 0));
+                        let item = m.entry(res.classname.clone()).or_insert((0, 0, 0));
+                        match res.msg {
+                            StatusMsg::None => item.0 += 1,
+                            StatusMsg::Error { .. } => item.1 += 1,
+                            StatusMsg::Failure { .. } => item.2 += 1,
+                        };
                     }
                 }
                 Some(m)
@@ -118,8 +285,149 @@ impl TestResults {
         }
     }
 
+    pub fn result_state(&self) -> ResultState {
+        match self.state {
+            TestState::CompilationError => ResultState::CompilationError,
+            TestState::Timeout => ResultState::Timeout,
+            TestState::Ok { .. } => ResultState::Ok,
+        }
+    }
+
+    pub fn to_summary(&self) -> ResultSummary {
+        let (_, correct, errored, failed) = self.summary();
+        ResultSummary {
+            student: self.student.clone(),
+            test: self.test.clone(),
+            state: self.result_state(),
+            correct,
+            errored,
+            failed,
+        }
+    }
+
+    pub fn to_classname_summary(&self) -> ClassNameSummary {
+        let mut classes: Vec<ClassSummary> = self
+            .summarize_by_classname()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(classname, (correct, errored, failed))| ClassSummary {
+                classname,
+                correct: correct as usize,
+                errored: errored as usize,
+                failed: failed as usize,
+            })
+            .collect();
+        classes.sort_by(|a, b| a.classname.cmp(&b.classname));
+        ClassNameSummary { student: self.student.clone(), test: self.test.clone(), state: self.result_state(), classes }
+    }
+
+    /// A line per test class, sorted by class name
     pub fn print(&self) -> String {
-        let m = self.summarize_by_classname();
-        format!("{}_{} {:?}", self.student, self.test, m)
+        match self.state {
+            TestState::CompilationError => return format!("{}_{}: Compilation Error", self.student, self.test),
+            TestState::Timeout => return format!("{}_{}: Timed Out", self.student, self.test),
+            TestState::Ok { .. } => {}
+        }
+        let mut out = format!("{}_{}", self.student, self.test);
+        for class in self.to_classname_summary().classes {
+            out.push_str(&format!(
+                "\n  {}: Correct: {}, Errored: {}, Failed: {}",
+                class.classname, class.correct, class.errored, class.failed
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::{ClassSummary, StatusMsg, TestResult, TestResults, TestState};
+
+    fn test_result(name: &str, classname: &str, msg: StatusMsg) -> TestResult {
+        TestResult { name: name.to_string(), classname: classname.to_string(), time: Duration::from_millis(1500), msg }
+    }
+
+    #[test]
+    fn test_results_json() {
+        let results = TestResults {
+            student: "Alice".to_string(),
+            test: "StackTest".to_string(),
+            state: TestState::Ok {
+                results: vec![
+                    test_result("testPush", "StackTest", StatusMsg::None),
+                    test_result(
+                        "testPop",
+                        "StackTest",
+                        StatusMsg::Failure { message: Some("expected 1".to_string()), type_: "AssertionError".to_string(), full_message: None },
+                    ),
+                ],
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&results).unwrap(),
+            json!({
+                "student": "Alice",
+                "test": "StackTest",
+                "state": "ok",
+                "results": [
+                    {"name": "testPush", "classname": "StackTest", "time": 1.5, "status": "passed"},
+                    {
+                        "name": "testPop",
+                        "classname": "StackTest",
+                        "time": 1.5,
+                        "status": "failure",
+                        "message": "expected 1",
+                        "type": "AssertionError",
+                        "full_message": null
+                    }
+                ]
+            })
+        );
+        // Every result is counted, including the first of each class
+        assert_eq!(
+            results.to_classname_summary().classes,
+            [ClassSummary { classname: "StackTest".to_string(), correct: 1, errored: 0, failed: 1 }]
+        );
+
+        let compile_error = TestResults { state: TestState::CompilationError, ..results };
+        assert_eq!(
+            serde_json::to_value(&compile_error).unwrap(),
+            json!({"student": "Alice", "test": "StackTest", "state": "compilation_error"})
+        );
+    }
+
+    #[test]
+    fn test_results_text() {
+        let results = TestResults {
+            student: "Alice".to_string(),
+            test: "StackTest".to_string(),
+            state: TestState::Ok {
+                results: vec![
+                    test_result("testPush", "StackTest", StatusMsg::None),
+                    test_result(
+                        "testPop",
+                        "StackTest",
+                        StatusMsg::Failure {
+                            message: Some("expected 1".to_string()),
+                            type_: "AssertionError".to_string(),
+                            full_message: Some("expected 1\n\tat StackTest.testPop".to_string()),
+                        },
+                    ),
+                ],
+            },
+        };
+        assert_eq!(
+            results.everything(),
+            "Alice_StackTest\n  StackTest.testPush: Passed (1.500s)\n  StackTest.testPop: Failure (1.500s) AssertionError: expected 1\n      expected 1\n      \tat StackTest.testPop"
+        );
+        assert_eq!(results.print(), "Alice_StackTest\n  StackTest: Correct: 1, Errored: 0, Failed: 1");
+
+        let timeout = TestResults { state: TestState::Timeout, ..results };
+        assert_eq!(timeout.print(), "Alice_StackTest: Timed Out");
+        assert_eq!(timeout.everything(), "Alice_StackTest: Timed Out");
     }
 }
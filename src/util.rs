@@ -1,5 +1,5 @@
 use std::collections::{HashMap, HashSet};
-use std::fs::{self, create_dir_all, rename, OpenOptions};
+use std::fs::{self, create_dir_all, rename};
 use std::io::{copy, prelude::*, BufReader, BufWriter, Error, ErrorKind, Result};
 use std::num::ParseIntError;
 use std::path::PathBuf;
@@ -8,9 +8,10 @@ use std::str::FromStr;
 use std::{fs::File, io, path::Path};
 use trie_of_lists::Trie;
 
-use tempfile::{tempdir, NamedTempFile};
+use tempfile::tempdir_in;
 use zip::read::ZipFile;
-use zip::ZipArchive;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
 // TODO: Extract out file tree library
 // 
@@ -23,13 +24,13 @@ use zip::ZipArchive;
 //      entry
 // ).copy_to(|file|dest.join(file));
 
-use crate::{darwin_config, list_students};
+use crate::{darwin_config, diff, list_students};
 use crate::project_runner::Project;
 
 pub fn prompt_digit<T: FromStr<Err = ParseIntError> + ToString>(prompt: &str) -> Result<T> {
     let line = input(prompt)?;
     let line = &line[..line.len() - 1];
-    T::from_str(line).map_err(|e| io::Error::new(ErrorKind::Other, e.to_string()))
+    T::from_str(line).map_err(|e| io::Error::other(e.to_string()))
 }
 
 pub fn prompt_yn(prompt: &str) -> Result<bool> {
@@ -62,7 +63,7 @@ pub fn copy_dir_all(
     if dst.is_dir() && dst.read_dir()?.any(|_|true) {
         return Err(Error::from(ErrorKind::DirectoryNotEmpty));
     }
-    _copy_dir_all(src, dst, ignore).map_err(|e|Error::new(ErrorKind::Other, format!("Failed to copy {:?} to {:?}: {}", src, dst, e)))
+    _copy_dir_all(src, dst, ignore).map_err(|e|Error::other(format!("Failed to copy {:?} to {:?}: {}", src, dst, e)))
 }
 pub fn _copy_dir_all(
     src: impl AsRef<Path>,
@@ -77,7 +78,7 @@ pub fn _copy_dir_all(
         let ty = entry.file_type()?;
         if ty.is_dir() {
             _copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()), ignore)?;
-        } else if ignore.map_or(true, |i|!i.contains(entry.file_name().to_str().unwrap())) {
+        } else if ignore.is_none_or(|i|!i.contains(entry.file_name().to_str().unwrap())) {
             fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
         }
     }
@@ -117,7 +118,7 @@ fn _flatten_move_recursive(
                         name.set_extension("html");
                         if let Some(name) = name.file_name() {
                             if ignore
-                                .is_some_and(|i| name.to_str().map_or(false, |n| i.contains(n)))
+                                .is_some_and(|i| name.to_str().is_some_and(|n| i.contains(n)))
                             {
                                 continue;
                             }
@@ -186,7 +187,7 @@ pub fn project_root_in_zip(zip: &mut ZipArchive<File>, project_structure: &HashS
         if let Some(file_name) = zip.by_index(i)?.enclosed_name() {
             for part in project_structure.iter().cloned() {
                 if let Some(parent) = subpath_parent(&file_name, part) {
-                    project_structure_mappings.entry(parent).or_insert_with(HashSet::new).insert(part);
+                    project_structure_mappings.entry(parent).or_default().insert(part);
                 }
             }
         }
@@ -195,13 +196,13 @@ pub fn project_root_in_zip(zip: &mut ZipArchive<File>, project_structure: &HashS
 
     match roots.len() {
         0 => {
-            Err(Error::new(ErrorKind::Other, "No project root found in zipfile matching project_structure"))
+            Err(Error::other("No project root found in zipfile matching project_structure"))
         }
         1 => {
             Ok(roots[0].clone())
         }
         _ => {
-            Err(Error::new(ErrorKind::Other, format!("Too many project roots found in zipfile matching project_structure: {:?}", roots)))
+            Err(Error::other(format!("Too many project roots found in zipfile matching project_structure: {:?}", roots)))
         }
     }
 }
@@ -235,18 +236,10 @@ pub fn create_diff(original: &Path, deviant: &Path, dest_path: &Path) -> Result<
 }
 
 fn _create_diff(original: &Path, deviant: &Path, dest_path: &Path) -> Result<()> {
-    let output = Command::new("diff")
-        .arg("-ruN")
-        .arg(original)
-        .arg(deviant)
-        .stdout(Stdio::piped())
-        .spawn()?;
-
     let diff_file = File::create(dest_path)?;
-    let mut stdout_writer = BufWriter::new(diff_file);
-    let mut stdout = output.stdout.unwrap();
-    copy(&mut stdout, &mut stdout_writer)?;
-    stdout_writer.flush()?;
+    let mut diff_writer = BufWriter::new(diff_file);
+    diff::diff_trees(original, deviant, &mut diff_writer)?;
+    diff_writer.flush()?;
 
     Ok(())
 }
@@ -260,6 +253,13 @@ pub fn is_student(student: &str) -> bool {
     list_students::list_students().iter().any(|s| s == student)
 }
 
+/// Whether a `student` or `student:...` line of the compile errors or timeouts kept before the result store belongs to
+/// the student
+pub fn is_student_line(line: &str, student: &str) -> bool {
+    line.strip_prefix(student)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+}
+
 /// Applies transformation to directory structure
 /// eg. 
 pub fn directory_transform(dir: &Path, transformation: &HashMap<PathBuf, PathBuf>) -> Result<()> {
@@ -268,7 +268,11 @@ pub fn directory_transform(dir: &Path, transformation: &HashMap<PathBuf, PathBuf
         let path_parts = k.components().map(|part|part.as_os_str().to_string_lossy().to_string());
         trie.insert(path_parts, v.clone());
     }
-    let temp_dir = tempdir()?;
+    // Created next to dir so the final rename does not cross filesystems
+    let temp_dir = match dir.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => tempdir_in(parent)?,
+        _ => tempdir_in(".")?,
+    };
     for file in dir_list_absolute_file_paths_recursively(dir) {
         let stripped_file = file.strip_prefix(dir).map_err(|_|Error::from(ErrorKind::Other))?;
         let path_parts = stripped_file.components().map(|part|part.as_os_str().to_string_lossy().to_string());
@@ -278,13 +282,13 @@ pub fn directory_transform(dir: &Path, transformation: &HashMap<PathBuf, PathBuf
             if let Some(parent) = dest.parent() {
                 create_dir_all(parent)?;
             }
-            rename(&file, &dest).map_err(|e|Error::new(ErrorKind::Other, format!("Failed to rename {:?} to {:?}: {}", file, &dest, e)))?;
+            rename(&file, &dest).map_err(|e|Error::other(format!("Failed to rename {:?} to {:?}: {}", file, &dest, e)))?;
         } else {
             let dest = temp_dir.path().join(stripped_file);
             if let Some(parent) = dest.parent() {
                 create_dir_all(parent)?;
             }
-            rename(&file, &dest).map_err(|e|Error::new(ErrorKind::Other, format!("Failed to rename {:?} to {:?}: {}", file, dest, e)))?;
+            rename(&file, &dest).map_err(|e|Error::other(format!("Failed to rename {:?} to {:?}: {}", file, dest, e)))?;
         }
     }
     let backup_path = dir.with_extension("bak");
@@ -302,44 +306,12 @@ pub fn directory_transform(dir: &Path, transformation: &HashMap<PathBuf, PathBuf
 // patch_path: Directory containing original files
 // diff_path: Diff to be patched into patch_path
 // Destination path
-pub fn patch(patch_path: &Path, diff_path: &Path, dest_path: &Path, silent: bool) -> Result<()> {
+// Paths in the diff are relative to the parent of patch_path, as created by create_diff
+pub fn patch(patch_path: &Path, diff_path: &Path, dest_path: &Path) -> Result<()> {
     copy_dir_all(patch_path, dest_path, Some(&HashSet::new())).unwrap();
 
-    let stdout = match silent {
-        true => Stdio::null(),
-        false => Stdio::inherit()
-    };
-
-    let mut output = Command::new("patch")
-        .arg("-d")
-        .arg(dest_path)
-        .arg("-p2")
-        .stdin(Stdio::piped())
-        .stdout(stdout)
-        .spawn()?;
-
-    match output.stdin.take() {
-        Some(stdin) => {
-            let mut stdin_writer = BufWriter::new(stdin);
-            let patch_file = File::open(diff_path)?;
-            let mut patch_reader = BufReader::new(patch_file);
-            copy(&mut patch_reader, &mut stdin_writer)?;
-        }
-        None => {
-            return Err(Error::new(
-                ErrorKind::BrokenPipe,
-                format!("Cannot access stdin of patch process {}", output.id()),
-            ));
-        }
-    }
-
-    if output.wait().is_err() {
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Failed to wait for patch process",
-        ));
-    }
-    Ok(())
+    let diff = fs::read(diff_path)?;
+    diff::apply_patch(&diff, dest_path, 2)
 }
 
 
@@ -371,64 +343,72 @@ pub fn extract_zipfile(zipfile: ZipFile, dest: &Path) -> Result<()> {
     if let Some(parent) = dest.parent() {
         create_dir_all(parent)?;
     }
-    let dest_file = File::create(&dest)
-        .map_err(|e| Error::new(ErrorKind::Other, format!("Creating Dest file: {}", e)))?;
+    let dest_file = File::create(dest)
+        .map_err(|e| Error::other(format!("Creating Dest file: {}", e)))?;
     let mut reader = BufReader::new(zipfile);
     let mut writer = BufWriter::new(dest_file);
 
     // Copy the contents of the zip entry to the new file
     copy(&mut reader, &mut writer)
-        .map_err(|e| Error::new(ErrorKind::Other, format!("Copying zip entry to dest: {}", e)))?;
+        .map_err(|e| Error::other(format!("Copying zip entry to dest: {}", e)))?;
 
     // Flush the writer to ensure all data is written
     writer
         .flush()
-        .map_err(|e| Error::new(ErrorKind::Other, format!("Flushing buffer: {}", e)))?;
+        .map_err(|e| Error::other(format!("Flushing buffer: {}", e)))?;
     Ok(())
 }
 
-pub fn file_contains_line(file: &Path, line: &str) -> Result<bool> {
-    let file = File::open(file)?;
-    let mut file = BufReader::new(file);
-    let mut cur_line = String::new();
-    loop {
-        cur_line.clear();
-        match file.read_line(&mut cur_line) {
-            Ok(0) => {
-                return Ok(false);
-            }
-            Ok(_) => {
-                if line == &cur_line[0..cur_line.len() - 1] {
-                    return Ok(true);
-                }
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        }
-    }
+/// Writes dir and everything in it into dest as an uncompressed zip, with paths relative to dir.
+/// Returns dest, positioned at the end of the zip
+pub fn zip_dir(dir: &Path, dest: File) -> Result<File> {
+    let mut zip = ZipWriter::new(dest);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    zip_add_dir(&mut zip, dir, Path::new(""), options)?;
+    Ok(zip.finish()?)
 }
 
-pub fn file_append_line(file: &Path, line: &str) -> Result<()> {
-    let mut f = OpenOptions::new().append(true).open(file)?;
-    writeln!(f, "{}", line)?;
+/// Adds everything in dir to zip under prefix
+pub fn zip_add_dir<W: Write + Seek>(zip: &mut ZipWriter<W>, dir: &Path, prefix: &Path, options: SimpleFileOptions) -> Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.map(|e| e.map(|e| e.path())).collect::<Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        let name = prefix.join(path.file_name().unwrap());
+        // Zip paths always use '/'
+        let zip_name = name.to_string_lossy().replace('\\', "/");
+        if path.is_dir() {
+            zip.add_directory(zip_name, options)?;
+            zip_add_dir(zip, &path, &name, options)?;
+        } else if path.is_file() {
+            zip.start_file(zip_name, options)?;
+            copy(&mut File::open(&path)?, zip)?;
+        }
+    }
     Ok(())
 }
 
-pub fn file_replace_line(filename: &Path, prev: &str, new: &str) -> Result<()> {
-    let tempfile = NamedTempFile::new()?;
-    let reader = File::open(filename)?;
-    let mut reader = BufReader::new(reader);
-    let mut writer = BufWriter::new(tempfile);
-
-    buffer_flatmap(&mut reader, &mut writer, |line| {
-        if line.contains(prev) {
-            return Some(new.to_string());
-        }
-        Some(line.to_string())
-    })?;
-    writer.into_inner()?.persist(filename)?;
+pub fn is_tarball(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    [".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz"].iter().any(|ext| name.ends_with(ext))
+}
 
+/// Extracts a (possibly compressed) tarball into dest, which must exist
+pub fn extract_tarball(tarball: &Path, dest: &Path) -> Result<()> {
+    let output = Command::new("tar")
+        .arg("-xf")
+        .arg(tarball)
+        .arg("-C")
+        .arg(dest)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "Failed to extract {}: {}",
+            tarball.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
     Ok(())
 }
 
@@ -472,7 +452,7 @@ mod tests {
     use std::{collections::HashMap, fs::File, io::Read, path::{Path, PathBuf}};
     use zip::ZipArchive;
     use crate::util::buffer_flatmap;
-    use super::{directory_transform, file_replace_line, project_root_in_zip, subpath_parent, BufReader, BufWriter, Write};
+    use super::{directory_transform, zip_dir, is_student_line, project_root_in_zip, subpath_parent, BufReader, BufWriter, Write};
     use assert_fs::{self, assert::PathAssert, prelude::{FileTouch, FileWriteStr, PathChild}};
     use predicates::prelude::*;
 
@@ -499,19 +479,27 @@ mod tests {
     }
 
     #[test]
-    fn file_replace_line_test() {
-        let mut f = tempfile::NamedTempFile::new().unwrap();
-        write!(f, "a\nb\na\na\naa\n\nbb").unwrap();
-        f.as_file_mut().flush().unwrap();
-        file_replace_line(f.path(), "a", "c\n").unwrap();
+    fn is_student_line_test() {
+        assert!(is_student_line("alice:'mvn test-compile' failed", "alice"));
+        assert!(is_student_line("alice", "alice"));
+        assert!(!is_student_line("alice2:x", "alice"));
+        assert!(!is_student_line("bob:'mvn test-compile' failed", "alice"));
+    }
 
-        let mut actual_contents = String::new();
-        let mut file = File::open(f.path()).unwrap();
-        file.read_to_string(&mut actual_contents).unwrap(); // Read the contents after modification
+    #[test]
+    fn zip_dir_test() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("Impl/pom.xml").write_str("pom").unwrap();
+        temp.child("Impl/src/main/App.java").write_str("class App {}").unwrap();
 
-        let expected_contents = "c\nb\nc\nc\nc\n\nbb";
+        let mut zip = ZipArchive::new(zip_dir(temp.path(), tempfile::tempfile().unwrap()).unwrap()).unwrap();
+        let mut names: Vec<&str> = zip.file_names().collect();
+        names.sort();
+        assert_eq!(names, vec!["Impl/", "Impl/pom.xml", "Impl/src/", "Impl/src/main/", "Impl/src/main/App.java"]);
 
-        assert_eq!(actual_contents, expected_contents);
+        let mut contents = String::new();
+        zip.by_name("Impl/src/main/App.java").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "class App {}");
     }
 
     #[test]
@@ -535,7 +523,7 @@ mod tests {
         submission_zipfile_mapping.insert(PathBuf::from("src/test/"), PathBuf::from("src/test/"));
 
         let root = project_root_in_zip(&mut zip, &submission_zipfile_mapping.keys().collect()).unwrap();
-        assert!(root == PathBuf::from("Impl")); 
+        assert_eq!(root, Path::new("Impl")); 
     }
 
     #[test]
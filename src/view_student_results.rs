@@ -1,9 +1,28 @@
-use std::io;
+use std::{fs::File, io};
+
+use serde::Serialize;
 
 use crate::{
-    config::{compile_errors_file, student_result_file}, darwin_config, list_students::list_students, project_runner::Project, types::{TestResultError, TestResults, TestState}, util::file_contains_line
+    config::report_file, darwin_config, list_students::list_students, project_runner::Project, result_store::{read_result_store, RunStatus}, types::{ClassNameSummary, CompileError, ResultState, ResultSummary, TestResultError, TestResults, TestState}
 };
 
+/// Every test result, and the build output if the project failed to compile
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum VerboseResult {
+    Parsed {
+        #[serde(flatten)]
+        results: TestResults,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        compile_output: Option<CompileError>,
+    },
+    NotRun {
+        student: String,
+        test: String,
+        state: ResultState,
+    },
+}
+
 pub fn parse_test_results(project: &Project, student: &str, test: &str) -> Result<TestResults, TestResultError> {
     if !list_students().iter().any(|s| s == student) {
         return Err(TestResultError::IOError(io::Error::new(
@@ -29,19 +48,78 @@ fn _parse_test_results(project: &Project, student: &str, test: &str) -> Result<T
         state: TestState::CompilationError,
     };
 
-    if file_contains_line(&compile_errors_file(), student).unwrap() {
+    let store = read_result_store().map_err(TestResultError::IOError)?;
+    if store.has_compile_error(student) {
         out.state = TestState::CompilationError;
         return Ok(out);
     }
 
-    let result_path = student_result_file(student, test);
-    if !result_path.is_file() {
+    let Some(record) = store.test_record(student, test) else {
         return Err(TestResultError::TestsNotRun);
+    };
+    if record.status == RunStatus::Timeout {
+        out.state = TestState::Timeout;
+        return Ok(out);
     }
 
-    project.parse_result_report(&result_path, student, test).map(|results| {
+    let Some(report) = &record.report else {
+        return Err(TestResultError::IOError(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}'s report for {} is missing", student, test),
+        )));
+    };
+    project.parse_result_report(&report_file(report), student, test).map(|results| {
         out.state = TestState::Ok { results };
         out
     })
 }
 
+/// The student's results for the test, counted. Tests that have not been run are not an error
+pub fn result_summary(project: &Project, student: &str, test: &str) -> io::Result<ResultSummary> {
+    match parse_test_results(project, student, test) {
+        Ok(results) => Ok(results.to_summary()),
+        Err(TestResultError::TestsNotRun) => Ok(ResultSummary::not_run(student, test)),
+        Err(TestResultError::IOError(e)) => Err(e),
+    }
+}
+
+/// The student's results for the test, counted per test class. Tests that have not been run are not an error
+pub fn classname_summary(project: &Project, student: &str, test: &str) -> io::Result<ClassNameSummary> {
+    match parse_test_results(project, student, test) {
+        Ok(results) => Ok(results.to_classname_summary()),
+        Err(TestResultError::TestsNotRun) => Ok(ClassNameSummary::not_run(student, test)),
+        Err(TestResultError::IOError(e)) => Err(e),
+    }
+}
+
+/// Tests that have not been run are not an error
+pub fn verbose_result(project: &Project, student: &str, test: &str) -> io::Result<VerboseResult> {
+    match parse_test_results(project, student, test) {
+        Ok(results) => {
+            let compile_output = match results.state {
+                TestState::CompilationError => parse_compile_output(student)?,
+                _ => None,
+            };
+            Ok(VerboseResult::Parsed { results, compile_output })
+        }
+        Err(TestResultError::TestsNotRun) => Ok(VerboseResult::NotRun {
+            student: student.to_string(),
+            test: test.to_string(),
+            state: ResultState::NotRun,
+        }),
+        Err(TestResultError::IOError(e)) => Err(e),
+    }
+}
+
+/// The build output and diagnostics recorded when the student's project failed to compile.
+/// None if it compiled, or failed before diagnostics were recorded
+pub fn parse_compile_output(student: &str) -> io::Result<Option<CompileError>> {
+    let store = read_result_store()?;
+    let Some(output) = store.compile_errors.get(student).and_then(|record| record.output.as_ref()) else {
+        return Ok(None);
+    };
+    let file = File::open(report_file(output))?;
+    serde_json::from_reader(file)
+        .map(Some)
+        .map_err(|e| io::Error::other(format!("Failed to parse {}'s compile output: {}", student, e)))
+}
@@ -37,8 +37,8 @@ pub fn view_student_submission(project: &Project, student: &str, dest: &Path) ->
         ));
     }
 
-    project.recreate_normalized_project(dest, &student_diff_path)?;
-    project.recreate_original_project(dest, true)?;
+    project.recreate_normalized_project(student, dest)?;
+    project.recreate_original_project(student, dest, true)?;
 
     Ok(())
 }
@@ -0,0 +1,28 @@
+const code = document.querySelector("#code code");
+const compile_error_lines = document.querySelectorAll(".compile-error-line");
+
+// Moves each marker over the source line in its data-line attribute.
+// Positioned after load, once fonts are in and the line height is known
+
+function place_compile_error_lines() {
+    if (!code) {
+        return;
+    }
+    const style = getComputedStyle(code);
+    const line_height = parseFloat(style.lineHeight);
+    const top = code.offsetTop + parseFloat(style.paddingTop);
+    compile_error_lines.forEach((marker) => {
+        marker.style.top = `${top + (marker.dataset.line - 1) * line_height}px`;
+        marker.style.height = `${line_height}px`;
+    });
+
+    // The markers are the anchors linked to from the compile error panel
+    const target = window.location.hash && document.getElementById(window.location.hash.substring(1));
+    if (target) {
+        target.scrollIntoView({ block: "center" });
+    }
+}
+
+if (compile_error_lines.length > 0) {
+    window.addEventListener("load", place_compile_error_lines);
+}